@@ -4,20 +4,669 @@
 #[ink::contract]
 mod key_ledger {
 
-    use ink::storage::Mapping;
-    use ink::storage::traits::StorageLayout;
-    use ink_env::debug_println;
+    use ink::env::call::{build_call, Call, ExecutionInput, Selector};
+    use ink::env::hash::{Blake2x256, HashOutput, Keccak256};
+    use ink::prelude::{format, string::{String, ToString}, vec, vec::Vec};
+    use ink::primitives::{Key, KeyComposer};
+    use ink::storage::{Lazy, Mapping};
+    use ink::storage::traits::{Storable, StorageLayout};
 
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
     struct Node {
         nid: AccountId,
-        pub_k: String
+        pub_k: PublicKey,
+        key_type: KeyType,
+        // set by deregister_node, the node takes no new users from then on
+        exiting_since: Option<BlockNumber>,
+        // block of the last heartbeat, or of the registration
+        last_seen: BlockNumber,
+        info: NodeInfo,
+        // the key replaced by rotate_node_key and the first block it is no
+        // longer accepted at
+        previous_key: Option<(PublicKey, BlockNumber)>,
+        // first block the last attestation is no longer valid at
+        attested_until: BlockNumber,
+    }
+
+    /// How a node signs recovery proofs with its pub_k.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum KeyType {
+        /// Signs the proof digest as is
+        Secp256k1,
+        /// Key of an Ethereum account, signs the digest as an EIP-191 message
+        Ethereum,
+    }
+
+    /// Where and how clients reach a node, set by the node itself.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct NodeInfo {
+        /// API endpoint that accepts share storage requests
+        pub endpoint: String,
+        /// Region code, e.g. "eu-west"
+        pub region: String,
+        /// Maximum number of users the node is willing to serve, 0 for no limit
+        pub capacity: u32,
+    }
+
+    /// How many recoveries may be started per window of blocks, in total and
+    /// per paying account. A window of 0 turns the limiter off, a maximum of
+    /// 0 leaves that side unlimited.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct RateLimit {
+        pub window: BlockNumber,
+        pub max_starts: u32,
+        pub max_starts_per_account: u32,
+    }
+
+    /// How the recovery fee grows with the recovery attempts a secret started
+    /// recently, finished or not: every one within `window` blocks raises the
+    /// fee by `step_bps` of the previous price, up to `max_steps` times. The
+    /// default leaves the fee flat.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct FeeCurve {
+        /// 10_000 doubles the fee per recent attempt
+        pub step_bps: u32,
+        pub window: BlockNumber,
+        pub max_steps: u32,
+    }
+
+    /// Protocol wide counters for indexers and dashboards.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Stats {
+        /// Nodes in the node list, exiting ones included and blacklisted
+        /// ones left out
+        pub nodes: u32,
+        /// Registered user secrets
+        pub users: u32,
+        /// Recoveries started and not yet finished, cancelled or expired
+        pub active_recoveries: u32,
+        pub completed_recoveries: u64,
+    }
+
+    /// What a wallet checks about a guardian before sending it a share.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct NodeView {
+        pub pub_k: PublicKey,
+        pub key_type: KeyType,
+        /// Own stake, and own plus delegated stake
+        pub stake: Balance,
+        pub effective_stake: Balance,
+        pub info: NodeInfo,
+        pub last_seen: BlockNumber,
+        pub alive: bool,
+        pub exiting_since: Option<BlockNumber>,
+        pub blacklisted: bool,
+        /// User secrets the node guards
+        pub assigned: u32,
+        /// Key before the last rotation, accepted until the block
+        pub previous_key: Option<(PublicKey, BlockNumber)>,
+        /// First block the node's attestation has lapsed at
+        pub attested_until: BlockNumber,
+    }
+
+    /// Track record of a node as a guardian, for users picking reliable ones.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Reputation {
+        /// Recovery attempts the node served its share for
+        pub confirmed: u32,
+        /// Recovery attempts that expired without the node's confirmation
+        pub missed: u32,
+        pub slashes: u32,
+        /// Dropped shares the node confirmed deleting in time, and those it
+        /// did not
+        pub deletions_confirmed: u32,
+        pub deletions_missed: u32,
+    }
+
+    /// Tokens a holder delegated to a node, and its reward bookkeeping.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Delegation {
+        pub amount: Balance,
+        /// The node's reward_per_share when rewards were last settled
+        pub reward_per_share_paid: u128,
+        /// Settled rewards not claimed yet
+        pub pending_rewards: Balance,
+    }
+
+    /// Node rewards released linearly over `duration` blocks from `start`,
+    /// nothing of it can be claimed before `start + cliff`.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct VestingSchedule {
+        pub total: Balance,
+        pub claimed: Balance,
+        pub start: BlockNumber,
+        pub cliff: BlockNumber,
+        pub duration: BlockNumber,
+    }
+
+    impl VestingSchedule {
+        /// Tokens of the schedule vested at `now`, claimed or not.
+        pub fn vested(&self, now: BlockNumber) -> Result<Balance> {
+            let elapsed = now.saturating_sub(self.start);
+            if elapsed < self.cliff {
+                Ok(0)
+            } else if elapsed >= self.duration {
+                Ok(self.total)
+            } else {
+                let vested = self.total.checked_mul(elapsed as Balance).ok_or(Error::Overflow)?;
+                Ok(vested / self.duration as Balance)
+            }
+        }
+    }
+
+    /// Operational duties the owner can hand to other accounts. The owner
+    /// itself holds every role.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum Role {
+        /// May pause and unpause the contract
+        Pauser,
+        /// May slash misbehaving nodes
+        Slasher,
+        /// May change recovery pricing
+        FeeAdmin,
+        /// May manage which enclaves nodes can register with
+        NodeApprover,
+        /// May post results of condition checks done off chain, e.g. KYC
+        Oracle,
+    }
+
+    /// A privileged change that only takes effect `timelock_delay` blocks
+    /// after it was queued.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum AdminAction {
+        AllowMeasurement(Hash),
+        RevokeMeasurement(Hash),
+        SetAttestationSigner(Option<[u8; 33]>),
+        SetRecoveryFee(Balance),
+        SetTreasuryBps(u16),
+        SetMinBalance(Balance),
+        SetRegistrationFee(Balance),
+        SetStorageFee(Balance),
+        SetReferralReward(Balance),
+        SetStarterGrant(Balance),
+        SetFeeCurve(FeeCurve),
+        Upgrade(Hash),
+        SetTimelockDelay(BlockNumber),
+        /// Applies to proposals made after the change
+        SetVotingPeriod(BlockNumber),
+        /// New council members and how many of them must approve an action
+        SetCouncil(Vec<AccountId>, u32),
+        /// Only applies to nodes registering after the change
+        SetMinNodeStake(Balance),
+        /// Turns the operator allowlist for register_node on or off
+        SetOperatorAllowlist(bool),
+        ApproveOperator(AccountId),
+        RevokeOperator(AccountId),
+        /// Verifier contract for proofs of one condition type, None stops
+        /// checking them
+        SetConditionVerifier(ConditionType, Option<AccountId>),
+        /// PSP22 contract recovery fees are charged in, None charges them in
+        /// the built in ledger
+        SetFeeToken(Option<AccountId>),
+        SetLivenessWindow(BlockNumber),
+        SetUnbondingPeriod(BlockNumber),
+        SetKeyTransitionPeriod(BlockNumber),
+        SetAttestationValidity(BlockNumber),
+        /// Renumbers the epochs, nodes should claim before it changes
+        SetEpochLength(BlockNumber),
+        SetEpochEmission(Balance),
+        /// Cliff and duration of vesting schedules created after the change
+        SetVestingCliff(BlockNumber),
+        SetVestingDuration(BlockNumber),
+        SetDeletionGracePeriod(BlockNumber),
+        SetDeletionAckWindow(BlockNumber),
+        SetDeletionPenalty(Balance),
+        SetBillingPeriod(BlockNumber),
+        SetRentGracePeriod(BlockNumber),
+        SetChallengePeriod(BlockNumber),
+        SetMaxFailedAttempts(u32),
+        SetUnlockDelay(BlockNumber),
+        SetRecoveryCooldown(BlockNumber),
+        SetRateLimit(RateLimit),
+        SetRecoveryExpiry(BlockNumber),
+        /// New tokens for an account, and tokens destroyed from one
+        Mint(AccountId, Balance),
+        Burn(AccountId, Balance),
+        /// Pays out of the treasury, the native treasury and the treasury of
+        /// a fee token (token, to, amount)
+        WithdrawTreasury(AccountId, Balance),
+        WithdrawNativeTreasury(AccountId, Balance),
+        WithdrawFeeTokenTreasury(AccountId, AccountId, Balance),
+        GrantRole(Role, AccountId),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct QueuedAction {
+        pub action: AdminAction,
+        /// First block the action can be executed at
+        pub eta: BlockNumber,
+    }
+
+    /// A parameter change token holders vote on.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Proposal {
+        pub proposer: AccountId,
+        pub action: AdminAction,
+        /// Last block votes are accepted at
+        pub voting_ends: BlockNumber,
+        pub votes_for: Balance,
+        pub votes_against: Balance,
+        pub executed: bool,
+    }
+
+    /// Protocol parameters, read in one call with `get_config`. All of them
+    /// are changed through admin actions.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Config {
+        /// Paid by the user for every finished recovery
+        pub recovery_fee: Balance,
+        /// Share of the recovery fee kept by the treasury in basis points,
+        /// the rest is split between the guardians
+        pub treasury_bps: u16,
+        /// Balance a user needs to start a recovery
+        pub min_balance: Balance,
+        /// Native tokens a user pays with every registration proposal
+        pub registration_fee: Balance,
+        /// Rent per secret and billing period, split between its guardians
+        pub storage_fee: Balance,
+        /// Paid from the treasury to whoever referred a new user
+        pub referral_reward: Balance,
+        /// Tokens claim_starter_grant hands to a new account
+        pub starter_grant: Balance,
+        /// Raises the recovery fee for secrets recovered again and again
+        pub fee_curve: FeeCurve,
+        /// Ledger tokens a node locks when it registers
+        pub min_node_stake: Balance,
+        /// Blocks between queueing an admin action and executing it, gives
+        /// users time to leave before a change they disagree with
+        pub timelock_delay: BlockNumber,
+        /// Blocks a token holder proposal is open for voting
+        pub voting_period: BlockNumber,
+        /// Blocks per reward epoch, and tokens paid to nodes per epoch out
+        /// of the reward pool
+        pub epoch_length: BlockNumber,
+        pub epoch_emission: Balance,
+        /// Cliff and duration of new vesting schedules of node rewards
+        pub vesting_cliff: BlockNumber,
+        pub vesting_duration: BlockNumber,
+        /// Blocks an exiting node waits before its stake is released
+        pub unbonding_period: BlockNumber,
+        /// Blocks a node's old key stays valid after rotate_node_key
+        pub key_transition_period: BlockNumber,
+        /// Blocks a node's attestation is valid for before it must renew it
+        pub attestation_validity: BlockNumber,
+        /// A node without heartbeat for longer than this is considered offline
+        pub liveness_window: BlockNumber,
+        /// Blocks after which an unfinished recovery can be expired
+        pub recovery_expiry: BlockNumber,
+        /// Blocks between the guardian quorum and finalize_recovery, for the
+        /// user to notice and cancel a recovery it did not ask for
+        pub challenge_period: BlockNumber,
+        /// Blocks a user waits after a finished recovery before starting another
+        pub recovery_cooldown: BlockNumber,
+        /// Limit on recovery starts, overall and per paying account
+        pub rate_limit: RateLimit,
+        /// Failed attempts after which recovery gets locked, and the blocks
+        /// between asking for an unlock and the unlock
+        pub max_failed_attempts: u32,
+        pub unlock_delay: BlockNumber,
+        /// Blocks during which a user deletion can be reverted, 0 deletes at once
+        pub deletion_grace_period: BlockNumber,
+        /// Blocks a node has to confirm it deleted a dropped share, and the
+        /// stake it loses when it does not
+        pub deletion_ack_window: BlockNumber,
+        pub deletion_penalty: Balance,
+        /// Blocks one rent payment covers, and how long an overdue secret is
+        /// kept before guardians may purge it
+        pub billing_period: BlockNumber,
+        pub rent_grace_period: BlockNumber,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                recovery_fee: 3,
+                treasury_bps: 0,
+                min_balance: 3,
+                registration_fee: 0,
+                storage_fee: 0,
+                referral_reward: 0,
+                starter_grant: 0,
+                fee_curve: FeeCurve::default(),
+                min_node_stake: 0,
+                timelock_delay: DEFAULT_TIMELOCK_DELAY,
+                voting_period: DEFAULT_VOTING_PERIOD,
+                epoch_length: DEFAULT_EPOCH_LENGTH,
+                epoch_emission: 0,
+                vesting_cliff: DEFAULT_VESTING_CLIFF,
+                vesting_duration: DEFAULT_VESTING_DURATION,
+                unbonding_period: DEFAULT_UNBONDING_PERIOD,
+                key_transition_period: DEFAULT_KEY_TRANSITION_PERIOD,
+                attestation_validity: DEFAULT_ATTESTATION_VALIDITY,
+                liveness_window: DEFAULT_LIVENESS_WINDOW,
+                recovery_expiry: DEFAULT_RECOVERY_EXPIRY,
+                challenge_period: DEFAULT_CHALLENGE_PERIOD,
+                recovery_cooldown: DEFAULT_RECOVERY_COOLDOWN,
+                rate_limit: RateLimit::default(),
+                max_failed_attempts: DEFAULT_MAX_FAILED_ATTEMPTS,
+                unlock_delay: DEFAULT_UNLOCK_DELAY,
+                deletion_grace_period: DEFAULT_DELETION_GRACE_PERIOD,
+                deletion_ack_window: DEFAULT_DELETION_ACK_WINDOW,
+                deletion_penalty: 0,
+                billing_period: DEFAULT_BILLING_PERIOD,
+                rent_grace_period: DEFAULT_RENT_GRACE_PERIOD,
+            }
+        }
+    }
+
+    /// Proof that a node's key was generated inside an approved enclave.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct AttestationReport {
+        /// Measurement (MRENCLAVE) of the enclave running the node
+        pub measurement: Hash,
+        /// Attestation service signature over `(measurement, pub_k)`
+        pub signature: [u8; 65],
+    }
+
+    /// A node holding one of the user's secret shares, together with the
+    /// condition the node checks before serving it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Guardian {
+        pub node_id: AccountId,
+        pub cond_type: ConditionType,
+        /// Salted hash of the condition data, see `condition_commitment`
+        pub cond_commitment: Hash,
+        /// Hash of the encrypted share the node was handed, see
+        /// `share_commitment`
+        pub share_commitment: Hash,
+        /// How much the node's confirmation counts towards the threshold
+        pub weight: u32,
+    }
+
+    /// What a guardian node checks before it serves its share. Encoded as the
+    /// same byte the untyped cond_type used.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum ConditionType {
+        Email = 1,
+        Phone = 2,
+        OAuth = 3,
+        Passphrase = 4,
+        TrustedContact = 5,
+    }
+
+    impl TryFrom<u8> for ConditionType {
+        type Error = Error;
+
+        fn try_from(value: u8) -> Result<Self> {
+            match value {
+                1 => Ok(ConditionType::Email),
+                2 => Ok(ConditionType::Phone),
+                3 => Ok(ConditionType::OAuth),
+                4 => Ok(ConditionType::Passphrase),
+                5 => Ok(ConditionType::TrustedContact),
+                _ => Err(Error::UnknownCondition),
+            }
+        }
     }
 
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
     struct User {
+        uid: AccountId,
+        pub_k: PublicKey,
+        guardians: Vec<Guardian>,
+        // number of guardian confirmations needed to finish a recovery
+        threshold: u32,
+        // set by delete_user while the deletion can still be reverted
+        deletion_requested_at: Option<BlockNumber>,
+        // bumped every time the shares are re-distributed to the guardians
+        share_version: u32,
+        // one entry per guardian, set once the node stored its share. the
+        // user can only be recovered once all are set
+        acknowledged: Vec<bool>,
+    }
+
+    /// A user's recovery setup for one secret, as clients render it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct UserView {
+        pub pub_k: PublicKey,
+        /// Guardian nodes in registration order, with the condition each checks
+        /// and the weight of its confirmation
+        pub guardians: Vec<AccountId>,
+        pub cond_types: Vec<ConditionType>,
+        pub weights: Vec<u32>,
+        pub threshold: u32,
+        pub share_version: u32,
+        pub deletion_requested_at: Option<BlockNumber>,
+        /// Whether every guardian acknowledged storing its share
+        pub active: bool,
+    }
+
+    /// Where a secret's latest recovery attempt stands.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum RecoveryStatus {
+        /// No attempt was started yet
+        Idle,
+        /// Guardians are confirming the attempt
+        Started,
+        /// The guardians confirmed, waiting for finalize_recovery
+        PendingFinalize,
+        /// The attempt finished and the guardians got paid
+        Finished,
+        /// The attempt ran out of time without enough confirmations
+        Expired,
+        /// The user, its veto key or a guardian freeze aborted the attempt
+        Cancelled,
+    }
+
+    /// Progress of a user's current recovery attempt, for apps to poll.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct RecoveryView {
+        pub status: RecoveryStatus,
+        pub recovery_id: RecoveryId,
+        /// Guardians that confirmed the current attempt, and their weight
+        pub confirmed_by: Vec<AccountId>,
+        pub confirmed_weight: u64,
+        pub threshold: u32,
+        pub started_at: BlockNumber,
+        /// First block finalize_recovery goes through
+        pub finalizable_at: BlockNumber,
+        /// Finished recoveries, and failed attempts since the last one
+        pub completed: u32,
+        pub failed_attempts: u32,
+        pub locked: bool,
+        pub new_owner: Option<AccountId>,
+        pub request: Option<RecoveryRequest>,
+    }
+
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    struct Confirmation {
+        confirmed: bool,
+        // hash of the accepted proof, the proof itself is only in the
+        // RecoveryConfirmed event
+        proof_hash: Option<Hash>,
+    }
+
+    /// A guardian's signed statement that it served its share, of version
+    /// `share_version`, for one recovery attempt.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct RecoveryProof {
+        pub share_version: u32,
+        /// ECDSA signature over `(user, secret_id, recovery_id, share_version)`
+        /// by the node's registered key, see `KeyType`
+        pub signature: [u8; 65],
+        /// Proof that the requester met the guardian's condition, checked by
+        /// the verifier set for its condition type, empty without one
+        pub condition_proof: Vec<u8>,
+    }
+
+    /// A node's share of a finished recovery's fee, see `get_payouts`.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Payout {
+        pub block: BlockNumber,
+        pub user: AccountId,
+        pub secret_id: SecretId,
+        pub recovery_id: RecoveryId,
+        pub amount: Balance,
+        /// PSP22 contract the amount was paid in, None for the built in ledger
+        pub fee_token: Option<AccountId>,
+    }
+
+    /// What is kept of a finished recovery once the next attempt starts.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct RecoveryRecord {
+        pub recovery_id: RecoveryId,
+        pub started_at: BlockNumber,
+        pub finished_at: BlockNumber,
+        pub new_owner: Option<AccountId>,
+        /// Guardians whose confirmation counted and who got paid
+        pub confirmed_by: Vec<AccountId>,
+        pub fee: Balance,
+    }
+
+    /// A new guardian set waiting for its nodes to confirm they got their
+    /// shares, the old set stays in charge until then.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Reshare {
+        pub guardians: Vec<Guardian>,
+        pub threshold: u32,
+        /// One entry per new guardian, in the same order
+        pub acknowledged: Vec<bool>,
+        pub started_at: BlockNumber,
+    }
+
+    /// Ordinary accounts, e.g. friends' wallets, that hold no share but must
+    /// approve a recovery before node confirmations are accepted.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct SocialGuardians {
+        pub accounts: Vec<AccountId>,
+        /// How many of the accounts must approve
+        pub threshold: u32,
+    }
+
+    /// Evidence of node misbehaviour waiting for a slasher to check it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct FraudReport {
+        pub reporter: AccountId,
+        pub node: AccountId,
+        /// E.g. a served share that fails verification, encoded off chain
+        pub evidence: Vec<u8>,
+        pub filed_at: BlockNumber,
+    }
+
+    /// Storage rent account of one secret, see `charge_period`.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct Subscription {
+        /// Rent paid in advance and not charged yet, held by the contract
+        pub prepaid: Balance,
+        /// Start of the first billing period not charged yet
+        pub paid_until: BlockNumber,
+        /// Set when a period could not be paid, guardians may purge the
+        /// shares once the rent grace period passed from then
+        pub overdue_since: Option<BlockNumber>,
+    }
+
+    /// Who may recover a secret once its owner stopped checking in.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct DeadManSwitch {
+        pub beneficiary: AccountId,
+        /// Blocks without a check_in after which the beneficiary can recover
+        pub inactivity_period: BlockNumber,
+    }
+
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    struct Recovery {
+        status: RecoveryStatus,
+        uid: AccountId,
+        r_times: u32,
+        // fresh for every start_recovery, proofs must sign the current one.
+        // it is the attempt's nonce, a confirmation signed for an earlier
+        // attempt neither verifies nor is accepted for this one
+        recovery_id: RecoveryId,
+        // block the current attempt was started at
+        started_at: BlockNumber,
+        // first block the attempt can be finalized at, the user can still veto
+        // it until then
+        not_before: BlockNumber,
+        // block the last successful recovery finished at
+        last_finished_at: Option<BlockNumber>,
+        // expired or cancelled attempts since the last successful recovery,
+        // reaching max_failed_attempts locks recovery
+        failed_attempts: u32,
+        // attempts the user or its veto key aborted as not asked for
+        suspicious_attempts: u32,
+        locked: bool,
+        unlock_requested_at: Option<BlockNumber>,
+        // fee locked by start_recovery, paid out on finish or refunded
+        escrowed: Balance,
+        // account the user record moves to once the recovery finishes, it
+        // also paid the escrowed fee
+        new_owner: Option<AccountId>,
+        // external token the escrowed fee was paid in, None for the ledger
+        fee_token: Option<AccountId>,
+        // one entry per guardian, in the same order as User::guardians
+        confirmations: Vec<Confirmation>,
+    }
+
+    /// Why a recovery was asked for and by which device, so guardians have
+    /// context for the checks they run off chain.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub struct RecoveryRequest {
+        pub reason: RecoveryReason,
+        /// Hash of an identifier of the requesting device, so guardians can
+        /// tell repeated requests from the same device apart
+        pub device_commitment: Hash,
+        /// Key the recovered secret is re-encrypted for, None for the
+        /// user's registered key
+        pub target_pub_k: Option<PublicKey>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    pub enum RecoveryReason {
+        LostDevice,
+        NewDevice,
+        Compromised,
+        Other,
+    }
+
+    /// User record as written by storage version 1, with exactly three
+    /// guardians of which two had to confirm.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    struct LegacyUser {
         uid: AccountId,
         pub_k: String,
         node1_cond_type: u8,
@@ -28,10 +677,19 @@ mod key_ledger {
         node3_id: AccountId,
     }
 
+    /// Node record as written by storage version 1.
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
-    struct Recovery {
-        status: u8, // 0 for not started, 1 for started, 2 for finished
+    struct LegacyNode {
+        nid: AccountId,
+        pub_k: String,
+    }
+
+    /// Recovery record as written by storage version 1.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
+    struct LegacyRecovery {
+        status: u8,
         uid: AccountId,
         r_times: u32,
         recovery1_proof: String,
@@ -42,360 +700,9265 @@ mod key_ledger {
         node3_confirm: u32,
     }
   
+    /// Besides total_supply every field is a lazy cell or a mapping, a
+    /// message only loads the fields it uses.
     #[ink(storage)]
     pub struct KeyLedger {
         /// Stores balance for both user and node
         total_supply: Balance,
+        /// PSP22 metadata shown by wallets
+        name: Lazy<String>,
+        symbol: Lazy<String>,
+        decimals: Lazy<u8>,
+        /// Account allowed to manage the token supply
+        owner: Lazy<AccountId>,
+        /// Account the owner offered ownership to, it becomes owner once it
+        /// accepts
+        pending_owner: Lazy<Option<AccountId>>,
+        /// While set, registrations, recoveries and transfers are halted.
+        /// Storage of version 1 has no flag and stays paused until
+        /// complete_migration
+        paused: Lazy<bool>,
+        /// Accounts granted a role besides the owner
+        roles: Mapping<(Role, AccountId), ()>,
+        /// Bumped by every code upgrade, starts at 1
+        contract_version: Lazy<u32>,
+        /// Layout of the stored records, STORAGE_VERSION once every record
+        /// from an older layout got migrated. Version 1 did not store it
+        storage_version: Lazy<u32>,
+        queued_actions: Mapping<ActionId, QueuedAction>,
+        last_action_id: Lazy<ActionId>,
+        /// Admins that must approve queued actions, council_quorum of them
+        /// before an action can be executed. The members are only loaded by
+        /// the admin messages
+        council: Lazy<Vec<AccountId>>,
+        council_quorum: Lazy<u32>,
+        action_approvals: Mapping<(ActionId, AccountId), ()>,
+        /// Token holder proposals, open for voting_period blocks
+        proposals: Mapping<ProposalId, Proposal>,
+        last_proposal_id: Lazy<ProposalId>,
+        votes: Mapping<(ProposalId, AccountId), ()>,
+        /// Tokens a voter can not transfer until the block, so the same
+        /// tokens can not vote twice from different accounts
+        vote_locks: Mapping<AccountId, (Balance, BlockNumber)>,
         balances: Mapping<AccountId, Balance>,
+        /// Amount a spender may still move on behalf of an owner
+        allowances: Mapping<(AccountId, AccountId), Balance>,
         nodes: Mapping<AccountId ,Node>,
-        users: Mapping<AccountId, User>,
-        recoveries: Mapping<AccountId, Recovery>
+        users: Mapping<(AccountId, SecretId), User>,
+        recoveries: Mapping<(AccountId, SecretId), Recovery>,
+        /// Context the user sent with start_recovery for the current attempt,
+        /// kept apart from the recovery so confirmations do not load it
+        recovery_requests: Mapping<(AccountId, SecretId), RecoveryRequest>,
+        /// Finished recoveries of a user's secret, by the r_times they finished
+        /// as, starting from 0. Never overwritten
+        recovery_history: Mapping<(AccountId, SecretId, u32), RecoveryRecord>,
+        /// Blocks the owner started its latest attempts on a secret at, oldest
+        /// first, as many as the fee curve looks at
+        recent_starts: Mapping<(AccountId, SecretId), Vec<BlockNumber>>,
+        /// Recovery fee shares paid to each node, by node and index. Never
+        /// overwritten
+        payout_counts: Mapping<AccountId, u32>,
+        payouts: Mapping<(AccountId, u32), Payout>,
+        /// The user's secret each recovery attempt was started for
+        recovery_ids: Mapping<RecoveryId, (AccountId, SecretId)>,
+        /// Handed out to the latest started recovery, ids start at 1
+        last_recovery_id: Lazy<RecoveryId>,
+        /// Guardian changes waiting for the new nodes to acknowledge
+        reshares: Mapping<(AccountId, SecretId), Reshare>,
+        dead_man_switches: Mapping<(AccountId, SecretId), DeadManSwitch>,
+        social_guardians: Mapping<(AccountId, SecretId), SocialGuardians>,
+        /// Last block an oracle saw the user pass a condition check, and the
+        /// secrets whose guardians only confirm after such a check
+        verifications: Mapping<(AccountId, SecretId, ConditionType), BlockNumber>,
+        verification_required: Mapping<(AccountId, SecretId), ()>,
+        /// Second account, e.g. a hardware wallet, that may veto recoveries
+        veto_keys: Mapping<(AccountId, SecretId), AccountId>,
+        /// Guardians that asked to freeze a user's secret, cleared once the
+        /// freeze went through
+        freeze_votes: Mapping<(AccountId, SecretId), Vec<AccountId>>,
+        /// Social guardians that approved a recovery attempt
+        social_approvals: Mapping<RecoveryId, Vec<AccountId>>,
+        /// Heirs of a secret and the veto delay of their claims
+        beneficiaries: Mapping<(AccountId, SecretId), Vec<(AccountId, BlockNumber)>>,
+        /// Last block a user showed it is still around
+        last_check_ins: Mapping<AccountId, BlockNumber>,
+        /// Next nonce a user signs for a relayed call, stops replays
+        nonces: Mapping<AccountId, u64>,
+        /// Times a user had guardians picked by register_user_auto, seeds the draw
+        assignment_nonces: Mapping<AccountId, u32>,
+        /// Enclave measurements nodes may run, and the attestation service key
+        /// that signs their reports. Attestation is not checked without a key
+        allowed_measurements: Mapping<Hash, ()>,
+        attestation_signer: Lazy<Option<[u8; 33]>>,
+        /// Contracts implementing `ConditionVerifier` that check the
+        /// condition proofs of recovery confirmations, per condition type
+        condition_verifiers: Mapping<ConditionType, AccountId>,
+        /// When on, only approved operators can register nodes
+        operator_allowlist: Lazy<bool>,
+        approved_operators: Mapping<AccountId, ()>,
+        /// Index of registered nodes, `Mapping` itself can not be iterated
+        node_count: Lazy<u32>,
+        node_ids: Mapping<u32, AccountId>,
+        node_positions: Mapping<AccountId, u32>,
+        /// Nodes that may not guard new users nor confirm recoveries
+        blacklisted_nodes: Mapping<AccountId, ()>,
+        /// Nodes migrate moved to the current layout. Until the migration
+        /// completes every other node record is in the version 1 layout
+        migrated_nodes: Mapping<AccountId, ()>,
+        /// Hot key a node lets call finish_recovery and heartbeat for it,
+        /// and the node each key acts for
+        session_keys: Mapping<AccountId, AccountId>,
+        session_nodes: Mapping<AccountId, AccountId>,
+        /// Index of the user secrets each node guards
+        assigned_counts: Mapping<AccountId, u32>,
+        assigned_users: Mapping<(AccountId, u32), (AccountId, SecretId)>,
+        assigned_positions: Mapping<(AccountId, AccountId, SecretId), u32>,
+        /// Shares a node dropped and has to confirm deleting, by the block
+        deletion_deadlines: Mapping<(AccountId, AccountId, SecretId), BlockNumber>,
+        stakes: Mapping<AccountId, Balance>,
+        /// Guardian track record, kept after a node leaves
+        reputations: Mapping<AccountId, Reputation>,
+        /// Fraud reports waiting for a slasher
+        reports: Mapping<ReportId, FraudReport>,
+        last_report_id: Lazy<ReportId>,
+        reward_pool: Lazy<Balance>,
+        /// Activity points of a node in an epoch, removed once claimed
+        epoch_points: Mapping<(u32, AccountId), u32>,
+        epoch_total_points: Mapping<u32, u32>,
+        /// Emission taken from the pool for an epoch, at its first claim
+        epoch_rewards: Mapping<u32, Balance>,
+        /// Stake token holders delegated to a node, adds to its effective
+        /// stake and earns a share of its rewards
+        delegations: Mapping<(AccountId, AccountId), Delegation>,
+        delegated_stakes: Mapping<AccountId, Balance>,
+        /// Share of the delegators' rewards a node keeps
+        commissions: Mapping<AccountId, u16>,
+        /// Delegator rewards per delegated token so far, scaled by
+        /// REWARD_PER_SHARE_SCALE
+        reward_per_share: Mapping<AccountId, u128>,
+        /// Vesting schedules of node rewards by (node, index), the (first
+        /// unfinished, next) index of each node
+        vesting_schedules: Mapping<(AccountId, u32), VestingSchedule>,
+        vesting_indices: Mapping<AccountId, (u32, u32)>,
+        /// The (window start, starts) counted against the recovery rate
+        /// limit, overall and per paying account
+        recovery_starts: Lazy<(BlockNumber, u32)>,
+        account_recovery_starts: Mapping<AccountId, (BlockNumber, u32)>,
+        /// Storage rent accounts
+        subscriptions: Mapping<(AccountId, SecretId), Subscription>,
+        /// Counters behind stats(), kept up to date by save_user and
+        /// save_recovery. user_count is also the length of the user index
+        user_count: Lazy<u32>,
+        user_ids: Mapping<u32, (AccountId, SecretId)>,
+        user_positions: Mapping<(AccountId, SecretId), u32>,
+        active_recoveries: Lazy<u32>,
+        completed_recoveries: Lazy<u64>,
+        /// Tokens confiscated from nodes or paid as fees, owned by the protocol
+        treasury: Lazy<Balance>,
+        /// Who referred an account, and whether the referral was rewarded
+        referrals: Mapping<AccountId, (AccountId, bool)>,
+        /// Tokens set aside for starter grants, the accounts that claimed
+        /// theirs, and the (block, claims) of the latest block with claims
+        faucet: Lazy<Balance>,
+        starter_grants: Mapping<AccountId, ()>,
+        faucet_claims: Lazy<(BlockNumber, u32)>,
+        /// Native tokens paid as registration fees and held by the contract
+        native_treasury: Lazy<Balance>,
+        /// Native tokens wrapped into balances by deposit, backs withdraw
+        native_deposits: Lazy<Balance>,
+        /// PSP22 contract recovery fees are charged in, the built in ledger
+        /// when None, and the treasury cut of fees paid in each such token
+        fee_token: Lazy<Option<AccountId>>,
+        fee_token_treasuries: Mapping<AccountId, Balance>,
+        config: Lazy<Config>,
+    }
+
+    /// Emitted whenever tokens move between accounts, `from` is `None` when
+    /// tokens are created and `to` when they are burned. Stakes, escrowed
+    /// fees and the treasury are held by the contract account.
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+    }
+
+    /// Emitted when an owner sets the allowance of a spender.
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: Balance,
+    }
+
+    /// Emitted when a node joins the registry.
+    #[ink(event)]
+    pub struct NodeRegistered {
+        #[ink(topic)]
+        node: AccountId,
+        stake: Balance,
+    }
+
+    /// Emitted when a node changes where clients can reach it.
+    #[ink(event)]
+    pub struct NodeInfoUpdated {
+        #[ink(topic)]
+        node: AccountId,
+        info: NodeInfo,
+    }
+
+    /// Emitted when the owner offers ownership to another account.
+    #[ink(event)]
+    pub struct OwnershipTransferStarted {
+        #[ink(topic)]
+        previous_owner: AccountId,
+        #[ink(topic)]
+        new_owner: AccountId,
+    }
+
+    /// Emitted when the offered account accepted ownership.
+    #[ink(event)]
+    pub struct OwnershipTransferred {
+        #[ink(topic)]
+        previous_owner: AccountId,
+        #[ink(topic)]
+        new_owner: AccountId,
+    }
+
+    /// Emitted when the owner grants a role to an account.
+    #[ink(event)]
+    pub struct RoleGranted {
+        #[ink(topic)]
+        role: Role,
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    /// Emitted when the owner takes a role from an account.
+    #[ink(event)]
+    pub struct RoleRevoked {
+        #[ink(topic)]
+        role: Role,
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    /// Emitted when the owner replaced the contract code.
+    #[ink(event)]
+    pub struct Upgraded {
+        #[ink(topic)]
+        code_hash: Hash,
+        contract_version: u32,
+    }
+
+    /// Emitted for every batch of old layout records migrated.
+    #[ink(event)]
+    pub struct RecordsMigrated {
+        count: u32,
+    }
+
+    /// Emitted once all stored records use the current layout.
+    #[ink(event)]
+    pub struct StorageMigrated {
+        storage_version: u32,
+    }
+
+    /// Emitted when an admin action got queued, it can be executed from
+    /// `eta` on.
+    #[ink(event)]
+    pub struct ActionQueued {
+        #[ink(topic)]
+        action_id: ActionId,
+        action: AdminAction,
+        eta: BlockNumber,
+    }
+
+    /// Emitted when a council member approved a queued admin action.
+    #[ink(event)]
+    pub struct ActionApproved {
+        #[ink(topic)]
+        action_id: ActionId,
+        #[ink(topic)]
+        member: AccountId,
+        approvals: u32,
+    }
+
+    /// Emitted when a queued admin action was dropped.
+    #[ink(event)]
+    pub struct ActionCancelled {
+        #[ink(topic)]
+        action_id: ActionId,
+    }
+
+    /// Emitted when a queued admin action took effect.
+    #[ink(event)]
+    pub struct ActionExecuted {
+        #[ink(topic)]
+        action_id: ActionId,
+    }
+
+    /// Emitted when a token holder proposes a parameter change.
+    #[ink(event)]
+    pub struct ProposalCreated {
+        #[ink(topic)]
+        proposal_id: ProposalId,
+        #[ink(topic)]
+        proposer: AccountId,
+        action: AdminAction,
+        voting_ends: BlockNumber,
+    }
+
+    /// Emitted for every vote, weighted by the voter's balance.
+    #[ink(event)]
+    pub struct VoteCast {
+        #[ink(topic)]
+        proposal_id: ProposalId,
+        #[ink(topic)]
+        voter: AccountId,
+        support: bool,
+        weight: Balance,
+    }
+
+    /// Emitted when a passed proposal took effect.
+    #[ink(event)]
+    pub struct ProposalExecuted {
+        #[ink(topic)]
+        proposal_id: ProposalId,
+    }
+
+    /// Emitted when the owner halts the contract.
+    #[ink(event)]
+    pub struct Paused {
+        #[ink(topic)]
+        by: AccountId,
+    }
+
+    /// Emitted when the owner resumes the contract.
+    #[ink(event)]
+    pub struct Unpaused {
+        #[ink(topic)]
+        by: AccountId,
+    }
+
+    /// Emitted when tokens are added to the node reward pool.
+    #[ink(event)]
+    pub struct RewardsFunded {
+        #[ink(topic)]
+        from: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a node claimed its rewards for an epoch.
+    #[ink(event)]
+    pub struct RewardsClaimed {
+        #[ink(topic)]
+        node: AccountId,
+        epoch: u32,
+        amount: Balance,
+    }
+
+    /// Emitted when a node claimed vested rewards.
+    #[ink(event)]
+    pub struct VestedClaimed {
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a token holder delegates stake to a node.
+    #[ink(event)]
+    pub struct Delegated {
+        #[ink(topic)]
+        delegator: AccountId,
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a token holder takes delegated stake back.
+    #[ink(event)]
+    pub struct Undelegated {
+        #[ink(topic)]
+        delegator: AccountId,
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a node changes the commission it takes from delegators.
+    #[ink(event)]
+    pub struct CommissionSet {
+        #[ink(topic)]
+        node: AccountId,
+        bps: u16,
+    }
+
+    /// Emitted when a delegator claimed its share of a node's rewards.
+    #[ink(event)]
+    pub struct DelegationRewardsClaimed {
+        #[ink(topic)]
+        delegator: AccountId,
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when the owner pays out treasury tokens.
+    #[ink(event)]
+    pub struct TreasuryWithdrawn {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when the owner pays out native registration fees.
+    #[ink(event)]
+    pub struct NativeTreasuryWithdrawn {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when the owner pays out the treasury cut of fees paid in an
+    /// external fee token.
+    #[ink(event)]
+    pub struct FeeTokenTreasuryWithdrawn {
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a node starts leaving, users should move their shares
+    /// away before `release_block`.
+    #[ink(event)]
+    pub struct NodeExiting {
+        #[ink(topic)]
+        node: AccountId,
+        release_block: BlockNumber,
+    }
+
+    /// Emitted when a node switched to a new key, the old one is accepted
+    /// until `old_key_valid_until`. The users of the secrets it guards,
+    /// listed by `assigned_users`, should re-encrypt their shares for
+    /// `new_pub_k` and send them again.
+    #[ink(event)]
+    pub struct NodeKeyRotated {
+        #[ink(topic)]
+        node: AccountId,
+        old_pub_k: PublicKey,
+        new_pub_k: PublicKey,
+        old_key_valid_until: BlockNumber,
+        /// Number of user secrets assigned to the node
+        assigned_users: u32,
+    }
+
+    /// Emitted when a node proved again that it runs in an approved enclave.
+    #[ink(event)]
+    pub struct AttestationRenewed {
+        #[ink(topic)]
+        node: AccountId,
+        valid_until: BlockNumber,
+    }
+
+    /// Emitted when an exited node got its stake back and left the registry.
+    #[ink(event)]
+    pub struct NodeDeregistered {
+        #[ink(topic)]
+        node: AccountId,
+        stake: Balance,
+    }
+
+    /// Emitted when part of a node's stake is moved to the treasury.
+    #[ink(event)]
+    pub struct NodeSlashed {
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+        evidence: Vec<u8>,
+    }
+
+    /// Emitted when a node is blacklisted, the users it guards should
+    /// replace it.
+    #[ink(event)]
+    pub struct NodeBlacklisted {
+        #[ink(topic)]
+        node: AccountId,
+        /// Number of user secrets still assigned to the node
+        assigned_users: u32,
+    }
+
+    /// Emitted when a node sets or clears its session key.
+    #[ink(event)]
+    pub struct SessionKeySet {
+        #[ink(topic)]
+        node: AccountId,
+        key: Option<AccountId>,
+    }
+
+    /// Emitted when someone files evidence against a node.
+    #[ink(event)]
+    pub struct NodeReported {
+        #[ink(topic)]
+        report_id: ReportId,
+        #[ink(topic)]
+        reporter: AccountId,
+        #[ink(topic)]
+        node: AccountId,
+    }
+
+    /// Emitted when a slasher upheld or dismissed a fraud report.
+    #[ink(event)]
+    pub struct ReportResolved {
+        #[ink(topic)]
+        report_id: ReportId,
+        slashed: Balance,
+        /// Part of the slashed stake paid to the reporter
+        reward: Balance,
+    }
+
+    /// Emitted when a user proposes a guardian configuration, the guardians
+    /// still have to acknowledge their shares.
+    #[ink(event)]
+    pub struct RegistrationProposed {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        guardians: Vec<AccountId>,
+        threshold: u32,
+    }
+
+    /// Emitted when the last guardian acknowledged its share and the user
+    /// became active.
+    #[ink(event)]
+    pub struct UserRegistered {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        guardians: Vec<AccountId>,
+        threshold: u32,
+    }
+
+    /// Emitted when a user changes its key, guardians should re-encrypt the
+    /// shares they store for it.
+    #[ink(event)]
+    pub struct UserKeyRotated {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        old_pub_k: PublicKey,
+        new_pub_k: PublicKey,
+        share_version: u32,
+    }
+
+    /// Emitted when a user changes how many guardians must confirm.
+    #[ink(event)]
+    pub struct ThresholdChanged {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        threshold: u32,
+    }
+
+    /// Emitted when a user swaps one of its guardians, the new node should
+    /// expect to receive a share.
+    #[ink(event)]
+    pub struct GuardianReplaced {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        old_node: AccountId,
+        #[ink(topic)]
+        new_node: AccountId,
+        share_version: u32,
+    }
+
+    /// Emitted when a user starts moving its secret to a new guardian set,
+    /// each new node should expect a share and acknowledge it.
+    #[ink(event)]
+    pub struct ReshareStarted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        guardians: Vec<AccountId>,
+        threshold: u32,
+    }
+
+    /// Emitted when a new guardian confirmed it received its share.
+    #[ink(event)]
+    pub struct ShareAcknowledged {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        node: AccountId,
+    }
+
+    /// Emitted when every new guardian acknowledged and the old set retired,
+    /// old guardians can drop their shares.
+    #[ink(event)]
+    pub struct ReshareCompleted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        share_version: u32,
+    }
+
+    /// Emitted when a user abandons a pending reshare.
+    #[ink(event)]
+    pub struct ReshareCancelled {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+    }
+
+    /// Emitted when a user arms or disarms the dead-man switch of a secret.
+    #[ink(event)]
+    pub struct DeadManSwitchSet {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        switch: Option<DeadManSwitch>,
+    }
+
+    /// Emitted when a user adds, changes or removes an heir of a secret.
+    #[ink(event)]
+    pub struct BeneficiarySet {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        delay_blocks: Option<BlockNumber>,
+    }
+
+    /// Emitted when an heir claims a secret, the user can cancel_recovery
+    /// until `veto_until`.
+    #[ink(event)]
+    pub struct InheritanceStarted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        recovery_id: RecoveryId,
+        veto_until: BlockNumber,
+    }
+
+    /// Emitted when a user asks to leave, the deletion can be reverted until
+    /// `delete_block`.
+    #[ink(event)]
+    pub struct UserDeletionRequested {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        delete_block: BlockNumber,
+    }
+
+    /// Emitted when a user's records are removed, nodes can purge its shares.
+    #[ink(event)]
+    pub struct UserDeleted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+    }
+
+    /// Emitted when a node has to delete a share it no longer guards, and
+    /// confirm it with confirm_share_deleted before `deadline`.
+    #[ink(event)]
+    pub struct ShareDeletionRequested {
+        #[ink(topic)]
+        node: AccountId,
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        deadline: BlockNumber,
+    }
+
+    /// Emitted when a node confirmed it deleted a share, or missed to.
+    #[ink(event)]
+    pub struct ShareDeletionResolved {
+        #[ink(topic)]
+        node: AccountId,
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        confirmed: bool,
+    }
+
+    /// Emitted when a user asks its guardians to serve their shares.
+    #[ink(event)]
+    pub struct RecoveryStarted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        new_owner: Option<AccountId>,
+    }
+
+    /// Emitted each time a guardian confirms it served its share.
+    #[ink(event)]
+    pub struct RecoveryConfirmed {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        node: AccountId,
+        /// The accepted proof, the recovery only keeps its hash
+        proof: RecoveryProof,
+        /// Guardian weight confirmed so far, out of the user's threshold
+        confirmations: u64,
+        threshold: u32,
+    }
+
+    /// Emitted when an oracle saw a user pass a condition check.
+    #[ink(event)]
+    pub struct VerificationPosted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        cond_type: ConditionType,
+        verified_at: BlockNumber,
+        #[ink(topic)]
+        oracle: AccountId,
+    }
+
+    /// Emitted each time a social guardian approves a recovery.
+    #[ink(event)]
+    pub struct RecoveryApproved {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        guardian: AccountId,
+    }
+
+    /// Emitted when a recovery did not reach its threshold in time.
+    #[ink(event)]
+    pub struct RecoveryExpired {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        recovery_id: RecoveryId,
+    }
+
+    /// Emitted whenever a recovery attempt moves to another status.
+    #[ink(event)]
+    pub struct RecoveryStatusChanged {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        recovery_id: RecoveryId,
+        status: RecoveryStatus,
+    }
+
+    /// Emitted when a user aborts its own recovery, nodes stop serving shares.
+    #[ink(event)]
+    pub struct RecoveryCancelled {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        recovery_id: RecoveryId,
+    }
+
+    /// Emitted when a recovery the user did not ask for was vetoed, wallets
+    /// should alert the user.
+    #[ink(event)]
+    pub struct RecoveryVetoed {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        vetoed_by: AccountId,
+        suspicious_attempts: u32,
+    }
+
+    /// Emitted when too many failed attempts locked a user's recovery.
+    #[ink(event)]
+    pub struct RecoveryLocked {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        failed_attempts: u32,
+    }
+
+    /// Emitted when storage rent for one or more periods was paid to the
+    /// guardians of a secret.
+    #[ink(event)]
+    pub struct RentCharged {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        periods: u32,
+        amount: Balance,
     }
 
-    pub type Result<T> = core::result::Result<T, Error>;
+    /// Emitted when a secret's prepaid rent ran out, its guardians may purge
+    /// the shares from `purge_after` on.
+    #[ink(event)]
+    pub struct RentOverdue {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        purge_after: BlockNumber,
+    }
+
+    /// Emitted when a new account got its starter grant from the faucet.
+    #[ink(event)]
+    pub struct StarterGrantClaimed {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a referrer was paid for a new user.
+    #[ink(event)]
+    pub struct ReferralRewarded {
+        #[ink(topic)]
+        referrer: AccountId,
+        #[ink(topic)]
+        user: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a guardian asked to freeze a user's secret.
+    #[ink(event)]
+    pub struct FreezeVoted {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        node: AccountId,
+    }
+
+    /// Emitted when a quorum of guardians froze a user's secret, it stays
+    /// locked until the user goes through request_unlock.
+    #[ink(event)]
+    pub struct UserFrozen {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+    }
+
+    /// Emitted when a user asks to unlock recovery, the unlock is possible
+    /// from `unlock_block` on.
+    #[ink(event)]
+    pub struct UnlockRequested {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        unlock_block: BlockNumber,
+    }
+
+    /// Emitted when a locked recovery got unlocked.
+    #[ink(event)]
+    pub struct RecoveryUnlocked {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+    }
+
+    /// Emitted when the fee of a recovery attempt is locked in escrow.
+    /// `fee_token` is the PSP22 contract the fee is held in, None for the
+    /// built in ledger, here and in the other recovery accounting events.
+    #[ink(event)]
+    pub struct FeeCharged {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        payer: AccountId,
+        amount: Balance,
+        fee_token: Option<AccountId>,
+    }
+
+    /// Emitted for every guardian paid for a finished recovery.
+    #[ink(event)]
+    pub struct NodePaid {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        node: AccountId,
+        amount: Balance,
+        fee_token: Option<AccountId>,
+    }
+
+    /// Emitted when the treasury keeps its cut of a finished recovery's fee.
+    #[ink(event)]
+    pub struct TreasuryCut {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        amount: Balance,
+        fee_token: Option<AccountId>,
+    }
+
+    /// Emitted when the escrowed fee of an unfinished attempt goes back to
+    /// its payer.
+    #[ink(event)]
+    pub struct FeeRefunded {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        #[ink(topic)]
+        payer: AccountId,
+        amount: Balance,
+        fee_token: Option<AccountId>,
+    }
+
+    /// Emitted when a recovered user record moved to a new account.
+    #[ink(event)]
+    pub struct UserMigrated {
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        secret_id: SecretId,
+    }
+
+    /// Emitted when enough guardians confirmed, the recovery can be finalized
+    /// from `finalizable_at` unless the user cancels it.
+    #[ink(event)]
+    pub struct RecoveryPendingFinalize {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        #[ink(topic)]
+        recovery_id: RecoveryId,
+        finalizable_at: BlockNumber,
+    }
+
+    /// Emitted once enough guardians confirmed and the nodes got paid.
+    #[ink(event)]
+    pub struct RecoveryFinished {
+        #[ink(topic)]
+        user: AccountId,
+        secret_id: SecretId,
+        r_times: u32,
+    }
+
+    /// Identifies one of the independent secrets a user protects, each with
+    /// its own guardians and recovery state.
+    pub type SecretId = u32;
+
+    /// Compressed secp256k1 public key, a 0x02 or 0x03 prefix and the 32
+    /// byte x coordinate.
+    pub type PublicKey = [u8; 33];
+
+    /// Identifies a single recovery attempt, unique across all users.
+    pub type RecoveryId = u64;
+
+    /// Identifies a queued admin action.
+    pub type ActionId = u32;
+
+    /// Identifies a governance proposal.
+    pub type ProposalId = u32;
+
+    /// Identifies a fraud report against a node.
+    pub type ReportId = u32;
+
+    /// Roughly seven days of 6 second blocks.
+    const DEFAULT_VESTING_CLIFF: BlockNumber = 100_800;
+    /// Roughly ninety days of 6 second blocks.
+    const DEFAULT_VESTING_DURATION: BlockNumber = 1_296_000;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_UNBONDING_PERIOD: BlockNumber = 14_400;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_KEY_TRANSITION_PERIOD: BlockNumber = 14_400;
+    /// Roughly thirty days of 6 second blocks.
+    const DEFAULT_ATTESTATION_VALIDITY: BlockNumber = 432_000;
+    /// Roughly one hour of 6 second blocks.
+    const DEFAULT_LIVENESS_WINDOW: BlockNumber = 600;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_RECOVERY_EXPIRY: BlockNumber = 14_400;
+    const DEFAULT_RECOVERY_COOLDOWN: BlockNumber = 100;
+    /// Roughly twelve hours of 6 second blocks.
+    const DEFAULT_CHALLENGE_PERIOD: BlockNumber = 7_200;
+    const DEFAULT_MAX_FAILED_ATTEMPTS: u32 = 3;
+    /// Roughly three days of 6 second blocks.
+    const DEFAULT_UNLOCK_DELAY: BlockNumber = 43_200;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_DELETION_GRACE_PERIOD: BlockNumber = 14_400;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_DELETION_ACK_WINDOW: BlockNumber = 14_400;
+    /// Roughly thirty days of 6 second blocks.
+    const DEFAULT_BILLING_PERIOD: BlockNumber = 432_000;
+    /// Roughly seven days of 6 second blocks.
+    const DEFAULT_RENT_GRACE_PERIOD: BlockNumber = 100_800;
+    /// Roughly two days of 6 second blocks.
+    const DEFAULT_TIMELOCK_DELAY: BlockNumber = 28_800;
+    /// Roughly one week of 6 second blocks.
+    const DEFAULT_VOTING_PERIOD: BlockNumber = 100_800;
+    /// Share of the total supply that must vote for a proposal, 10%.
+    const PROPOSAL_QUORUM_BPS: u16 = 1_000;
+    /// Roughly one day of 6 second blocks.
+    const DEFAULT_EPOCH_LENGTH: BlockNumber = 14_400;
+    /// Share of a slashed stake that goes to the reporter, 50%.
+    const REPORTER_REWARD_BPS: u16 = 5_000;
+    /// Reward points for serving a share of a finished recovery, a heartbeat
+    /// is worth 1.
+    const RECOVERY_REWARD_POINTS: u32 = 10;
+    /// Fixed point precision of the per delegated token rewards.
+    const REWARD_PER_SHARE_SCALE: u128 = 1_000_000_000_000;
+    /// Starter grants handed out per block at most, so fresh accounts can
+    /// not drain the faucet in one go.
+    const MAX_FAUCET_CLAIMS_PER_BLOCK: u32 = 10;
+
+    /// 100% in basis points.
+    const MAX_BPS: u16 = 10_000;
+    /// Longest node endpoint URL, in bytes.
+    const MAX_ENDPOINT_LEN: usize = 256;
+    /// Longest node region code, in bytes.
+    const MAX_REGION_LEN: usize = 32;
+    /// Longest evidence a slash or fraud report carries, in bytes.
+    const MAX_EVIDENCE_LEN: usize = 4_096;
+    /// Longest condition proof a guardian hands in, in bytes.
+    const MAX_CONDITION_PROOF_LEN: usize = 4_096;
+    /// Most confirmations a batched or aggregated call carries.
+    const MAX_BATCH_LEN: usize = 32;
+
+    /// Layout of the records this code reads and writes. Version 1 stored
+    /// three fixed guardians per user.
+    const STORAGE_VERSION: u32 = 2;
+    /// Root keys version 1 kept its users, recoveries and nodes under, by
+    /// account. The mappings of the same name still use them, with other key
+    /// and value types
+    const V1_USERS_KEY: Key = KeyComposer::from_str("KeyLedger::users");
+    const V1_RECOVERIES_KEY: Key = KeyComposer::from_str("KeyLedger::recoveries");
+    const V1_NODES_KEY: Key = KeyComposer::from_str("KeyLedger::nodes");
+    /// Root key version 1 node records with a key that does not decode are
+    /// moved to, the nodes mapping could not read them
+    const V1_STRANDED_NODES_KEY: Key = KeyComposer::from_str("KeyLedger::stranded_nodes");
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// `a + b` for token amounts, `Error::Overflow` instead of wrapping.
+    fn safe_add(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_add(b).ok_or(Error::Overflow)
+    }
+
+    /// `a - b` for token amounts, `Error::InsufficientBalance` instead of
+    /// panicking when `b` is larger.
+    fn safe_sub(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_sub(b).ok_or(Error::InsufficientBalance)
+    }
+
+    /// The ERC-20 error types.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum Error {
+        /// Returned if not enough balance to fulfill a request is available.
+        InsufficientBalance,
+        /// Returned if the caller is not the contract owner.
+        NotOwner,
+        /// Returned if the caller was not offered ownership.
+        NotPendingOwner,
+        /// Returned if the caller neither is the owner nor holds the role
+        /// the message needs.
+        MissingRole,
+        /// Returned if the contract is paused.
+        Paused,
+        /// Returned if the runtime refused to switch to the new code.
+        UpgradeFailed,
+        /// Returned if the stored records already use the current layout.
+        AlreadyMigrated,
+        /// Returned if no admin action is queued under the id.
+        ActionNotFound,
+        /// Returned if a queued admin action is executed before its delay
+        /// passed, with the blocks left to wait.
+        ActionNotReady(BlockNumber),
+        /// Returned if the caller is not on the admin council.
+        NotCouncilMember,
+        /// Returned if the council member already approved the action.
+        AlreadyApproved,
+        /// Returned if a queued admin action has fewer approvals than the
+        /// council quorum.
+        QuorumNotReached,
+        /// Returned if a council has duplicate members or a quorum of 0 or
+        /// above its size.
+        InvalidCouncil,
+        /// Returned if token holders propose an action reserved to the
+        /// owner and council.
+        NotGovernable,
+        /// Returned if no proposal exists under the id.
+        ProposalNotFound,
+        /// Returned if voting on a proposal is over.
+        VotingClosed,
+        /// Returned if a proposal is executed while voting is still open,
+        /// with the blocks left.
+        VotingOpen(BlockNumber),
+        /// Returned if the account already voted on the proposal.
+        AlreadyVoted,
+        /// Returned if the node has no unclaimed points in the previous epoch.
+        NoRewards,
+        /// Returned if undelegating more than was delegated to the node.
+        InsufficientDelegation,
+        /// Returned if a proposal did not get a majority or quorum, or was
+        /// already executed.
+        ProposalNotPassed,
+        /// Returned if pausing an already paused contract or unpausing one
+        /// that is not paused.
+        PauseStateUnchanged,
+        /// Returned if a basis point value is above 10000.
+        InvalidBasisPoints,
+        /// Returned if the caller already registered itself as a node.
+        NodeAlreadyRegistered,
+        /// Returned if the caller is not a registered node.
+        NodeNotRegistered,
+        /// Returned if the enclave measurement is not on the allowlist.
+        MeasurementNotAllowed,
+        /// Returned if the allowlist is on and the operator is not approved.
+        OperatorNotApproved,
+        /// Returned if the attestation signature does not match the service key.
+        InvalidAttestation,
+        /// Returned if the node's attestation lapsed, see `renew_attestation`.
+        AttestationExpired,
+        /// Returned if a node has less than the minimum stake locked.
+        InsufficientStake,
+        /// Returned if the node is leaving and takes no new users.
+        NodeExiting,
+        /// Returned if the node was blacklisted.
+        NodeBlacklisted,
+        /// Returned if a guardian has a weight of 0.
+        InvalidWeight,
+        /// Returned if the account already got its starter grant.
+        AlreadyClaimed,
+        /// Returned if the faucet can not cover a starter grant.
+        FaucetEmpty,
+        /// Returned if an account names itself as its referrer.
+        SelfReferral,
+        /// Returned if no oracle verified the guardian's condition during the
+        /// running attempt.
+        VerificationMissing,
+        /// Returned if an oracle posts a check from a future block.
+        InvalidVerificationBlock,
+        /// Returned if the fee token refused to move a fee.
+        FeeTransferFailed,
+        /// Returned if the new key is the one already registered.
+        SameKey,
+        /// Returned if a key, proof, endpoint or other supplied value is too
+        /// long or not in the expected format.
+        InvalidInput,
+        /// Returned if not every guardian acknowledged its share yet.
+        RegistrationPending,
+        /// Returned if the acknowledged share is not the one the user proposed.
+        ShareMismatch,
+        /// Returned if the guardian already acknowledged its share.
+        AlreadyAcknowledged,
+        /// Returned if a token amount does not fit into a Balance.
+        Overflow,
+        /// Returned if fewer live, eligible nodes exist than guardians are needed.
+        NotEnoughNodes,
+        /// Returned if the node already guards as many users as its capacity.
+        NodeAtCapacity,
+        /// Returned if the condition verifier rejected the proof.
+        InvalidConditionProof,
+        /// Returned if the session key is a node or used by another node.
+        SessionKeyInUse,
+        /// Returned if less native tokens than the registration fee were sent.
+        RegistrationFeeTooLow,
+        /// Returned if the chain refused a native token transfer.
+        NativeTransferFailed,
+        /// Returned if there is no open report with the id.
+        ReportNotFound,
+        /// Returned if the node has not called deregister_node.
+        NodeNotExiting,
+        /// Returned if the unbonding period of the node is not over yet.
+        StakeLocked,
+        /// Returned if the account has no registered guardian configuration.
+        UserNotFound,
+        /// Returned if the account already has a guardian configuration.
+        UserAlreadyRegistered,
+        /// Returned if the threshold is 0 or above the number of guardians.
+        InvalidThreshold,
+        /// Returned if a node confirms a recovery the user has not started.
+        RecoveryNotActive,
+        /// Returned if the node is not one of the user's guardians.
+        NotAssignedGuardian,
+        /// Returned if the last recovery finished too recently, carries the
+        /// number of blocks left until a new one can start.
+        CooldownActive(BlockNumber),
+        /// Returned if too many recoveries were started lately, carries the
+        /// first block a start is allowed again.
+        RateLimited(BlockNumber),
+        /// Returned if recovery is locked for the user, after too many failed
+        /// attempts or a freeze by its guardians.
+        RecoveryLocked,
+        /// Returned if unlocking a recovery that is not locked.
+        RecoveryNotLocked,
+        /// Returned if the unlock was not requested or its delay is not over.
+        UnlockNotReady,
+        /// Returned if the recovery can not be expired yet.
+        RecoveryNotExpired,
+        /// Returned if the user asked to be deleted.
+        DeletionPending,
+        /// Returned if no deletion was requested or its grace period is not over.
+        DeletionNotReady,
+        /// Returned if a signature does not match the registered key.
+        InvalidSignature,
+        /// Returned if a relayed call does not use the user's next nonce.
+        InvalidNonce,
+        /// Returned if the change is not allowed while a recovery is running.
+        RecoveryInProgress,
+        /// Returned if the user is moving to a new guardian set.
+        ResharePending,
+        /// Returned if a condition type is not one of ConditionType.
+        UnknownCondition,
+        /// Returned if the caller is not the secret's beneficiary.
+        NotBeneficiary,
+        /// Returned if the user checked in too recently, with the blocks left
+        /// until the beneficiary can recover.
+        OwnerStillActive(BlockNumber),
+        /// Returned if not enough guardians confirmed the recovery yet.
+        ThresholdNotReached,
+        /// Returned if a node confirms before enough social guardians approved.
+        ApprovalsMissing,
+        /// Returned if the caller is not one of the user's social guardians.
+        NotSocialGuardian,
+        /// Returned if the caller is neither the user nor its veto key.
+        NotVetoKey,
+        /// Returned if the owner can still veto, with the blocks left.
+        VetoWindowOpen(BlockNumber),
+        /// Returned if the user has no reshare pending.
+        NoReshare,
+        /// Returned if the node already is one of the user's guardians.
+        DuplicateGuardian,
+        /// Returned if a recovery proof is not signed by the node for the
+        /// current recovery attempt.
+        InvalidProof,
+        /// Returned if a node served a share from before the last
+        /// re-distribution.
+        StaleShare,
+        /// Returned if the node was not asked to delete the share.
+        DeletionNotRequested,
+        /// Returned if the deletion deadline passed, or has not yet for a
+        /// report.
+        DeletionWindowClosed,
+        /// Returned if a missed deletion is reported before its deadline.
+        DeletionWindowOpen,
+    }
+
+    pub type PSP22Result<T> = core::result::Result<T, PSP22Error>;
+
+    /// The PSP22 error types, the variant order is fixed by the standard.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum PSP22Error {
+        /// Custom error type for cases if the implementation adds its own restrictions.
+        Custom(String),
+        /// Returned if not enough balance to fulfill a request is available.
+        InsufficientBalance,
+        /// Returned if not enough allowance to fulfill a request is available.
+        InsufficientAllowance,
+        /// Returned if recipient's address is zero.
+        ZeroRecipientAddress,
+        /// Returned if sender's address is zero.
+        ZeroSenderAddress,
+        /// Returned if a safe transfer check fails.
+        SafeTransferCheckFailed(String),
+    }
+
+    impl From<Error> for PSP22Error {
+        fn from(error: Error) -> Self {
+            match error {
+                Error::InsufficientBalance => PSP22Error::InsufficientBalance,
+                e => PSP22Error::Custom(format!("{:?}", e)),
+            }
+        }
+    }
+
+    /// The PSP22 fungible token standard, message selectors are derived from
+    /// the `PSP22::` prefixed names so the trait must keep this name.
+    #[ink::trait_definition]
+    pub trait PSP22 {
+        /// Returns the total token supply.
+        #[ink(message)]
+        fn total_supply(&self) -> Balance;
+
+        /// Returns the account balance for the specified `owner`.
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance;
+
+        /// Returns the amount which `spender` is still allowed to withdraw from `owner`.
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance;
+
+        /// Transfers `value` tokens from the caller's account to `to`.
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance, data: Vec<u8>) -> PSP22Result<()>;
+
+        /// Transfers `value` tokens on the behalf of `from` to `to`.
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance,
+            data: Vec<u8>) -> PSP22Result<()>;
+
+        /// Allows `spender` to withdraw from the caller's account up to `value` tokens.
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> PSP22Result<()>;
+
+        /// Atomically increases the allowance granted to `spender` by the caller.
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: AccountId, delta_value: Balance) -> PSP22Result<()>;
+
+        /// Atomically decreases the allowance granted to `spender` by the caller.
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: AccountId, delta_value: Balance) -> PSP22Result<()>;
+    }
+
+    /// Checks that a recovery requester met a guardian's condition, e.g. a
+    /// zero knowledge proof of a passed email OTP that does not reveal the
+    /// email. Called by finish_recovery for condition types with a verifier.
+    #[ink::trait_definition]
+    pub trait ConditionVerifier {
+        /// Returns true if `proof` shows the condition committed to in
+        /// `cond_commitment` was met for this recovery attempt.
+        #[ink(message)]
+        fn verify(&self, user: AccountId, secret_id: SecretId, recovery_id: RecoveryId,
+            cond_commitment: Hash, proof: Vec<u8>) -> bool;
+    }
+
+    /// The PSP22 metadata extension.
+    #[ink::trait_definition]
+    pub trait PSP22Metadata {
+        /// Returns the token name.
+        #[ink(message)]
+        fn token_name(&self) -> Option<String>;
+
+        /// Returns the token symbol.
+        #[ink(message)]
+        fn token_symbol(&self) -> Option<String>;
+
+        /// Returns the token decimals.
+        #[ink(message)]
+        fn token_decimals(&self) -> u8;
+    }
+
+    impl KeyLedger {
+        /// Constructor that initializes maps, without token metadata
+        #[ink(constructor)]
+        pub fn new(total_supply: Balance) -> Self {
+            Self::new_with_metadata(total_supply, None, None, 0)
+        }
+
+        /// Constructor that also sets the PSP22 metadata of the token
+        #[ink(constructor)]
+        pub fn new_with_metadata(total_supply: Balance, name: Option<String>,
+            symbol: Option<String>, decimals: u8) -> Self {
+            let mut balances = Mapping::default();
+            let mut nodes = Mapping::default();
+            let mut users = Mapping::default();
+            let mut recoveries  = Mapping::default();
+            let caller = Self::env().caller();
+            balances.insert(caller, &total_supply);
+            Self::env().emit_event(Transfer {
+                from: None,
+                to: Some(caller),
+                value: total_supply,
+            });
+            let mut instance = Self {
+                total_supply: total_supply,
+                name: Lazy::new(),
+                symbol: Lazy::new(),
+                decimals: Lazy::new(),
+                owner: Lazy::new(),
+                pending_owner: Lazy::new(),
+                paused: Lazy::new(),
+                roles: Mapping::default(),
+                contract_version: Lazy::new(),
+                storage_version: Lazy::new(),
+                queued_actions: Mapping::default(),
+                last_action_id: Lazy::new(),
+                council: Lazy::new(),
+                council_quorum: Lazy::new(),
+                action_approvals: Mapping::default(),
+                proposals: Mapping::default(),
+                last_proposal_id: Lazy::new(),
+                votes: Mapping::default(),
+                vote_locks: Mapping::default(),
+                balances: balances,
+                allowances: Mapping::default(),
+                nodes: nodes,
+                users: users,
+                recoveries: recoveries,
+                recovery_history: Mapping::default(),
+                recent_starts: Mapping::default(),
+                recovery_requests: Mapping::default(),
+                payout_counts: Mapping::default(),
+                payouts: Mapping::default(),
+                recovery_ids: Mapping::default(),
+                last_recovery_id: Lazy::new(),
+                reshares: Mapping::default(),
+                dead_man_switches: Mapping::default(),
+                beneficiaries: Mapping::default(),
+                social_guardians: Mapping::default(),
+                verifications: Mapping::default(),
+                verification_required: Mapping::default(),
+                veto_keys: Mapping::default(),
+                freeze_votes: Mapping::default(),
+                social_approvals: Mapping::default(),
+                last_check_ins: Mapping::default(),
+                nonces: Mapping::default(),
+                assignment_nonces: Mapping::default(),
+                node_count: Lazy::new(),
+                allowed_measurements: Mapping::default(),
+                condition_verifiers: Mapping::default(),
+                attestation_signer: Lazy::new(),
+                operator_allowlist: Lazy::new(),
+                approved_operators: Mapping::default(),
+                node_ids: Mapping::default(),
+                node_positions: Mapping::default(),
+                blacklisted_nodes: Mapping::default(),
+                migrated_nodes: Mapping::default(),
+                session_keys: Mapping::default(),
+                session_nodes: Mapping::default(),
+                assigned_counts: Mapping::default(),
+                assigned_users: Mapping::default(),
+                assigned_positions: Mapping::default(),
+                deletion_deadlines: Mapping::default(),
+                stakes: Mapping::default(),
+                reputations: Mapping::default(),
+                reports: Mapping::default(),
+                last_report_id: Lazy::new(),
+                reward_pool: Lazy::new(),
+                epoch_points: Mapping::default(),
+                epoch_total_points: Mapping::default(),
+                epoch_rewards: Mapping::default(),
+                delegations: Mapping::default(),
+                delegated_stakes: Mapping::default(),
+                commissions: Mapping::default(),
+                reward_per_share: Mapping::default(),
+                vesting_schedules: Mapping::default(),
+                vesting_indices: Mapping::default(),
+                recovery_starts: Lazy::new(),
+                account_recovery_starts: Mapping::default(),
+                subscriptions: Mapping::default(),
+                user_count: Lazy::new(),
+                user_ids: Mapping::default(),
+                user_positions: Mapping::default(),
+                active_recoveries: Lazy::new(),
+                completed_recoveries: Lazy::new(),
+                treasury: Lazy::new(),
+                referrals: Mapping::default(),
+                faucet: Lazy::new(),
+                starter_grants: Mapping::default(),
+                faucet_claims: Lazy::new(),
+                native_treasury: Lazy::new(),
+                native_deposits: Lazy::new(),
+                fee_token: Lazy::new(),
+                fee_token_treasuries: Mapping::default(),
+                config: Lazy::new(),
+            };
+            if let Some(name) = name {
+                instance.name.set(&name);
+            }
+            if let Some(symbol) = symbol {
+                instance.symbol.set(&symbol);
+            }
+            instance.decimals.set(&decimals);
+            instance.owner.set(&caller);
+            instance.paused.set(&false);
+            instance.contract_version.set(&1);
+            instance.storage_version.set(&STORAGE_VERSION);
+            instance.council.set(&vec![caller]);
+            instance.council_quorum.set(&1);
+            instance.config.set(&Config::default());
+            instance
+        }
+
+        #[ink(message)]
+        pub fn owner(&self) -> AccountId {
+            self.owner.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn pending_owner(&self) -> Option<AccountId> {
+            self.pending_owner.get().flatten()
+        }
+
+        // offer ownership to new_owner, nothing changes until it accepts so a
+        // mistyped account can not brick the contract. a new offer replaces
+        // the previous one
+        #[ink(message)]
+        pub fn transfer_ownership(&mut self, new_owner: AccountId) -> Result<()> {
+            self.ensure_owner()?;
+            self.pending_owner.set(&Some(new_owner));
+            self.env().emit_event(OwnershipTransferStarted {
+                previous_owner: self.owner(),
+                new_owner,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn accept_ownership(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            if self.pending_owner() != Some(caller) {
+                return Err(Error::NotPendingOwner)
+            }
+            let previous_owner = self.owner();
+            self.owner.set(&caller);
+            self.pending_owner.set(&None);
+            self.env().emit_event(OwnershipTransferred {
+                previous_owner,
+                new_owner: caller,
+            });
+            Ok(())
+        }
+
+        fn ensure_owner(&self) -> Result<()> {
+            if self.env().caller() != self.owner() {
+                return Err(Error::NotOwner)
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn has_role(&self, role: Role, account: AccountId) -> bool {
+            account == self.owner() || self.roles.contains((role, account))
+        }
+
+        fn grant_role(&mut self, role: Role, account: AccountId) {
+            self.roles.insert((role, account), &());
+            self.env().emit_event(RoleGranted { role, account });
+        }
+
+        #[ink(message)]
+        pub fn revoke_role(&mut self, role: Role, account: AccountId) -> Result<()> {
+            self.ensure_owner()?;
+            self.roles.remove((role, account));
+            self.env().emit_event(RoleRevoked { role, account });
+            Ok(())
+        }
+
+        fn ensure_role(&self, role: Role) -> Result<()> {
+            if !self.has_role(role, self.env().caller()) {
+                return Err(Error::MissingRole)
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn paused(&self) -> bool {
+            self.paused.get().unwrap_or(true)
+        }
+
+        // halt registrations, recoveries and transfers during an incident,
+        // admin tools and heartbeats keep working
+        #[ink(message)]
+        pub fn pause(&mut self) -> Result<()> {
+            self.ensure_role(Role::Pauser)?;
+            if self.paused() {
+                return Err(Error::PauseStateUnchanged)
+            }
+            self.paused.set(&true);
+            self.env().emit_event(Paused { by: self.env().caller() });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn unpause(&mut self) -> Result<()> {
+            self.ensure_role(Role::Pauser)?;
+            if !self.paused() {
+                return Err(Error::PauseStateUnchanged)
+            }
+            self.paused.set(&false);
+            self.env().emit_event(Unpaused { by: self.env().caller() });
+            Ok(())
+        }
+
+        fn ensure_not_paused(&self) -> Result<()> {
+            if self.paused() {
+                return Err(Error::Paused)
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn contract_version(&self) -> u32 {
+            self.contract_version.get().unwrap_or_default()
+        }
+
+        // switch to new contract code, storage and the contract address stay
+        // the same so users keep their records. runs as a queued admin action
+        fn upgrade(&mut self, code_hash: Hash) -> Result<()> {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(code_hash.as_ref());
+            ink::env::set_code_hash(&hash).map_err(|_| Error::UpgradeFailed)?;
+            let contract_version = self.contract_version() + 1;
+            self.contract_version.set(&contract_version);
+            self.env().emit_event(Upgraded { code_hash, contract_version });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn timelock_delay(&self) -> BlockNumber {
+            self.get_config().timelock_delay
+        }
+
+        #[ink(message)]
+        pub fn queued_action(&self, action_id: ActionId) -> Option<QueuedAction> {
+            self.queued_actions.get(action_id)
+        }
+
+        // the role or owner that may queue, cancel and execute an action
+        fn ensure_action_allowed(&self, action: &AdminAction) -> Result<()> {
+            match action {
+                AdminAction::AllowMeasurement(_)
+                | AdminAction::RevokeMeasurement(_)
+                | AdminAction::SetAttestationSigner(_)
+                | AdminAction::ApproveOperator(_)
+                | AdminAction::RevokeOperator(_) => self.ensure_role(Role::NodeApprover),
+                AdminAction::SetRecoveryFee(_)
+                | AdminAction::SetTreasuryBps(_)
+                | AdminAction::SetMinBalance(_)
+                | AdminAction::SetRegistrationFee(_)
+                | AdminAction::SetStorageFee(_)
+                | AdminAction::SetReferralReward(_)
+                | AdminAction::SetStarterGrant(_)
+                | AdminAction::SetFeeCurve(_) => self.ensure_role(Role::FeeAdmin),
+                AdminAction::Upgrade(_)
+                | AdminAction::SetTimelockDelay(_)
+                | AdminAction::SetVotingPeriod(_)
+                | AdminAction::SetCouncil(..)
+                | AdminAction::SetMinNodeStake(_)
+                | AdminAction::SetOperatorAllowlist(_)
+                | AdminAction::SetConditionVerifier(..)
+                | AdminAction::SetFeeToken(_)
+                | AdminAction::SetLivenessWindow(_)
+                | AdminAction::SetUnbondingPeriod(_)
+                | AdminAction::SetKeyTransitionPeriod(_)
+                | AdminAction::SetAttestationValidity(_)
+                | AdminAction::SetEpochLength(_)
+                | AdminAction::SetEpochEmission(_)
+                | AdminAction::SetVestingCliff(_)
+                | AdminAction::SetVestingDuration(_)
+                | AdminAction::SetDeletionGracePeriod(_)
+                | AdminAction::SetDeletionAckWindow(_)
+                | AdminAction::SetDeletionPenalty(_)
+                | AdminAction::SetBillingPeriod(_)
+                | AdminAction::SetRentGracePeriod(_)
+                | AdminAction::SetChallengePeriod(_)
+                | AdminAction::SetMaxFailedAttempts(_)
+                | AdminAction::SetUnlockDelay(_)
+                | AdminAction::SetRecoveryCooldown(_)
+                | AdminAction::SetRateLimit(_)
+                | AdminAction::SetRecoveryExpiry(_)
+                | AdminAction::Mint(..)
+                | AdminAction::Burn(..)
+                | AdminAction::WithdrawTreasury(..)
+                | AdminAction::WithdrawNativeTreasury(..)
+                | AdminAction::WithdrawFeeTokenTreasury(..)
+                | AdminAction::GrantRole(..) => self.ensure_owner(),
+            }
+        }
+
+        // privileged changes are announced first and only take effect after
+        // timelock_delay, so users can exit before a hostile change
+        #[ink(message)]
+        pub fn queue_action(&mut self, action: AdminAction) -> Result<ActionId> {
+            self.ensure_action_allowed(&action)?;
+            match &action {
+                AdminAction::SetTreasuryBps(bps) if *bps > MAX_BPS => {
+                    return Err(Error::InvalidBasisPoints)
+                }
+                AdminAction::SetCouncil(members, quorum) => Self::check_council(members, *quorum)?,
+                _ => {}
+            }
+            let action_id = self.last_action_id.get().unwrap_or_default() + 1;
+            self.last_action_id.set(&action_id);
+            let eta = self.env().block_number() + self.get_config().timelock_delay;
+            self.queued_actions.insert(action_id, &QueuedAction {
+                action: action.clone(),
+                eta,
+            });
+            self.env().emit_event(ActionQueued { action_id, action, eta });
+            // proposing counts as approving for council members
+            if self.council_members().contains(&self.env().caller()) {
+                self.approve_action(action_id)?;
+            }
+            Ok(action_id)
+        }
+
+        #[ink(message)]
+        pub fn council(&self) -> (Vec<AccountId>, u32) {
+            (self.council_members(), self.council_quorum.get().unwrap_or_default())
+        }
+
+        fn council_members(&self) -> Vec<AccountId> {
+            self.council.get().unwrap_or_default()
+        }
+
+        fn check_council(members: &[AccountId], quorum: u32) -> Result<()> {
+            if quorum == 0 || quorum as usize > members.len() {
+                return Err(Error::InvalidCouncil)
+            }
+            for (i, m) in members.iter().enumerate() {
+                if members[..i].contains(m) {
+                    return Err(Error::InvalidCouncil)
+                }
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn approve_action(&mut self, action_id: ActionId) -> Result<()> {
+            let member = self.env().caller();
+            if !self.council_members().contains(&member) {
+                return Err(Error::NotCouncilMember)
+            }
+            if !self.queued_actions.contains(action_id) {
+                return Err(Error::ActionNotFound)
+            }
+            if self.action_approvals.contains((action_id, member)) {
+                return Err(Error::AlreadyApproved)
+            }
+            self.action_approvals.insert((action_id, member), &());
+            self.env().emit_event(ActionApproved {
+                action_id,
+                member,
+                approvals: self.action_approvals(action_id),
+            });
+            Ok(())
+        }
+
+        // only approvals of current council members count
+        #[ink(message)]
+        pub fn action_approvals(&self, action_id: ActionId) -> u32 {
+            self.council_members().iter()
+                .filter(|m| self.action_approvals.contains((action_id, **m)))
+                .count() as u32
+        }
+
+        fn remove_action(&mut self, action_id: ActionId) {
+            self.queued_actions.remove(action_id);
+            for m in self.council_members().iter() {
+                self.action_approvals.remove((action_id, *m));
+            }
+        }
+
+        #[ink(message)]
+        pub fn cancel_action(&mut self, action_id: ActionId) -> Result<()> {
+            let queued = self.queued_actions.get(action_id).ok_or(Error::ActionNotFound)?;
+            self.ensure_action_allowed(&queued.action)?;
+            self.remove_action(action_id);
+            self.env().emit_event(ActionCancelled { action_id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn execute_action(&mut self, action_id: ActionId) -> Result<()> {
+            let queued = self.queued_actions.get(action_id).ok_or(Error::ActionNotFound)?;
+            self.ensure_action_allowed(&queued.action)?;
+            let now = self.env().block_number();
+            if now < queued.eta {
+                return Err(Error::ActionNotReady(queued.eta - now))
+            }
+            if self.action_approvals(action_id) < self.council_quorum.get().unwrap_or_default() {
+                return Err(Error::QuorumNotReached)
+            }
+            self.remove_action(action_id);
+            self.apply_action(queued.action)?;
+            self.env().emit_event(ActionExecuted { action_id });
+            Ok(())
+        }
+
+        fn apply_action(&mut self, action: AdminAction) -> Result<()> {
+            let mut config = self.get_config();
+            match action {
+                AdminAction::AllowMeasurement(measurement) => self.allow_measurement(measurement),
+                AdminAction::RevokeMeasurement(measurement) => self.revoke_measurement(measurement),
+                AdminAction::SetAttestationSigner(signer) => self.set_attestation_signer(signer),
+                AdminAction::SetRecoveryFee(fee) => config.recovery_fee = fee,
+                AdminAction::SetTreasuryBps(bps) => {
+                    if bps > MAX_BPS {
+                        return Err(Error::InvalidBasisPoints)
+                    }
+                    config.treasury_bps = bps;
+                }
+                AdminAction::SetMinBalance(balance) => config.min_balance = balance,
+                AdminAction::SetRegistrationFee(fee) => config.registration_fee = fee,
+                AdminAction::SetStorageFee(fee) => config.storage_fee = fee,
+                AdminAction::SetReferralReward(reward) => config.referral_reward = reward,
+                AdminAction::SetStarterGrant(grant) => config.starter_grant = grant,
+                AdminAction::SetFeeCurve(curve) => config.fee_curve = curve,
+                AdminAction::Upgrade(code_hash) => self.upgrade(code_hash)?,
+                AdminAction::SetTimelockDelay(blocks) => config.timelock_delay = blocks,
+                AdminAction::SetVotingPeriod(blocks) => config.voting_period = blocks,
+                AdminAction::SetCouncil(members, quorum) => {
+                    self.council.set(&members);
+                    self.council_quorum.set(&quorum);
+                }
+                AdminAction::SetMinNodeStake(stake) => config.min_node_stake = stake,
+                AdminAction::SetOperatorAllowlist(on) => self.operator_allowlist.set(&on),
+                AdminAction::ApproveOperator(operator) => self.approve_operator(operator),
+                AdminAction::RevokeOperator(operator) => self.revoke_operator(operator),
+                AdminAction::SetConditionVerifier(cond_type, verifier) => match verifier {
+                    Some(verifier) => {
+                        self.condition_verifiers.insert(cond_type, &verifier);
+                    }
+                    None => self.condition_verifiers.remove(cond_type),
+                },
+                AdminAction::SetFeeToken(token) => self.fee_token.set(&token),
+                AdminAction::SetLivenessWindow(blocks) => config.liveness_window = blocks,
+                AdminAction::SetUnbondingPeriod(blocks) => config.unbonding_period = blocks,
+                AdminAction::SetKeyTransitionPeriod(blocks) => {
+                    config.key_transition_period = blocks
+                }
+                AdminAction::SetAttestationValidity(blocks) => config.attestation_validity = blocks,
+                AdminAction::SetEpochLength(blocks) => config.epoch_length = blocks,
+                AdminAction::SetEpochEmission(amount) => config.epoch_emission = amount,
+                AdminAction::SetVestingCliff(blocks) => config.vesting_cliff = blocks,
+                AdminAction::SetVestingDuration(blocks) => config.vesting_duration = blocks,
+                AdminAction::SetDeletionGracePeriod(blocks) => {
+                    config.deletion_grace_period = blocks
+                }
+                AdminAction::SetDeletionAckWindow(blocks) => config.deletion_ack_window = blocks,
+                AdminAction::SetDeletionPenalty(penalty) => config.deletion_penalty = penalty,
+                AdminAction::SetBillingPeriod(blocks) => config.billing_period = blocks,
+                AdminAction::SetRentGracePeriod(blocks) => config.rent_grace_period = blocks,
+                AdminAction::SetChallengePeriod(blocks) => config.challenge_period = blocks,
+                AdminAction::SetMaxFailedAttempts(attempts) => {
+                    config.max_failed_attempts = attempts
+                }
+                AdminAction::SetUnlockDelay(blocks) => config.unlock_delay = blocks,
+                AdminAction::SetRecoveryCooldown(blocks) => config.recovery_cooldown = blocks,
+                AdminAction::SetRateLimit(limit) => config.rate_limit = limit,
+                AdminAction::SetRecoveryExpiry(blocks) => config.recovery_expiry = blocks,
+                AdminAction::Mint(to, amount) => self.mint(to, amount)?,
+                AdminAction::Burn(from, amount) => self.burn(from, amount)?,
+                AdminAction::WithdrawTreasury(to, amount) => self.withdraw_treasury(to, amount)?,
+                AdminAction::WithdrawNativeTreasury(to, amount) => {
+                    self.withdraw_native_treasury(to, amount)?
+                }
+                AdminAction::WithdrawFeeTokenTreasury(token, to, amount) => {
+                    self.withdraw_fee_token_treasury(token, to, amount)?
+                }
+                AdminAction::GrantRole(role, account) => self.grant_role(role, account),
+            }
+            self.config.set(&config);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn proposal(&self, proposal_id: ProposalId) -> Option<Proposal> {
+            self.proposals.get(proposal_id)
+        }
+
+        #[ink(message)]
+        pub fn voting_period(&self) -> BlockNumber {
+            self.get_config().voting_period
+        }
+
+        // token holders can change fees, the node stake minimum and the
+        // enclave and operator allowlists, code and admin changes stay with
+        // the council
+        #[ink(message)]
+        pub fn propose(&mut self, action: AdminAction) -> Result<ProposalId> {
+            self.ensure_not_paused()?;
+            let proposer = self.env().caller();
+            match &action {
+                AdminAction::AllowMeasurement(_)
+                | AdminAction::RevokeMeasurement(_)
+                | AdminAction::SetRecoveryFee(_)
+                | AdminAction::SetMinBalance(_)
+                | AdminAction::SetRegistrationFee(_)
+                | AdminAction::SetStorageFee(_)
+                | AdminAction::SetReferralReward(_)
+                | AdminAction::SetStarterGrant(_)
+                | AdminAction::SetFeeCurve(_)
+                | AdminAction::SetMinNodeStake(_)
+                | AdminAction::SetOperatorAllowlist(_)
+                | AdminAction::ApproveOperator(_)
+                | AdminAction::RevokeOperator(_) => {}
+                AdminAction::SetTreasuryBps(bps) => {
+                    if *bps > MAX_BPS {
+                        return Err(Error::InvalidBasisPoints)
+                    }
+                }
+                _ => return Err(Error::NotGovernable),
+            }
+            if self.balance_of(proposer) == 0 {
+                return Err(Error::InsufficientBalance)
+            }
+            let proposal_id = self.last_proposal_id.get().unwrap_or_default() + 1;
+            self.last_proposal_id.set(&proposal_id);
+            let voting_ends = self.env().block_number() + self.get_config().voting_period;
+            self.proposals.insert(proposal_id, &Proposal {
+                proposer,
+                action: action.clone(),
+                voting_ends,
+                votes_for: 0,
+                votes_against: 0,
+                executed: false,
+            });
+            self.env().emit_event(ProposalCreated {
+                proposal_id,
+                proposer,
+                action,
+                voting_ends,
+            });
+            Ok(proposal_id)
+        }
+
+        // the whole balance votes and stays locked until voting ends
+        #[ink(message)]
+        pub fn vote(&mut self, proposal_id: ProposalId, support: bool) -> Result<()> {
+            self.ensure_not_paused()?;
+            let voter = self.env().caller();
+            let mut p = self.proposals.get(proposal_id).ok_or(Error::ProposalNotFound)?;
+            if self.env().block_number() > p.voting_ends {
+                return Err(Error::VotingClosed)
+            }
+            if self.votes.contains((proposal_id, voter)) {
+                return Err(Error::AlreadyVoted)
+            }
+            let weight = self.balance_of(voter);
+            if weight == 0 {
+                return Err(Error::InsufficientBalance)
+            }
+            if support {
+                p.votes_for = safe_add(p.votes_for, weight)?;
+            } else {
+                p.votes_against = safe_add(p.votes_against, weight)?;
+            }
+            self.proposals.insert(proposal_id, &p);
+            self.votes.insert((proposal_id, voter), &());
+            let (locked, until) = self.vote_locks.get(voter).unwrap_or_default();
+            self.vote_locks.insert(voter, &(locked.max(weight), until.max(p.voting_ends)));
+            self.env().emit_event(VoteCast {
+                proposal_id,
+                voter,
+                support,
+                weight,
+            });
+            Ok(())
+        }
+
+        // tokens of an account that can not move because they voted
+        fn vote_locked(&self, account: AccountId) -> Balance {
+            match self.vote_locks.get(account) {
+                Some((locked, until)) if self.env().block_number() <= until => locked,
+                _ => 0,
+            }
+        }
+
+        // anyone can apply a proposal once voting is over, it needs more
+        // votes for than against and a quorum of the total supply voting for
+        #[ink(message)]
+        pub fn execute_proposal(&mut self, proposal_id: ProposalId) -> Result<()> {
+            let mut p = self.proposals.get(proposal_id).ok_or(Error::ProposalNotFound)?;
+            let now = self.env().block_number();
+            if now <= p.voting_ends {
+                return Err(Error::VotingOpen(p.voting_ends + 1 - now))
+            }
+            let quorum = self.total_supply * PROPOSAL_QUORUM_BPS as Balance / MAX_BPS as Balance;
+            if p.executed || p.votes_for <= p.votes_against || p.votes_for < quorum {
+                return Err(Error::ProposalNotPassed)
+            }
+            p.executed = true;
+            self.proposals.insert(proposal_id, &p);
+            self.apply_action(p.action)?;
+            self.env().emit_event(ProposalExecuted { proposal_id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn storage_version(&self) -> u32 {
+            self.storage_version.get().unwrap_or(1)
+        }
+
+        // move a batch of accounts from the version 1 layout to the current
+        // one, the account's node first and then its user, which ends up as
+        // secret 0. anyone may pay for a batch, the records come out the same
+        // whoever runs it. returns how many records were migrated so all
+        // accounts can be paged through over several calls
+        #[ink(message)]
+        pub fn migrate(&mut self, accounts: Vec<AccountId>) -> Result<u32> {
+            if self.storage_version() == STORAGE_VERSION {
+                return Err(Error::AlreadyMigrated)
+            }
+            let mut count = 0;
+            for account in accounts {
+                if self.migrate_legacy_node(account) {
+                    count += 1;
+                }
+                if self.migrate_legacy_user(account) {
+                    count += 1;
+                }
+            }
+            self.env().emit_event(RecordsMigrated { count });
+            Ok(count)
+        }
+
+        fn legacy_record<R: Storable>(root_key: Key, account: AccountId) -> Option<R> {
+            ink::env::get_contract_storage(&(root_key, account)).ok().flatten()
+        }
+
+        // until the migration completes, node records migrate did not write
+        // are still in the version 1 layout. no node can register before
+        fn is_legacy_node(&self, node: AccountId) -> bool {
+            self.storage_version() != STORAGE_VERSION
+                && self.nodes.contains(node)
+                && !self.migrated_nodes.contains(node)
+        }
+
+        // a record the nodes mapping could not read would break every message
+        // that loads the node, so one with a key that does not decode is
+        // moved to V1_STRANDED_NODES_KEY and one that does not decode at all
+        // is cleared. the operator can register again afterwards
+        fn migrate_legacy_node(&mut self, account: AccountId) -> bool {
+            if !self.is_legacy_node(account) {
+                return false
+            }
+            let legacy: LegacyNode = match Self::legacy_record(V1_NODES_KEY, account) {
+                Some(legacy) => legacy,
+                None => {
+                    ink::env::clear_contract_storage(&(V1_NODES_KEY, account));
+                    return false
+                }
+            };
+            let pub_k = match Self::decode_pub_key(&legacy.pub_k) {
+                Some(pub_k) => pub_k,
+                None => {
+                    ink::env::set_contract_storage(&(V1_STRANDED_NODES_KEY, account), &legacy);
+                    ink::env::clear_contract_storage(&(V1_NODES_KEY, account));
+                    return false
+                }
+            };
+            let now = self.env().block_number();
+            self.nodes.insert(account, &Node {
+                nid: account,
+                pub_k,
+                key_type: KeyType::Secp256k1,
+                exiting_since: None,
+                last_seen: now,
+                info: NodeInfo::default(),
+                previous_key: None,
+                attested_until: now + self.get_config().attestation_validity,
+            });
+            self.migrated_nodes.insert(account, &());
+            // blacklisted before its record was migrated, it stays out of the
+            // node list
+            if !self.blacklisted_nodes.contains(account) {
+                self.add_node_index(account);
+            }
+            true
+        }
+
+        // users with a condition the enum does not know, a key that does not
+        // decode or a guardian still in the legacy layout are left as they are
+        fn migrate_legacy_user(&mut self, account: AccountId) -> bool {
+            let legacy: LegacyUser = match Self::legacy_record(V1_USERS_KEY, account) {
+                Some(legacy) => legacy,
+                None => return false,
+            };
+            let guardians = match Self::legacy_guardians(&legacy) {
+                Ok(guardians) => guardians,
+                Err(_) => return false,
+            };
+            if guardians.iter().any(|g| self.is_legacy_node(g.node_id)) {
+                return false
+            }
+            let pub_k = match Self::decode_pub_key(&legacy.pub_k) {
+                Some(pub_k) => pub_k,
+                None => return false,
+            };
+            // attempts in flight are dropped, only the count survives
+            let r_times = Self::legacy_record::<LegacyRecovery>(V1_RECOVERIES_KEY, account)
+                .map(|r| r.r_times)
+                .unwrap_or_default();
+            let recovery = Recovery {
+                r_times,
+                ..Self::fresh_recovery(account, guardians.len())
+            };
+            // legacy guardians hold their shares already
+            let acknowledged = vec![true; guardians.len()];
+            let user = User {
+                uid: account,
+                pub_k,
+                guardians,
+                threshold: 2,
+                deletion_requested_at: None,
+                share_version: 0,
+                acknowledged,
+            };
+            self.assign_guardians(&user, 0);
+            self.save_user((account, 0), &user);
+            self.save_recovery((account, 0), &recovery);
+            ink::env::clear_contract_storage(&(V1_USERS_KEY, account));
+            ink::env::clear_contract_storage(&(V1_RECOVERIES_KEY, account));
+            true
+        }
+
+        fn legacy_guardians(legacy: &LegacyUser) -> Result<Vec<Guardian>> {
+            Ok(vec![
+                Guardian {
+                    node_id: legacy.node1_id,
+                    cond_type: legacy.node1_cond_type.try_into()?,
+                    cond_commitment: Hash::default(),
+                    share_commitment: Hash::default(),
+                    weight: 1,
+                },
+                Guardian {
+                    node_id: legacy.node2_id,
+                    cond_type: legacy.node2_cond_type.try_into()?,
+                    cond_commitment: Hash::default(),
+                    share_commitment: Hash::default(),
+                    weight: 1,
+                },
+                Guardian {
+                    node_id: legacy.node3_id,
+                    cond_type: legacy.node3_cond_type.try_into()?,
+                    cond_commitment: Hash::default(),
+                    share_commitment: Hash::default(),
+                    weight: 1,
+                },
+            ])
+        }
+
+        // called once migrate went through every account, marks the storage
+        // as fully on the current layout and lifts the pause storage of
+        // version 1 starts in
+        #[ink(message)]
+        pub fn complete_migration(&mut self) -> Result<()> {
+            if self.storage_version() == STORAGE_VERSION {
+                return Err(Error::AlreadyMigrated)
+            }
+            match self.owner.get() {
+                Some(_) => self.ensure_owner()?,
+                None => self.claim_legacy_ownership()?,
+            }
+            self.storage_version.set(&STORAGE_VERSION);
+            if self.paused.get().is_none() {
+                self.paused.set(&false);
+            }
+            self.env().emit_event(StorageMigrated { storage_version: STORAGE_VERSION });
+            Ok(())
+        }
+
+        // version 1 had no owner, its deployer was minted the whole supply.
+        // an account still holding most of it takes over as owner and
+        // council, like the constructor sets up a new contract
+        fn claim_legacy_ownership(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            if self.balance_of(caller) <= self.total_supply / 2 {
+                return Err(Error::NotOwner)
+            }
+            self.owner.set(&caller);
+            self.council.set(&vec![caller]);
+            self.council_quorum.set(&1);
+            Ok(())
+        }
+
+        // create new tokens for to, the supply only grows through an admin
+        // action so holders see it coming
+        fn mint(&mut self, to: AccountId, amount: Balance) -> Result<()> {
+            self.total_supply = safe_add(self.total_supply, amount)?;
+            self.credit(to, amount)?;
+            self.env().emit_event(Transfer {
+                from: None,
+                to: Some(to),
+                value: amount,
+            });
+            Ok(())
+        }
+
+        // destroy tokens held by from, through an admin action as well
+        fn burn(&mut self, from: AccountId, amount: Balance) -> Result<()> {
+            self.debit(from, amount)?;
+            self.total_supply = safe_sub(self.total_supply, amount)?;
+            self.env().emit_event(Transfer {
+                from: Some(from),
+                to: None,
+                value: amount,
+            });
+            Ok(())
+        }
+
+        // wrap the native tokens sent with the call into ledger tokens 1:1
+        #[ink(message, payable)]
+        pub fn deposit(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            let amount = self.env().transferred_value();
+            self.total_supply = safe_add(self.total_supply, amount)?;
+            self.native_deposits.set(&safe_add(self.native_deposits(), amount)?);
+            self.credit(caller, amount)?;
+            self.env().emit_event(Transfer {
+                from: None,
+                to: Some(caller),
+                value: amount,
+            });
+            Ok(())
+        }
+
+        // unwrap ledger tokens back into native tokens, only as much as was
+        // deposited can leave, minted tokens are not backed
+        #[ink(message)]
+        pub fn withdraw(&mut self, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.debit(caller, amount)?;
+            self.native_deposits.set(&safe_sub(self.native_deposits(), amount)?);
+            self.total_supply = safe_sub(self.total_supply, amount)?;
+            self.env().transfer(caller, amount).map_err(|_| Error::NativeTransferFailed)?;
+            self.env().emit_event(Transfer {
+                from: Some(caller),
+                to: None,
+                value: amount,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn native_deposits(&self) -> Balance {
+            self.native_deposits.get().unwrap_or_default()
+        }
+
+        // set tokens aside for starter grants, anyone may top the faucet up
+        #[ink(message)]
+        pub fn fund_faucet(&mut self, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.pay_in(caller, amount)?;
+            self.faucet.set(&safe_add(self.faucet_balance(), amount)?);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn faucet_balance(&self) -> Balance {
+            self.faucet.get().unwrap_or_default()
+        }
+
+        // new users get a small balance once, enough to pay for their first
+        // recovery
+        #[ink(message)]
+        pub fn claim_starter_grant(&mut self) -> Result<Balance> {
+            self.ensure_not_paused()?;
+            let caller = self.env().caller();
+            if self.starter_grants.contains(caller) {
+                return Err(Error::AlreadyClaimed)
+            }
+            let now = self.env().block_number();
+            let (block, claims) = self.faucet_claims.get().unwrap_or_default();
+            let claims = if block == now { claims } else { 0 };
+            if claims >= MAX_FAUCET_CLAIMS_PER_BLOCK {
+                return Err(Error::RateLimited(now + 1))
+            }
+            let grant = self.get_config().starter_grant;
+            if grant == 0 {
+                return Err(Error::FaucetEmpty)
+            }
+            let faucet = safe_sub(self.faucet_balance(), grant).map_err(|_| Error::FaucetEmpty)?;
+            self.faucet.set(&faucet);
+            self.faucet_claims.set(&(now, claims + 1));
+            self.starter_grants.insert(caller, &());
+            self.pay_out(caller, grant)?;
+            self.env().emit_event(StarterGrantClaimed { account: caller, amount: grant });
+            Ok(grant)
+        }
+
+        #[ink(message)]
+        pub fn claimed_starter_grant(&self, account: AccountId) -> bool {
+            self.starter_grants.contains(account)
+        }
+
+        fn approve_from_to(&mut self, owner: AccountId, spender: AccountId,
+            value: Balance,
+        ) {
+            self.allowances.insert((owner, spender), &value);
+            self.env().emit_event(Approval {
+                owner,
+                spender,
+                value,
+            });
+        }
+
+        fn transfer_from_to(&mut self, from: &AccountId,
+            to: &AccountId, value: Balance,
+        ) -> Result<()> {
+            self.ensure_not_paused()?;
+            self.debit(*from, value)?;
+            self.credit(*to, value)?;
+            self.env().emit_event(Transfer {
+                from: Some(*from),
+                to: Some(*to),
+                value,
+            });
+            Ok(())
+        }
+
+        // for new machines just install node app, call register_node to alert the chain.
+        // the node must hold min_node_stake tokens, they stay locked as its stake
+        #[ink(message)]
+        pub fn register_node(&mut self, pub_k: PublicKey, key_type: KeyType,
+            attestation_report: AttestationReport) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if self.nodes.contains(sender) {
+                return Err(Error::NodeAlreadyRegistered)
+            }
+            if self.session_nodes.contains(sender) {
+                return Err(Error::SessionKeyInUse)
+            }
+            if self.operator_allowlist() && !self.approved_operators.contains(sender) {
+                return Err(Error::OperatorNotApproved)
+            }
+            Self::ensure_compressed_key_prefix(&pub_k)?;
+            self.verify_attestation(&pub_k, &attestation_report)?;
+            let config = self.get_config();
+            let stake = config.min_node_stake;
+            self.pay_in(sender, stake)?;
+            self.stakes.insert(sender, &stake);
+            self.nodes.insert(sender, &Node {
+                nid: sender,
+                pub_k,
+                key_type,
+                exiting_since: None,
+                last_seen: self.env().block_number(),
+                info: NodeInfo::default(),
+                previous_key: None,
+                attested_until: self.env().block_number() + config.attestation_validity,
+            });
+            self.add_node_index(sender);
+            self.env().emit_event(NodeRegistered { node: sender, stake });
+            Ok(())
+        }
+
+        // for nodes whose enclave was re-provisioned. the new key needs an
+        // attestation just like at registration, the old one keeps being
+        // accepted for key_transition_period while users re-send their shares
+        #[ink(message)]
+        pub fn rotate_node_key(&mut self, new_pub_k: PublicKey,
+            proof: AttestationReport) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            if new_pub_k == node.pub_k {
+                return Err(Error::SameKey)
+            }
+            Self::ensure_compressed_key_prefix(&new_pub_k)?;
+            self.verify_attestation(&new_pub_k, &proof)?;
+            let config = self.get_config();
+            let valid_until = self.env().block_number() + config.key_transition_period;
+            let old_pub_k = core::mem::replace(&mut node.pub_k, new_pub_k);
+            node.previous_key = Some((old_pub_k, valid_until));
+            node.attested_until = self.env().block_number() + config.attestation_validity;
+            self.nodes.insert(sender, &node);
+            self.env().emit_event(NodeKeyRotated {
+                node: sender,
+                old_pub_k,
+                new_pub_k,
+                old_key_valid_until: valid_until,
+                assigned_users: self.assigned_count(sender),
+            });
+            Ok(())
+        }
+
+        // attestations lapse after attestation_validity, a node sends a fresh
+        // report for its current key to keep guarding and confirming
+        #[ink(message)]
+        pub fn renew_attestation(&mut self, report: AttestationReport) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            self.verify_attestation(&node.pub_k, &report)?;
+            node.attested_until = self.env().block_number() + self.get_config().attestation_validity;
+            self.nodes.insert(sender, &node);
+            self.env().emit_event(AttestationRenewed {
+                node: sender,
+                valid_until: node.attested_until,
+            });
+            Ok(())
+        }
+
+        // always true while attestation checks are off
+        #[ink(message)]
+        pub fn is_attested(&self, node: AccountId) -> bool {
+            match self.nodes.get(node) {
+                Some(_) if self.attestation_signer.get().flatten().is_none() => true,
+                Some(n) => self.env().block_number() < n.attested_until,
+                None => false
+            }
+        }
+
+        // the report must come from an allowed enclave and be signed by the
+        // attestation service for exactly this pub_k
+        fn verify_attestation(&self, pub_k: &PublicKey, report: &AttestationReport) -> Result<()> {
+            let signer = match self.attestation_signer.get().flatten() {
+                Some(signer) => signer,
+                None => return Ok(())
+            };
+            if !self.allowed_measurements.contains(report.measurement) {
+                return Err(Error::MeasurementNotAllowed)
+            }
+            let digest = Self::attestation_digest(&report.measurement, pub_k);
+            let recovered = self.env().ecdsa_recover(&report.signature, &digest)
+                .map_err(|_| Error::InvalidAttestation)?;
+            if recovered != signer {
+                return Err(Error::InvalidAttestation)
+            }
+            Ok(())
+        }
+
+        fn attestation_digest(measurement: &Hash, pub_k: &PublicKey) -> [u8; 32] {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(&(measurement, pub_k), &mut digest);
+            digest
+        }
+
+        fn allow_measurement(&mut self, measurement: Hash) {
+            self.allowed_measurements.insert(measurement, &());
+        }
+
+        fn revoke_measurement(&mut self, measurement: Hash) {
+            self.allowed_measurements.remove(measurement);
+        }
+
+        #[ink(message)]
+        pub fn is_measurement_allowed(&self, measurement: Hash) -> bool {
+            self.allowed_measurements.contains(measurement)
+        }
+
+        // revoking does not remove an already registered node, slash it for that
+        fn approve_operator(&mut self, operator: AccountId) {
+            self.approved_operators.insert(operator, &());
+        }
+
+        fn revoke_operator(&mut self, operator: AccountId) {
+            self.approved_operators.remove(operator);
+        }
+
+        #[ink(message)]
+        pub fn operator_allowlist(&self) -> bool {
+            self.operator_allowlist.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn is_operator_approved(&self, operator: AccountId) -> bool {
+            self.approved_operators.contains(operator)
+        }
+
+        // None turns attestation checks off, e.g. on dev chains without enclaves.
+        // this and the measurement allowlist are changed through queue_action
+        fn set_attestation_signer(&mut self, signer: Option<[u8; 33]>) {
+            self.attestation_signer.set(&signer);
+        }
+
+        // a node leaving the protocol stops taking new users right away, its
+        // stake is released after unbonding_period so users can migrate shares
+        #[ink(message)]
+        pub fn deregister_node(&mut self) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            // a blacklisted node keeps its stake bonded for slashing
+            if self.blacklisted_nodes.contains(sender) {
+                return Err(Error::NodeBlacklisted)
+            }
+            if node.exiting_since.is_some() {
+                return Err(Error::NodeExiting)
+            }
+            let now = self.env().block_number();
+            node.exiting_since = Some(now);
+            self.nodes.insert(sender, &node);
+            self.env().emit_event(NodeExiting {
+                node: sender,
+                release_block: now + self.get_config().unbonding_period,
+            });
+            Ok(())
+        }
+
+        // return the stake of an exited node and drop it from the registry
+        #[ink(message)]
+        pub fn withdraw_stake(&mut self) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            // also when it was blacklisted after it started leaving
+            if self.blacklisted_nodes.contains(sender) {
+                return Err(Error::NodeBlacklisted)
+            }
+            let exiting_since = node.exiting_since.ok_or(Error::NodeNotExiting)?;
+            if self.env().block_number() < exiting_since + self.get_config().unbonding_period {
+                return Err(Error::StakeLocked)
+            }
+            let stake = self.stake_of(sender);
+            self.pay_out(sender, stake)?;
+            self.stakes.remove(sender);
+            self.nodes.remove(sender);
+            self.remove_node_index(sender);
+            // the session key no longer acts for the node and is free again
+            if let Some(key) = self.session_keys.get(sender) {
+                self.session_nodes.remove(key);
+                self.session_keys.remove(sender);
+            }
+            self.env().emit_event(NodeDeregistered { node: sender, stake });
+            Ok(())
+        }
+
+        fn add_node_index(&mut self, node: AccountId) {
+            let node_count = self.node_count();
+            self.node_ids.insert(node_count, &node);
+            self.node_positions.insert(node, &node_count);
+            self.node_count.set(&(node_count + 1));
+        }
+
+        // swap the last indexed node into the removed node's slot
+        fn remove_node_index(&mut self, node: AccountId) {
+            if let Some(pos) = self.node_positions.get(node) {
+                let last = self.node_count() - 1;
+                if pos != last {
+                    if let Some(moved) = self.node_ids.get(last) {
+                        self.node_ids.insert(pos, &moved);
+                        self.node_positions.insert(moved, &pos);
+                    }
+                }
+                self.node_ids.remove(last);
+                self.node_positions.remove(node);
+                self.node_count.set(&last);
+            }
+        }
+
+        #[ink(message)]
+        pub fn update_node_info(&mut self, endpoint: String, region: String,
+            capacity: u32) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            Self::ensure_valid_endpoint(&endpoint)?;
+            Self::ensure_valid_region(&region)?;
+            node.info = NodeInfo {
+                endpoint,
+                region,
+                capacity,
+            };
+            self.nodes.insert(sender, &node);
+            self.env().emit_event(NodeInfoUpdated { node: sender, info: node.info });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_node(&self, node: AccountId) -> Option<NodeView> {
+            let n = self.nodes.get(node)?;
+            Some(NodeView {
+                pub_k: n.pub_k,
+                key_type: n.key_type,
+                stake: self.stake_of(node),
+                effective_stake: self.effective_stake(node),
+                info: n.info,
+                last_seen: n.last_seen,
+                alive: self.is_node_alive(node),
+                exiting_since: n.exiting_since,
+                blacklisted: self.is_blacklisted(node),
+                assigned: self.assigned_count(node),
+                previous_key: n.previous_key,
+                attested_until: n.attested_until,
+            })
+        }
+
+        #[ink(message)]
+        pub fn node_info(&self, node: AccountId) -> Option<NodeInfo> {
+            self.nodes.get(node).map(|n| n.info)
+        }
+
+        // how many more user secrets the node takes, u32::MAX without a limit
+        #[ink(message)]
+        pub fn free_capacity(&self, node: AccountId) -> u32 {
+            match self.nodes.get(node) {
+                Some(n) if n.info.capacity == 0 => u32::MAX,
+                Some(n) => n.info.capacity.saturating_sub(self.assigned_count(node)),
+                None => 0
+            }
+        }
+
+        // candidate guardians for wallets, live nodes that can guard new users
+        // in the region, if given, with at least min_free_capacity room left
+        #[ink(message)]
+        pub fn find_nodes(&self, region: Option<String>, min_free_capacity: u32,
+            limit: u32) -> Vec<AccountId> {
+            (0..self.node_count())
+                .filter_map(|i| self.node_ids.get(i))
+                .filter(|n| self.is_node_alive(*n) && self.ensure_eligible_guardian(*n).is_ok())
+                .filter(|n| match &region {
+                    Some(region) => self.nodes.get(n).map_or(false, |node| &node.info.region == region),
+                    None => true
+                })
+                .filter(|n| self.free_capacity(*n) >= min_free_capacity)
+                .take(limit as usize)
+                .collect()
+        }
+
+        // let a hot key call finish_recovery and heartbeat for the node, so
+        // the staked key can stay offline. None removes the key
+        #[ink(message)]
+        pub fn set_session_key(&mut self, key: Option<AccountId>) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.nodes.contains(sender) {
+                return Err(Error::NodeNotRegistered)
+            }
+            if let Some(key) = key {
+                if self.nodes.contains(key) || self.session_nodes.contains(key) {
+                    return Err(Error::SessionKeyInUse)
+                }
+            }
+            if let Some(old) = self.session_keys.get(sender) {
+                self.session_nodes.remove(old);
+                self.session_keys.remove(sender);
+            }
+            if let Some(key) = key {
+                self.session_keys.insert(sender, &key);
+                self.session_nodes.insert(key, &sender);
+            }
+            self.env().emit_event(SessionKeySet { node: sender, key });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn session_key(&self, node: AccountId) -> Option<AccountId> {
+            self.session_keys.get(node)
+        }
+
+        // the node the caller acts for, itself unless it is a session key
+        fn calling_node(&self) -> AccountId {
+            let caller = self.env().caller();
+            self.session_nodes.get(caller).unwrap_or(caller)
+        }
+
+        // nodes call heartbeat periodically to show they are still online
+        #[ink(message)]
+        pub fn heartbeat(&mut self) -> Result<()> {
+            let sender = self.calling_node();
+            let mut node = self.nodes.get(sender).ok_or(Error::NodeNotRegistered)?;
+            let now = self.env().block_number();
+            // one rewarded heartbeat per liveness window, more do not pay
+            let window = self.get_config().liveness_window.max(1);
+            let new_window = now / window != node.last_seen / window;
+            node.last_seen = now;
+            self.nodes.insert(sender, &node);
+            // nodes on their way out still report in, but earn nothing
+            if node.exiting_since.is_some() || self.blacklisted_nodes.contains(sender) {
+                return Ok(())
+            }
+            if new_window {
+                self.add_reward_points(sender, 1);
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_node_alive(&self, node: AccountId) -> bool {
+            match self.nodes.get(node) {
+                Some(n) => self.env().block_number() - n.last_seen <= self.get_config().liveness_window,
+                None => false
+            }
+        }
+
+        #[ink(message)]
+        pub fn liveness_window(&self) -> BlockNumber {
+            self.get_config().liveness_window
+        }
+
+        #[ink(message)]
+        pub fn unbonding_period(&self) -> BlockNumber {
+            self.get_config().unbonding_period
+        }
+
+        #[ink(message)]
+        pub fn key_transition_period(&self) -> BlockNumber {
+            self.get_config().key_transition_period
+        }
+
+        #[ink(message)]
+        pub fn attestation_validity(&self) -> BlockNumber {
+            self.get_config().attestation_validity
+        }
+
+        #[ink(message)]
+        pub fn stake_of(&self, node: AccountId) -> Balance {
+            self.stakes.get(node).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn min_node_stake(&self) -> Balance {
+            self.get_config().min_node_stake
+        }
+
+        // confiscate amount of a node's stake, when it failed to serve shares
+        // or submitted invalid recovery proofs. evidence is kept in the event
+        #[ink(message)]
+        pub fn slash_node(&mut self, node: AccountId, amount: Balance,
+            evidence: Vec<u8>) -> Result<()> {
+            self.ensure_role(Role::Slasher)?;
+            Self::ensure_max_len(evidence.len(), MAX_EVIDENCE_LEN)?;
+            self.slash(node, amount, evidence)
+        }
+
+        fn slash(&mut self, node: AccountId, amount: Balance, evidence: Vec<u8>) -> Result<()> {
+            if !self.nodes.contains(node) {
+                return Err(Error::NodeNotRegistered)
+            }
+            let stake = self.stake_of(node).checked_sub(amount).ok_or(Error::InsufficientStake)?;
+            self.stakes.insert(node, &stake);
+            self.treasury.set(&safe_add(self.treasury_balance(), amount)?);
+            let mut rep = self.reputation(node);
+            rep.slashes += 1;
+            self.reputations.insert(node, &rep);
+            self.env().emit_event(NodeSlashed { node, amount, evidence });
+            Ok(())
+        }
+
+        // anyone can file evidence against a node, e.g. a share it served that
+        // fails verification. a slasher checks it with resolve_report
+        #[ink(message)]
+        pub fn report_node(&mut self, node: AccountId, evidence: Vec<u8>) -> Result<ReportId> {
+            self.ensure_not_paused()?;
+            if !self.nodes.contains(node) {
+                return Err(Error::NodeNotRegistered)
+            }
+            Self::ensure_max_len(evidence.len(), MAX_EVIDENCE_LEN)?;
+            let reporter = self.env().caller();
+            let report_id = self.last_report_id.get().unwrap_or_default() + 1;
+            self.last_report_id.set(&report_id);
+            self.reports.insert(report_id, &FraudReport {
+                reporter,
+                node,
+                evidence,
+                filed_at: self.env().block_number(),
+            });
+            self.env().emit_event(NodeReported { report_id, reporter, node });
+            Ok(report_id)
+        }
+
+        #[ink(message)]
+        pub fn report(&self, report_id: ReportId) -> Option<FraudReport> {
+            self.reports.get(report_id)
+        }
+
+        // an amount of 0 dismisses the report. otherwise the node is slashed
+        // and the reporter gets REPORTER_REWARD_BPS of it, the rest stays in
+        // the treasury
+        #[ink(message)]
+        pub fn resolve_report(&mut self, report_id: ReportId, amount: Balance) -> Result<()> {
+            self.ensure_role(Role::Slasher)?;
+            let report = self.reports.get(report_id).ok_or(Error::ReportNotFound)?;
+            let mut reward = 0;
+            if amount > 0 {
+                self.slash(report.node, amount, format!("report {}", report_id).into_bytes())?;
+                reward = amount * REPORTER_REWARD_BPS as Balance / MAX_BPS as Balance;
+                self.treasury.set(&safe_sub(self.treasury_balance(), reward)?);
+                self.pay_out(report.reporter, reward)?;
+            }
+            self.reports.remove(report_id);
+            self.env().emit_event(ReportResolved { report_id, slashed: amount, reward });
+            Ok(())
+        }
+
+        // eject a misbehaving node: it is dropped from the node list, takes no
+        // new users and can not confirm recoveries. its users have to run
+        // replace_guardian, assigned_users lists them
+        #[ink(message)]
+        pub fn blacklist_node(&mut self, node: AccountId) -> Result<()> {
+            self.ensure_role(Role::Slasher)?;
+            if !self.nodes.contains(node) {
+                return Err(Error::NodeNotRegistered)
+            }
+            if self.blacklisted_nodes.contains(node) {
+                return Err(Error::NodeBlacklisted)
+            }
+            self.blacklisted_nodes.insert(node, &());
+            self.remove_node_index(node);
+            self.env().emit_event(NodeBlacklisted {
+                node,
+                assigned_users: self.assigned_count(node),
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_blacklisted(&self, node: AccountId) -> bool {
+            self.blacklisted_nodes.contains(node)
+        }
+
+        #[ink(message)]
+        pub fn assigned_count(&self, node: AccountId) -> u32 {
+            self.assigned_counts.get(node).unwrap_or_default()
+        }
+
+        // page through the (user, secret_id) pairs a node guards
+        #[ink(message)]
+        pub fn assigned_users(&self, node: AccountId, offset: u32,
+            limit: u32) -> Vec<(AccountId, SecretId)> {
+            let end = offset.saturating_add(limit).min(self.assigned_count(node));
+            (offset..end).filter_map(|i| self.assigned_users.get((node, i))).collect()
+        }
+
+        fn assign(&mut self, node: AccountId, user: AccountId, secret_id: SecretId) {
+            let pos = self.assigned_count(node);
+            self.assigned_users.insert((node, pos), &(user, secret_id));
+            self.assigned_positions.insert((node, user, secret_id), &pos);
+            self.assigned_counts.insert(node, &(pos + 1));
+            // a node that guards the secret again keeps its share, it is no
+            // longer asked to delete it
+            self.deletion_deadlines.remove((node, user, secret_id));
+        }
+
+        // swap the node's last assignment into the removed one's slot
+        fn unassign(&mut self, node: AccountId, user: AccountId, secret_id: SecretId) {
+            if let Some(pos) = self.assigned_positions.get((node, user, secret_id)) {
+                let last = self.assigned_count(node) - 1;
+                if pos != last {
+                    if let Some(moved) = self.assigned_users.get((node, last)) {
+                        self.assigned_users.insert((node, pos), &moved);
+                        self.assigned_positions.insert((node, moved.0, moved.1), &pos);
+                    }
+                }
+                self.assigned_users.remove((node, last));
+                self.assigned_positions.remove((node, user, secret_id));
+                self.assigned_counts.insert(node, &last);
+            }
+        }
+
+        fn assign_guardians(&mut self, u: &User, secret_id: SecretId) {
+            for g in u.guardians.iter() {
+                self.assign(g.node_id, u.uid, secret_id);
+            }
+        }
+
+        fn unassign_guardians(&mut self, u: &User, secret_id: SecretId) {
+            for g in u.guardians.iter() {
+                self.unassign(g.node_id, u.uid, secret_id);
+            }
+        }
+
+        #[ink(message)]
+        pub fn current_epoch(&self) -> u32 {
+            self.env().block_number() / self.get_config().epoch_length.max(1)
+        }
+
+        #[ink(message)]
+        pub fn epoch_length(&self) -> BlockNumber {
+            self.get_config().epoch_length
+        }
+
+        #[ink(message)]
+        pub fn epoch_emission(&self) -> Balance {
+            self.get_config().epoch_emission
+        }
+
+        #[ink(message)]
+        pub fn reward_pool(&self) -> Balance {
+            self.reward_pool.get().unwrap_or_default()
+        }
+
+        // move tokens of the caller into the pool node rewards are paid from
+        #[ink(message)]
+        pub fn fund_rewards(&mut self, amount: Balance) -> Result<()> {
+            let from = self.env().caller();
+            self.pay_in(from, amount)?;
+            self.reward_pool.set(&safe_add(self.reward_pool(), amount)?);
+            self.env().emit_event(RewardsFunded { from, amount });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn reward_points(&self, node: AccountId, epoch: u32) -> u32 {
+            self.epoch_points.get((epoch, node)).unwrap_or_default()
+        }
+
+        fn add_reward_points(&mut self, node: AccountId, points: u32) {
+            let epoch = self.current_epoch();
+            let node_points = self.reward_points(node, epoch);
+            self.epoch_points.insert((epoch, node), &(node_points + points));
+            let total = self.epoch_total_points.get(epoch).unwrap_or_default();
+            self.epoch_total_points.insert(epoch, &(total + points));
+        }
+
+        // vest the caller its share of the previous epoch's emission, by its
+        // heartbeats and served recoveries in that epoch. points of older
+        // epochs can no longer be claimed
+        #[ink(message)]
+        pub fn claim_rewards(&mut self) -> Result<Balance> {
+            self.ensure_not_paused()?;
+            let node = self.env().caller();
+            let epoch = self.current_epoch().checked_sub(1).ok_or(Error::NoRewards)?;
+            let points = self.reward_points(node, epoch);
+            if points == 0 {
+                return Err(Error::NoRewards)
+            }
+            let emission = match self.epoch_rewards.get(epoch) {
+                Some(emission) => emission,
+                None => {
+                    let pool = self.reward_pool();
+                    let emission = self.get_config().epoch_emission.min(pool);
+                    self.reward_pool.set(&(pool - emission));
+                    self.epoch_rewards.insert(epoch, &emission);
+                    emission
+                }
+            };
+            let total = self.epoch_total_points.get(epoch).unwrap_or_default();
+            let reward = emission * points as Balance / total as Balance;
+            self.epoch_points.remove((epoch, node));
+            let amount = safe_sub(reward, self.share_with_delegators(node, reward)?)?;
+            self.add_vesting(node, amount);
+            self.env().emit_event(RewardsClaimed { node, epoch, amount });
+            Ok(amount)
+        }
+
+        fn add_vesting(&mut self, node: AccountId, amount: Balance) {
+            if amount == 0 {
+                return
+            }
+            let (first, next) = self.vesting_indices.get(node).unwrap_or_default();
+            let config = self.get_config();
+            self.vesting_schedules.insert((node, next), &VestingSchedule {
+                total: amount,
+                claimed: 0,
+                start: self.env().block_number(),
+                cliff: config.vesting_cliff,
+                duration: config.vesting_duration,
+            });
+            self.vesting_indices.insert(node, &(first, next + 1));
+        }
+
+        // pay out everything vested so far, fully claimed schedules are removed
+        #[ink(message)]
+        pub fn claim_vested(&mut self) -> Result<Balance> {
+            self.ensure_not_paused()?;
+            let node = self.env().caller();
+            let now = self.env().block_number();
+            let (mut first, next) = self.vesting_indices.get(node).unwrap_or_default();
+            let mut amount = 0;
+            for i in first..next {
+                let mut v = match self.vesting_schedules.get((node, i)) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let vested = v.vested(now)?;
+                amount = safe_add(amount, vested - v.claimed)?;
+                v.claimed = vested;
+                if v.claimed == v.total {
+                    self.vesting_schedules.remove((node, i));
+                    if i == first {
+                        first += 1;
+                    }
+                } else {
+                    self.vesting_schedules.insert((node, i), &v);
+                }
+            }
+            if amount == 0 {
+                return Err(Error::NoRewards)
+            }
+            self.vesting_indices.insert(node, &(first, next));
+            self.pay_out(node, amount)?;
+            self.env().emit_event(VestedClaimed { node, amount });
+            Ok(amount)
+        }
+
+        #[ink(message)]
+        pub fn vesting_schedules(&self, node: AccountId) -> Vec<VestingSchedule> {
+            let (first, next) = self.vesting_indices.get(node).unwrap_or_default();
+            (first..next).filter_map(|i| self.vesting_schedules.get((node, i))).collect()
+        }
+
+        // what claim_vested would pay now
+        #[ink(message)]
+        pub fn vested_balance(&self, node: AccountId) -> Balance {
+            let now = self.env().block_number();
+            // a schedule that overflows shows nothing, claim_vested fails on it
+            self.vesting_schedules(node).iter()
+                .map(|v| v.vested(now).map_or(0, |vested| vested - v.claimed))
+                .sum()
+        }
+
+        #[ink(message)]
+        pub fn vesting_cliff(&self) -> BlockNumber {
+            self.get_config().vesting_cliff
+        }
+
+        #[ink(message)]
+        pub fn vesting_duration(&self) -> BlockNumber {
+            self.get_config().vesting_duration
+        }
+
+        #[ink(message)]
+        pub fn effective_stake(&self, node: AccountId) -> Balance {
+            self.stake_of(node) + self.delegated_stake(node)
+        }
+
+        #[ink(message)]
+        pub fn delegated_stake(&self, node: AccountId) -> Balance {
+            self.delegated_stakes.get(node).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn delegation(&self, node: AccountId, delegator: AccountId) -> Delegation {
+            let mut d = self.delegations.get((node, delegator)).unwrap_or_default();
+            // d stays as last settled on Overflow, claim_delegation_rewards
+            // reports it
+            self.settle_delegation(node, &mut d).unwrap_or_default();
+            d
+        }
+
+        #[ink(message)]
+        pub fn commission(&self, node: AccountId) -> u16 {
+            self.commissions.get(node).unwrap_or_default()
+        }
+
+        // only applies to rewards claimed after the change
+        #[ink(message)]
+        pub fn set_commission(&mut self, bps: u16) -> Result<()> {
+            let node = self.env().caller();
+            if !self.nodes.contains(node) {
+                return Err(Error::NodeNotRegistered)
+            }
+            if bps > MAX_BPS {
+                return Err(Error::InvalidBasisPoints)
+            }
+            self.commissions.insert(node, &bps);
+            self.env().emit_event(CommissionSet { node, bps });
+            Ok(())
+        }
+
+        // move rewards earned since the last settlement into pending_rewards,
+        // d is left as it was on Overflow
+        fn settle_delegation(&self, node: AccountId, d: &mut Delegation) -> Result<()> {
+            let per_share = self.reward_per_share.get(node).unwrap_or_default();
+            let earned = d.amount.checked_mul(per_share - d.reward_per_share_paid)
+                .ok_or(Error::Overflow)? / REWARD_PER_SHARE_SCALE;
+            d.pending_rewards = safe_add(d.pending_rewards, earned)?;
+            d.reward_per_share_paid = per_share;
+            Ok(())
+        }
+
+        // delegators get their stake's part of a node reward, minus the node's
+        // commission. returns what goes to the delegators
+        fn share_with_delegators(&mut self, node: AccountId, reward: Balance) -> Result<Balance> {
+            let delegated = self.delegated_stake(node);
+            if delegated == 0 {
+                return Ok(0)
+            }
+            let stake_share = reward.checked_mul(delegated).ok_or(Error::Overflow)?
+                / self.effective_stake(node);
+            let commission = stake_share.checked_mul(self.commission(node) as Balance)
+                .ok_or(Error::Overflow)? / MAX_BPS as Balance;
+            let share = stake_share - commission;
+            let per_share = self.reward_per_share.get(node).unwrap_or_default();
+            let added = share.checked_mul(REWARD_PER_SHARE_SCALE).ok_or(Error::Overflow)? / delegated;
+            self.reward_per_share.insert(node, &safe_add(per_share, added)?);
+            Ok(share)
+        }
+
+        #[ink(message)]
+        pub fn delegate(&mut self, node: AccountId, amount: Balance) -> Result<()> {
+            self.ensure_not_paused()?;
+            let delegator = self.env().caller();
+            if !self.nodes.contains(node) {
+                return Err(Error::NodeNotRegistered)
+            }
+            let mut d = self.delegations.get((node, delegator)).unwrap_or_default();
+            self.settle_delegation(node, &mut d)?;
+            d.amount = safe_add(d.amount, amount)?;
+            self.delegations.insert((node, delegator), &d);
+            self.delegated_stakes.insert(node, &safe_add(self.delegated_stake(node), amount)?);
+            self.pay_in(delegator, amount)?;
+            self.env().emit_event(Delegated { delegator, node, amount });
+            Ok(())
+        }
+
+        // also works after the node left, so delegators can always get out
+        #[ink(message)]
+        pub fn undelegate(&mut self, node: AccountId, amount: Balance) -> Result<()> {
+            self.ensure_not_paused()?;
+            let delegator = self.env().caller();
+            let mut d = self.delegations.get((node, delegator)).unwrap_or_default();
+            if d.amount < amount {
+                return Err(Error::InsufficientDelegation)
+            }
+            self.settle_delegation(node, &mut d)?;
+            d.amount -= amount;
+            self.delegations.insert((node, delegator), &d);
+            self.delegated_stakes.insert(node, &safe_sub(self.delegated_stake(node), amount)?);
+            self.pay_out(delegator, amount)?;
+            self.env().emit_event(Undelegated { delegator, node, amount });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn claim_delegation_rewards(&mut self, node: AccountId) -> Result<Balance> {
+            self.ensure_not_paused()?;
+            let delegator = self.env().caller();
+            let mut d = self.delegations.get((node, delegator)).unwrap_or_default();
+            self.settle_delegation(node, &mut d)?;
+            let amount = d.pending_rewards;
+            if amount == 0 {
+                return Err(Error::NoRewards)
+            }
+            d.pending_rewards = 0;
+            self.delegations.insert((node, delegator), &d);
+            self.pay_out(delegator, amount)?;
+            self.env().emit_event(DelegationRewardsClaimed { delegator, node, amount });
+            Ok(amount)
+        }
+
+        #[ink(message)]
+        pub fn reputation(&self, node: AccountId) -> Reputation {
+            self.reputations.get(node).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn treasury_balance(&self) -> Balance {
+            self.treasury.get().unwrap_or_default()
+        }
+
+        fn withdraw_treasury(&mut self, to: AccountId, amount: Balance) -> Result<()> {
+            self.treasury.set(&safe_sub(self.treasury_balance(), amount)?);
+            self.pay_out(to, amount)?;
+            self.env().emit_event(TreasuryWithdrawn { to, amount });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn native_treasury(&self) -> Balance {
+            self.native_treasury.get().unwrap_or_default()
+        }
+
+        // pays out native tokens collected with registrations
+        fn withdraw_native_treasury(&mut self, to: AccountId, amount: Balance) -> Result<()> {
+            self.native_treasury.set(&safe_sub(self.native_treasury(), amount)?);
+            self.env().transfer(to, amount).map_err(|_| Error::NativeTransferFailed)?;
+            self.env().emit_event(NativeTreasuryWithdrawn { to, amount });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn fee_token(&self) -> Option<AccountId> {
+            self.fee_token.get().flatten()
+        }
+
+        #[ink(message)]
+        pub fn fee_token_treasury(&self, token: AccountId) -> Balance {
+            self.fee_token_treasuries.get(token).unwrap_or_default()
+        }
+
+        // pays out the treasury cut of recovery fees charged in token
+        fn withdraw_fee_token_treasury(&mut self, token: AccountId, to: AccountId,
+            amount: Balance) -> Result<()> {
+            let held = safe_sub(self.fee_token_treasury(token), amount)?;
+            self.fee_token_treasuries.insert(token, &held);
+            self.pay_fee(Some(token), to, amount)?;
+            self.env().emit_event(FeeTokenTreasuryWithdrawn { token, to, amount });
+            Ok(())
+        }
+
+        // recovery fees move through the built in ledger, or with a fee token
+        // through PSP22 calls on it. collecting needs an allowance from `from`
+        fn collect_fee(&mut self, token: Option<AccountId>, from: AccountId,
+            amount: Balance) -> Result<()> {
+            let token = match token {
+                Some(token) => token,
+                None => return self.pay_in(from, amount),
+            };
+            if amount == 0 {
+                return Ok(())
+            }
+            build_call::<Environment>()
+                .call_type(Call::new().callee(token))
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("PSP22::transfer_from")))
+                        .push_arg(from)
+                        .push_arg(self.env().account_id())
+                        .push_arg(amount)
+                        .push_arg(Vec::<u8>::new()))
+                .returns::<PSP22Result<()>>()
+                .fire()
+                .map_err(|_| Error::FeeTransferFailed)?
+                .map_err(|_| Error::FeeTransferFailed)
+        }
+
+        fn pay_fee(&mut self, token: Option<AccountId>, to: AccountId,
+            amount: Balance) -> Result<()> {
+            let token = match token {
+                Some(token) => token,
+                None => return self.pay_out(to, amount),
+            };
+            if amount == 0 {
+                return Ok(())
+            }
+            build_call::<Environment>()
+                .call_type(Call::new().callee(token))
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("PSP22::transfer")))
+                        .push_arg(to)
+                        .push_arg(amount)
+                        .push_arg(Vec::<u8>::new()))
+                .returns::<PSP22Result<()>>()
+                .fire()
+                .map_err(|_| Error::FeeTransferFailed)?
+                .map_err(|_| Error::FeeTransferFailed)
+        }
+
+        #[ink(message)]
+        pub fn verify_node(&mut self, pub_k: PublicKey) -> bool {
+            let sender = self.env().caller();
+            let node = self.nodes.get(sender);
+            match node {
+                Some(n) => n.pub_k == pub_k,
+                None => false
+            }
+        }
+
+        #[ink(message)]
+        pub fn node_count(&self) -> u32 {
+            self.node_count.get().unwrap_or_default()
+        }
+
+        // get a page of registered nodes, nodes that left are swapped out so
+        // the order only follows registration until the first removal
+        #[ink(message)]
+        pub fn get_nodes(&self, offset: u32, limit: u32) -> Vec<AccountId> {
+            let end = offset.saturating_add(limit).min(self.node_count());
+            (offset..end).filter_map(|i| self.node_ids.get(i)).collect()
+        }
+
+        // for new user, propose the registration while handing the secret
+        // shares to the guardian nodes, each of them calls ack_share_stored
+        // once it stored its share. threshold is how many of the guardians
+        // must confirm before a recovery is finished. each secret_id is
+        // registered and recovered on its own, with its own guardians.
+        // the registration fee is paid in native tokens with the call, anything
+        // sent above it is kept as well. a referrer named on the first
+        // registration of an account is rewarded once the guardians acked.
+        #[ink(message, payable)]
+        pub fn propose_registration(&mut self, secret_id: SecretId, pub_k: PublicKey,
+            guardians: Vec<Guardian>, threshold: u32,
+            referrer: Option<AccountId>) -> Result<()> {
+            self.ensure_not_paused()?;
+            let paid = self.env().transferred_value();
+            if paid < self.get_config().registration_fee {
+                return Err(Error::RegistrationFeeTooLow)
+            }
+            let sender = self.env().caller();
+            if referrer == Some(sender) {
+                return Err(Error::SelfReferral)
+            }
+            self.register(secret_id, pub_k, guardians, threshold)?;
+            self.native_treasury.set(&safe_add(self.native_treasury(), paid)?);
+            if let Some(referrer) = referrer {
+                if !self.referrals.contains(sender) {
+                    self.referrals.insert(sender, &(referrer, false));
+                }
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn referrer_of(&self, user: AccountId) -> Option<AccountId> {
+            self.referrals.get(user).map(|(referrer, _)| referrer)
+        }
+
+        #[ink(message)]
+        pub fn referral_reward(&self) -> Balance {
+            self.get_config().referral_reward
+        }
+
+        // paid from the treasury the first time a referred account becomes
+        // active, nothing is paid while the treasury can not cover it
+        fn reward_referrer(&mut self, user: AccountId) -> Result<()> {
+            let (referrer, rewarded) = match self.referrals.get(user) {
+                Some(referral) => referral,
+                None => return Ok(())
+            };
+            let amount = self.get_config().referral_reward;
+            if rewarded || amount == 0 || self.treasury_balance() < amount {
+                return Ok(())
+            }
+            self.referrals.insert(user, &(referrer, true));
+            self.treasury.set(&safe_sub(self.treasury_balance(), amount)?);
+            self.pay_out(referrer, amount)?;
+            self.env().emit_event(ReferralRewarded { referrer, user, amount });
+            Ok(())
+        }
+
+        // like propose_registration, but the contract picks a live, eligible
+        // node for each of the conditions, usually three, so clients can not
+        // bias the choice. the user hands the returned nodes their shares afterwards,
+        // the commitments are recorded as the nodes acknowledge them
+        #[ink(message, payable)]
+        pub fn register_user_auto(&mut self, secret_id: SecretId, pub_k: PublicKey,
+            conditions: Vec<(ConditionType, Hash)>, threshold: u32) -> Result<Vec<AccountId>> {
+            self.ensure_not_paused()?;
+            let paid = self.env().transferred_value();
+            if paid < self.get_config().registration_fee {
+                return Err(Error::RegistrationFeeTooLow)
+            }
+            let nodes = self.pick_guardians(conditions.len())?;
+            let guardians = nodes.iter().zip(conditions)
+                .map(|(node_id, (cond_type, cond_commitment))| Guardian {
+                    node_id: *node_id,
+                    cond_type,
+                    cond_commitment,
+                    share_commitment: Hash::default(),
+                    weight: 1,
+                })
+                .collect();
+            self.register(secret_id, pub_k, guardians, threshold)?;
+            self.native_treasury.set(&safe_add(self.native_treasury(), paid)?);
+            Ok(nodes)
+        }
+
+        // draws count distinct nodes out of the live and eligible ones. the
+        // seed mixes block data with a per user counter, good enough against
+        // clients picking their own nodes but not against block producers
+        fn pick_guardians(&mut self, count: usize) -> Result<Vec<AccountId>> {
+            let mut candidates: Vec<AccountId> = (0..self.node_count())
+                .filter_map(|i| self.node_ids.get(i))
+                .filter(|n| self.is_node_alive(*n) && self.ensure_eligible_guardian(*n).is_ok())
+                .filter(|n| self.ensure_free_capacity(*n).is_ok())
+                .collect();
+            if candidates.len() < count {
+                return Err(Error::NotEnoughNodes)
+            }
+            let caller = self.env().caller();
+            let nonce = self.assignment_nonces.get(caller).unwrap_or_default();
+            self.assignment_nonces.insert(caller, &(nonce + 1));
+            let seed = (self.env().block_number(), self.env().block_timestamp(), caller, nonce);
+            for i in 0..count {
+                let mut draw = <Blake2x256 as HashOutput>::Type::default();
+                ink::env::hash_encoded::<Blake2x256, _>(&(seed, i as u32), &mut draw);
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&draw[..8]);
+                let j = i + (u64::from_le_bytes(bytes) % (candidates.len() - i) as u64) as usize;
+                candidates.swap(i, j);
+            }
+            candidates.truncate(count);
+            Ok(candidates)
+        }
+
+        fn register(&mut self, secret_id: SecretId, pub_k: PublicKey,
+            guardians: Vec<Guardian>, threshold: u32) -> Result<()> {
+            Self::ensure_compressed_key_prefix(&pub_k)?;
+            Self::ensure_valid_quorum(&guardians, threshold)?;
+            Self::ensure_distinct_guardians(&guardians)?;
+            let sender = self.env().caller();
+            if self.reshares.contains((sender, secret_id)) {
+                return Err(Error::ResharePending)
+            }
+            // the running attempt holds an escrowed fee and confirmations for
+            // the current guardians
+            let recovery = self.recoveries.get((sender, secret_id));
+            if recovery.as_ref().map_or(false, Self::is_active) {
+                return Err(Error::RecoveryInProgress)
+            }
+            // a lockout or a guardian freeze is not lifted by registering again
+            if recovery.as_ref().map_or(false, |r| r.locked) {
+                return Err(Error::RecoveryLocked)
+            }
+            // registering again re-distributes the shares
+            let previous = self.users.get((sender, secret_id));
+            for g in guardians.iter() {
+                self.ensure_eligible_guardian(g.node_id)?;
+                // a node already guarding this secret keeps its slot
+                let kept = previous.as_ref()
+                    .map_or(false, |u| u.guardians.iter().any(|p| p.node_id == g.node_id));
+                if !kept {
+                    self.ensure_free_capacity(g.node_id)?;
+                }
+            }
+            let share_version = previous.as_ref()
+                .map(|u| u.share_version + 1)
+                .unwrap_or_default();
+            if let Some(u) = previous {
+                self.unassign_guardians(&u, secret_id);
+                self.request_dropped_share_deletion(sender, secret_id, &u.guardians, &guardians);
+            }
+            let guardian_count = guardians.len();
+            let guardian_ids = guardians.iter().map(|g| g.node_id).collect();
+            let user = User {
+                uid: sender,
+                pub_k: pub_k,
+                guardians: guardians,
+                threshold: threshold,
+                deletion_requested_at: None,
+                share_version,
+                acknowledged: vec![false; guardian_count],
+            };
+            self.assign_guardians(&user, secret_id);
+            self.save_user((sender, secret_id), &user);
+            // the recovery record outlives a re-registration, with its history
+            // count and cooldown. only the confirmations follow the new
+            // guardians
+            let recovery = match recovery {
+                Some(r) => Recovery {
+                    confirmations: vec![Confirmation::default(); guardian_count],
+                    ..r
+                },
+                None => Self::fresh_recovery(sender, guardian_count),
+            };
+            self.save_recovery((sender, secret_id), &recovery);
+            // registering again keeps the rent account
+            if !self.subscriptions.contains((sender, secret_id)) {
+                let sub = self.subscription_or_new(sender, secret_id);
+                self.subscriptions.insert((sender, secret_id), &sub);
+            }
+            self.env().emit_event(RegistrationProposed {
+                user: sender,
+                secret_id,
+                guardians: guardian_ids,
+                threshold,
+            });
+            Ok(())
+        }
+
+        // called by a guardian once it stored the user's share. commitment is
+        // the hash of the share it received, it has to match the one the user
+        // proposed, or is recorded when the user proposed none
+        #[ink(message)]
+        pub fn ack_share_stored(&mut self, user: AccountId, secret_id: SecretId,
+            commitment: Hash) -> Result<()> {
+            self.ensure_not_paused()?;
+            let node = self.calling_node();
+            let mut u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let index = u.guardians.iter().position(|g| g.node_id == node)
+                .ok_or(Error::NotAssignedGuardian)?;
+            let g = &mut u.guardians[index];
+            if g.share_commitment == Hash::default() {
+                g.share_commitment = commitment;
+            } else if g.share_commitment != commitment {
+                return Err(Error::ShareMismatch)
+            }
+            if u.acknowledged[index] {
+                return Err(Error::AlreadyAcknowledged)
+            }
+            u.acknowledged[index] = true;
+            self.save_user((user, secret_id), &u);
+            self.env().emit_event(ShareAcknowledged { user, secret_id, node });
+            if Self::is_user_active(&u) {
+                self.env().emit_event(UserRegistered {
+                    user,
+                    secret_id,
+                    guardians: u.guardians.iter().map(|g| g.node_id).collect(),
+                    threshold: u.threshold,
+                });
+                self.reward_referrer(user)?;
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn pending_acknowledgements(&self, user: AccountId,
+            secret_id: SecretId) -> Vec<AccountId> {
+            self.users.get((user, secret_id))
+                .map(|u| u.guardians.iter().zip(u.acknowledged.iter())
+                    .filter(|(_, acked)| !**acked)
+                    .map(|(g, _)| g.node_id)
+                    .collect())
+                .unwrap_or_default()
+        }
+
+        fn is_user_active(u: &User) -> bool {
+            u.acknowledged.iter().all(|a| *a)
+        }
+
+        // the threshold is a guardian weight, a threshold of 0 or above the
+        // total weight can never be met
+        fn ensure_valid_quorum(guardians: &[Guardian], threshold: u32) -> Result<()> {
+            if guardians.iter().any(|g| g.weight == 0) {
+                return Err(Error::InvalidWeight)
+            }
+            if threshold == 0 || threshold as u64 > Self::total_weight(guardians.iter()) {
+                return Err(Error::InvalidThreshold)
+            }
+            Ok(())
+        }
+
+        fn total_weight<'a>(guardians: impl Iterator<Item = &'a Guardian>) -> u64 {
+            guardians.map(|g| g.weight as u64).sum()
+        }
+
+        fn confirmed_weight(u: &User, r: &Recovery) -> u64 {
+            Self::total_weight(u.guardians.iter().zip(r.confirmations.iter())
+                .filter(|(_, c)| c.confirmed)
+                .map(|(g, _)| g))
+        }
+
+        fn fresh_recovery(uid: AccountId, guardian_count: usize) -> Recovery {
+            Recovery {
+                status: RecoveryStatus::Idle,
+                uid,
+                r_times: 0,
+                recovery_id: 0,
+                started_at: 0,
+                not_before: 0,
+                last_finished_at: None,
+                failed_attempts: 0,
+                suspicious_attempts: 0,
+                locked: false,
+                unlock_requested_at: None,
+                escrowed: 0,
+                new_owner: None,
+                fee_token: None,
+                confirmations: vec![Confirmation::default(); guardian_count],
+            }
+        }
+
+        // user and recovery records are only written through these, so the
+        // user index and the counters behind stats() stay exact
+        fn save_user(&mut self, key: (AccountId, SecretId), user: &User) {
+            if !self.users.contains(key) {
+                let user_count = self.user_count();
+                self.user_ids.insert(user_count, &key);
+                self.user_positions.insert(key, &user_count);
+                self.user_count.set(&(user_count + 1));
+            }
+            self.users.insert(key, user);
+        }
+
+        // swap the last indexed user into the removed user's slot
+        fn remove_user_record(&mut self, key: (AccountId, SecretId)) {
+            if let Some(pos) = self.user_positions.get(key) {
+                let last = self.user_count() - 1;
+                if pos != last {
+                    if let Some(moved) = self.user_ids.get(last) {
+                        self.user_ids.insert(pos, &moved);
+                        self.user_positions.insert(moved, &pos);
+                    }
+                }
+                self.user_ids.remove(last);
+                self.user_positions.remove(key);
+                self.user_count.set(&last);
+            }
+            self.users.remove(key);
+        }
+
+        // get a page of registered (user, secret_id) pairs, in registration
+        // order until the first deletion
+        #[ink(message)]
+        pub fn get_users(&self, offset: u32, limit: u32) -> Vec<(AccountId, SecretId)> {
+            let end = offset.saturating_add(limit).min(self.user_count());
+            (offset..end).filter_map(|i| self.user_ids.get(i)).collect()
+        }
+
+        fn user_count(&self) -> u32 {
+            self.user_count.get().unwrap_or_default()
+        }
+
+        fn save_recovery(&mut self, key: (AccountId, SecretId), recovery: &Recovery) {
+            let old = self.recoveries.get(key).map_or(RecoveryStatus::Idle, |r| r.status);
+            let was_active = matches!(old, RecoveryStatus::Started | RecoveryStatus::PendingFinalize);
+            let active = self.active_recoveries.get().unwrap_or_default();
+            match (was_active, Self::is_active(recovery)) {
+                (false, true) => self.active_recoveries.set(&(active + 1)),
+                (true, false) => self.active_recoveries.set(&(active - 1)),
+                _ => {}
+            }
+            self.recoveries.insert(key, recovery);
+            if old != recovery.status {
+                self.env().emit_event(RecoveryStatusChanged {
+                    user: key.0,
+                    secret_id: key.1,
+                    recovery_id: recovery.recovery_id,
+                    status: recovery.status,
+                });
+            }
+        }
+
+        fn remove_recovery(&mut self, key: (AccountId, SecretId)) {
+            if self.recoveries.get(key).map_or(false, |r| Self::is_active(&r)) {
+                let active = self.active_recoveries.get().unwrap_or_default();
+                self.active_recoveries.set(&(active - 1));
+            }
+            self.recoveries.remove(key);
+            self.recovery_requests.remove(key);
+        }
+
+        fn is_active(recovery: &Recovery) -> bool {
+            matches!(recovery.status, RecoveryStatus::Started | RecoveryStatus::PendingFinalize)
+        }
+
+        #[ink(message)]
+        pub fn stats(&self) -> Stats {
+            Stats {
+                nodes: self.node_count(),
+                users: self.user_count(),
+                active_recoveries: self.active_recoveries.get().unwrap_or_default(),
+                completed_recoveries: self.completed_recoveries.get().unwrap_or_default(),
+            }
+        }
+
+        // a node takes no more user secrets than the capacity it declared
+        fn ensure_free_capacity(&self, node: AccountId) -> Result<()> {
+            if self.nodes.contains(node) && self.free_capacity(node) == 0 {
+                return Err(Error::NodeAtCapacity)
+            }
+            Ok(())
+        }
+
+        // every guardian must be a registered node with the minimum stake
+        // locked that is not leaving
+        fn ensure_eligible_guardian(&self, node: AccountId) -> Result<()> {
+            if self.blacklisted_nodes.contains(node) {
+                return Err(Error::NodeBlacklisted)
+            }
+            let n = self.nodes.get(node).ok_or(Error::NodeNotRegistered)?;
+            if n.exiting_since.is_some() {
+                return Err(Error::NodeExiting)
+            }
+            if !self.is_attested(node) {
+                return Err(Error::AttestationExpired)
+            }
+            if self.stake_of(node) < self.get_config().min_node_stake {
+                return Err(Error::InsufficientStake)
+            }
+            Ok(())
+        }
+
+        // a node holds at most one share of a secret
+        fn ensure_distinct_guardians(guardians: &[Guardian]) -> Result<()> {
+            for (i, g) in guardians.iter().enumerate() {
+                if guardians[..i].iter().any(|p| p.node_id == g.node_id) {
+                    return Err(Error::DuplicateGuardian)
+                }
+            }
+            Ok(())
+        }
+
+        // leave the protocol, with a grace period the user can still
+        // revert_deletion until finalize_deletion is called
+        #[ink(message)]
+        pub fn delete_user(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            // a locked or frozen secret stays until it is unlocked
+            if r.locked {
+                return Err(Error::RecoveryLocked)
+            }
+            if u.deletion_requested_at.is_some() {
+                return Err(Error::DeletionPending)
+            }
+            let grace_period = self.get_config().deletion_grace_period;
+            if grace_period == 0 {
+                return self.remove_user(sender, secret_id)
+            }
+            let now = self.env().block_number();
+            u.deletion_requested_at = Some(now);
+            self.save_user((sender, secret_id), &u);
+            self.env().emit_event(UserDeletionRequested {
+                user: sender,
+                secret_id,
+                delete_block: now + grace_period,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn revert_deletion(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if u.deletion_requested_at.is_none() {
+                return Err(Error::DeletionNotReady)
+            }
+            u.deletion_requested_at = None;
+            self.save_user((sender, secret_id), &u);
+            Ok(())
+        }
+
+        // anyone can remove a user whose grace period is over
+        #[ink(message)]
+        pub fn finalize_deletion(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let requested_at = u.deletion_requested_at.ok_or(Error::DeletionNotReady)?;
+            if self.env().block_number() < requested_at + self.get_config().deletion_grace_period {
+                return Err(Error::DeletionNotReady)
+            }
+            if self.recoveries.get((user, secret_id)).map_or(false, |r| r.locked) {
+                return Err(Error::RecoveryLocked)
+            }
+            self.remove_user(user, secret_id)
+        }
+
+        fn remove_user(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            if let Some(u) = self.users.get((user, secret_id)) {
+                self.unassign_guardians(&u, secret_id);
+                for g in u.guardians.iter() {
+                    self.request_share_deletion(g.node_id, user, secret_id);
+                }
+            }
+            // rent not charged yet goes back to the user
+            if let Some(sub) = self.subscriptions.get((user, secret_id)) {
+                self.pay_out(user, sub.prepaid)?;
+                self.subscriptions.remove((user, secret_id));
+            }
+            self.remove_user_record((user, secret_id));
+            // the recovery record stays, with its history, cooldown and lock.
+            // a later registration under the same key continues from it
+            self.recovery_requests.remove((user, secret_id));
+            self.reshares.remove((user, secret_id));
+            self.dead_man_switches.remove((user, secret_id));
+            self.beneficiaries.remove((user, secret_id));
+            self.social_guardians.remove((user, secret_id));
+            self.veto_keys.remove((user, secret_id));
+            self.env().emit_event(UserDeleted { user, secret_id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn deletion_grace_period(&self) -> BlockNumber {
+            self.get_config().deletion_grace_period
+        }
+
+        fn request_share_deletion(&mut self, node: AccountId, user: AccountId, secret_id: SecretId) {
+            let deadline = self.env().block_number() + self.get_config().deletion_ack_window;
+            self.deletion_deadlines.insert((node, user, secret_id), &deadline);
+            self.env().emit_event(ShareDeletionRequested { node, user, secret_id, deadline });
+        }
+
+        // a node in old that is not one of the kept guardians holds a share it
+        // has no use for anymore
+        fn request_dropped_share_deletion(&mut self, user: AccountId, secret_id: SecretId,
+            old: &[Guardian], kept: &[Guardian]) {
+            for g in old.iter().filter(|g| !kept.iter().any(|k| k.node_id == g.node_id)) {
+                self.request_share_deletion(g.node_id, user, secret_id);
+            }
+        }
+
+        // a node that dropped a share, because the user left or replaced it,
+        // states it wiped the share before the deadline
+        #[ink(message)]
+        pub fn confirm_share_deleted(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let node = self.calling_node();
+            let deadline = self.deletion_deadlines.get((node, user, secret_id))
+                .ok_or(Error::DeletionNotRequested)?;
+            if self.env().block_number() >= deadline {
+                return Err(Error::DeletionWindowClosed)
+            }
+            self.deletion_deadlines.remove((node, user, secret_id));
+            let mut rep = self.reputation(node);
+            rep.deletions_confirmed += 1;
+            self.reputations.insert(node, &rep);
+            self.env().emit_event(ShareDeletionResolved { node, user, secret_id, confirmed: true });
+            Ok(())
+        }
+
+        // anyone can report a node that let the deadline pass, it counts
+        // against its reputation and costs it up to deletion_penalty of stake
+        #[ink(message)]
+        pub fn report_missed_deletion(&mut self, node: AccountId, user: AccountId,
+            secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let deadline = self.deletion_deadlines.get((node, user, secret_id))
+                .ok_or(Error::DeletionNotRequested)?;
+            if self.env().block_number() < deadline {
+                return Err(Error::DeletionWindowOpen)
+            }
+            self.deletion_deadlines.remove((node, user, secret_id));
+            let mut rep = self.reputation(node);
+            rep.deletions_missed += 1;
+            self.reputations.insert(node, &rep);
+            let penalty = self.get_config().deletion_penalty.min(self.stake_of(node));
+            if penalty > 0 && self.nodes.contains(node) {
+                self.slash(node, penalty, b"missed share deletion".to_vec())?;
+            }
+            self.env().emit_event(ShareDeletionResolved { node, user, secret_id, confirmed: false });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn deletion_deadline(&self, node: AccountId, user: AccountId,
+            secret_id: SecretId) -> Option<BlockNumber> {
+            self.deletion_deadlines.get((node, user, secret_id))
+        }
+
+        #[ink(message)]
+        pub fn deletion_ack_window(&self) -> BlockNumber {
+            self.get_config().deletion_ack_window
+        }
+
+        #[ink(message)]
+        pub fn deletion_penalty(&self) -> Balance {
+            self.get_config().deletion_penalty
+        }
+
+        // pay storage rent for a secret in advance, charge_period draws from it
+        #[ink(message)]
+        pub fn prepay_storage(&mut self, secret_id: SecretId, amount: Balance) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            let mut sub = self.subscription_or_new(sender, secret_id);
+            self.pay_in(sender, amount)?;
+            sub.prepaid = safe_add(sub.prepaid, amount)?;
+            self.subscriptions.insert((sender, secret_id), &sub);
+            Ok(())
+        }
+
+        // charges every billing period that ended since the last charge and
+        // pays the rent to the secret's guardians. anyone may call it, e.g.
+        // the guardians themselves. when the prepaid rent runs out the
+        // secret is overdue, and purgeable after rent_grace_period
+        #[ink(message)]
+        pub fn charge_period(&mut self, user: AccountId, secret_id: SecretId) -> Result<u32> {
+            self.ensure_not_paused()?;
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut sub = self.subscription_or_new(user, secret_id);
+            let config = self.get_config();
+            let period = config.billing_period.max(1);
+            let due = (self.env().block_number().saturating_sub(sub.paid_until)) / period;
+            let fee = config.storage_fee;
+            let periods = match sub.prepaid.checked_div(fee) {
+                Some(affordable) => (due as Balance).min(affordable) as u32,
+                None => due,
+            };
+            let amount = fee * periods as Balance;
+            sub.prepaid = safe_sub(sub.prepaid, amount)?;
+            sub.paid_until += periods * period;
+            if periods < due {
+                if sub.overdue_since.is_none() {
+                    sub.overdue_since = Some(sub.paid_until);
+                    self.env().emit_event(RentOverdue {
+                        user,
+                        secret_id,
+                        purge_after: sub.paid_until + config.rent_grace_period,
+                    });
+                }
+            } else {
+                sub.overdue_since = None;
+            }
+            self.subscriptions.insert((user, secret_id), &sub);
+            // rent is the guardians' alone, unlike recovery fees the treasury
+            // takes no cut of it. every guardian gets the same share and only
+            // the rounding dust goes to the treasury
+            let guardian_count = u.guardians.len() as Balance;
+            let node_share = amount.checked_div(guardian_count).unwrap_or_default();
+            for g in u.guardians.iter() {
+                self.pay_out(g.node_id, node_share)?;
+            }
+            let dust = amount - node_share * guardian_count;
+            self.treasury.set(&safe_add(self.treasury_balance(), dust)?);
+            if periods > 0 {
+                self.env().emit_event(RentCharged { user, secret_id, periods, amount });
+            }
+            Ok(periods)
+        }
+
+        // secrets registered before rent existed start paying from now on
+        fn subscription_or_new(&self, user: AccountId, secret_id: SecretId) -> Subscription {
+            self.subscriptions.get((user, secret_id)).unwrap_or(Subscription {
+                paid_until: self.env().block_number(),
+                ..Default::default()
+            })
+        }
+
+        #[ink(message)]
+        pub fn subscription(&self, user: AccountId, secret_id: SecretId) -> Option<Subscription> {
+            self.subscriptions.get((user, secret_id))
+        }
+
+        // guardians may drop the shares of a secret whose rent stayed unpaid
+        // for longer than rent_grace_period
+        #[ink(message)]
+        pub fn shares_purgeable(&self, user: AccountId, secret_id: SecretId) -> bool {
+            match self.subscriptions.get((user, secret_id)).and_then(|s| s.overdue_since) {
+                Some(since) => self.env().block_number() >= since + self.get_config().rent_grace_period,
+                None => false,
+            }
+        }
+
+        #[ink(message)]
+        pub fn storage_fee(&self) -> Balance {
+            self.get_config().storage_fee
+        }
+
+        #[ink(message)]
+        pub fn billing_period(&self) -> BlockNumber {
+            self.get_config().billing_period
+        }
+
+        #[ink(message)]
+        pub fn rent_grace_period(&self) -> BlockNumber {
+            self.get_config().rent_grace_period
+        }
+
+        // switch the key guardians encrypt shares for, the old key signs
+        // (user, old key, new key) so the change can not be forged or replayed
+        #[ink(message)]
+        pub fn rotate_user_key(&mut self, secret_id: SecretId, new_pub_k: PublicKey,
+            signature: [u8; 65]) -> Result<()> {
+            self.ensure_not_paused()?;
+            Self::ensure_compressed_key_prefix(&new_pub_k)?;
+            let sender = self.env().caller();
+            let mut u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if r.locked {
+                return Err(Error::RecoveryLocked)
+            }
+            let digest = Self::key_rotation_digest(&self.env().account_id(), &sender, secret_id,
+                &u.pub_k, &new_pub_k);
+            if !self.is_signed_by(&u.pub_k, &digest, &signature) {
+                return Err(Error::InvalidSignature)
+            }
+            let old_pub_k = u.pub_k;
+            u.pub_k = new_pub_k;
+            // guardians re-encrypt their shares for the new key
+            u.share_version += 1;
+            self.save_user((sender, secret_id), &u);
+            self.env().emit_event(UserKeyRotated {
+                user: sender,
+                secret_id,
+                old_pub_k,
+                new_pub_k,
+                share_version: u.share_version,
+            });
+            Ok(())
+        }
+
+        // change the k of a k-of-n secret without registering again, e.g. to
+        // require every guardian for a high value secret
+        #[ink(message)]
+        pub fn set_threshold(&mut self, secret_id: SecretId, threshold: u32) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            Self::ensure_valid_quorum(&u.guardians, threshold)?;
+            u.threshold = threshold;
+            self.save_user((sender, secret_id), &u);
+            self.env().emit_event(ThresholdChanged { user: sender, secret_id, threshold });
+            Ok(())
+        }
+
+        // swap one guardian for another, e.g. when a node deregisters, the
+        // user hands the new node its share off chain
+        #[ink(message)]
+        pub fn replace_guardian(&mut self, secret_id: SecretId, old_node: AccountId,
+            new_node: AccountId, cond_type: ConditionType,
+            cond_commitment: Hash, share_commitment: Hash) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            if self.reshares.contains((sender, secret_id)) {
+                return Err(Error::ResharePending)
+            }
+            let index = u.guardians.iter().position(|g| g.node_id == old_node)
+                .ok_or(Error::NotAssignedGuardian)?;
+            if u.guardians.iter().any(|g| g.node_id == new_node) {
+                return Err(Error::DuplicateGuardian)
+            }
+            self.ensure_eligible_guardian(new_node)?;
+            self.ensure_free_capacity(new_node)?;
+            // the new node takes over the weight of the old one
+            u.guardians[index] = Guardian {
+                node_id: new_node,
+                cond_type,
+                cond_commitment,
+                share_commitment,
+                weight: u.guardians[index].weight,
+            };
+            // the new node has to acknowledge its share before a recovery
+            u.acknowledged[index] = false;
+            u.share_version += 1;
+            self.save_user((sender, secret_id), &u);
+            self.unassign(old_node, sender, secret_id);
+            self.assign(new_node, sender, secret_id);
+            self.request_share_deletion(old_node, sender, secret_id);
+            self.env().emit_event(GuardianReplaced {
+                user: sender,
+                secret_id,
+                old_node,
+                new_node,
+                share_version: u.share_version,
+            });
+            Ok(())
+        }
+
+        // move a secret to a new guardian set and threshold. the user hands the
+        // new nodes their shares off chain, the old guardians stay in charge
+        // until every new node called acknowledge_share
+        #[ink(message)]
+        pub fn start_reshare(&mut self, secret_id: SecretId, guardians: Vec<Guardian>,
+            threshold: u32) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if u.deletion_requested_at.is_some() {
+                return Err(Error::DeletionPending)
+            }
+            if self.reshares.contains((sender, secret_id)) {
+                return Err(Error::ResharePending)
+            }
+            Self::ensure_valid_quorum(&guardians, threshold)?;
+            Self::ensure_distinct_guardians(&guardians)?;
+            for g in guardians.iter() {
+                self.ensure_eligible_guardian(g.node_id)?;
+                if !u.guardians.iter().any(|p| p.node_id == g.node_id) {
+                    self.ensure_free_capacity(g.node_id)?;
+                }
+            }
+            let guardian_ids = guardians.iter().map(|g| g.node_id).collect();
+            self.reshares.insert((sender, secret_id), &Reshare {
+                acknowledged: vec![false; guardians.len()],
+                guardians,
+                threshold,
+                started_at: self.env().block_number(),
+            });
+            self.env().emit_event(ReshareStarted {
+                user: sender,
+                secret_id,
+                guardians: guardian_ids,
+                threshold,
+            });
+            Ok(())
+        }
+
+        // called by each new guardian once it holds its share, the last one
+        // retires the old set. not while a recovery runs on the old set
+        #[ink(message)]
+        pub fn acknowledge_share(&mut self, user: AccountId,
+            secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let node = self.calling_node();
+            let mut reshare = self.reshares.get((user, secret_id)).ok_or(Error::NoReshare)?;
+            let index = reshare.guardians.iter().position(|g| g.node_id == node)
+                .ok_or(Error::NotAssignedGuardian)?;
+            reshare.acknowledged[index] = true;
+            self.env().emit_event(ShareAcknowledged { user, secret_id, node });
+            if reshare.acknowledged.iter().any(|a| !a) {
+                self.reshares.insert((user, secret_id), &reshare);
+                return Ok(())
+            }
+
+            let mut u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            self.unassign_guardians(&u, secret_id);
+            self.request_dropped_share_deletion(user, secret_id, &u.guardians, &reshare.guardians);
+            u.guardians = reshare.guardians;
+            u.threshold = reshare.threshold;
+            u.share_version += 1;
+            u.acknowledged = reshare.acknowledged;
+            self.assign_guardians(&u, secret_id);
+            r.confirmations = vec![Confirmation::default(); u.guardians.len()];
+            self.save_user((user, secret_id), &u);
+            self.save_recovery((user, secret_id), &r);
+            self.reshares.remove((user, secret_id));
+            self.env().emit_event(ReshareCompleted {
+                user,
+                secret_id,
+                share_version: u.share_version,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn cancel_reshare(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.reshares.contains((sender, secret_id)) {
+                return Err(Error::NoReshare)
+            }
+            self.reshares.remove((sender, secret_id));
+            self.env().emit_event(ReshareCancelled { user: sender, secret_id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_user(&self, user: AccountId, secret_id: SecretId) -> Option<UserView> {
+            self.users.get((user, secret_id)).map(|u| UserView {
+                guardians: u.guardians.iter().map(|g| g.node_id).collect(),
+                cond_types: u.guardians.iter().map(|g| g.cond_type).collect(),
+                weights: u.guardians.iter().map(|g| g.weight).collect(),
+                pub_k: u.pub_k,
+                threshold: u.threshold,
+                share_version: u.share_version,
+                deletion_requested_at: u.deletion_requested_at,
+                active: Self::is_user_active(&u),
+            })
+        }
+
+        #[ink(message)]
+        pub fn guardians_of(&self, user: AccountId, secret_id: SecretId) -> Vec<Guardian> {
+            self.users.get((user, secret_id)).map(|u| u.guardians).unwrap_or_default()
+        }
+
+        // what propose_registration stores per guardian: the hash of a random
+        // salt and the condition data, e.g. an email address. the salt keeps
+        // the data from being guessed from the chain
+        #[ink(message)]
+        pub fn condition_commitment(&self, salt: Hash, data: Vec<u8>) -> Hash {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(&(salt, data), &mut digest);
+            Hash::from(digest)
+        }
+
+        // what the user commits to for each share at registration, the hash
+        // of the encrypted share as the node receives it
+        #[ink(message)]
+        pub fn share_commitment(&self, encrypted_share: Vec<u8>) -> Hash {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_bytes::<Blake2x256>(&encrypted_share, &mut digest);
+            Hash::from(digest)
+        }
+
+        // settles disputes about a share, true if it is the one the user
+        // committed to handing node
+        #[ink(message)]
+        pub fn verify_share(&self, user: AccountId, secret_id: SecretId, node: AccountId,
+            encrypted_share: Vec<u8>) -> bool {
+            let commitment = self.share_commitment(encrypted_share);
+            self.guardians_of(user, secret_id).iter()
+                .any(|g| g.node_id == node && g.share_commitment == commitment)
+        }
+
+        // lets a node check the data a requester presents against what the
+        // user registered for that guardian
+        #[ink(message)]
+        pub fn verify_condition(&self, user: AccountId, secret_id: SecretId, node: AccountId,
+            salt: Hash, data: Vec<u8>) -> bool {
+            let commitment = self.condition_commitment(salt, data);
+            self.guardians_of(user, secret_id).iter()
+                .any(|g| g.node_id == node && g.cond_commitment == commitment)
+        }
+
+        #[ink(message)]
+        pub fn reshare_pending(&self, user: AccountId, secret_id: SecretId) -> Option<Reshare> {
+            self.reshares.get((user, secret_id))
+        }
+
+        #[ink(message)]
+        pub fn verify_new_user(&self, secret_id: SecretId, pub_k: PublicKey) -> bool {
+            let sender = self.env().caller();
+            let user = self.users.get((sender, secret_id));
+            let recovery = self.recoveries.get((sender, secret_id));
+            let u = match user {
+                Some(user) => user,
+                None => return false
+            };
+            let r = match recovery {
+                Some(recovery) => recovery,
+                None => return false
+            };
+            u.pub_k == pub_k && matches!(
+                r.status,
+                RecoveryStatus::Idle | RecoveryStatus::Expired | RecoveryStatus::Cancelled
+            )
+        }
+
+        // before user try to access its secret, call request_recovery. the
+        // returned id is what the guardians confirm, request is stored with
+        // the attempt for them to check against
+        #[ink(message)]
+        pub fn start_recovery(&mut self, secret_id: SecretId,
+            request: RecoveryRequest) -> Result<RecoveryId> {
+            self.ensure_not_paused()?;
+            if let Some(target) = &request.target_pub_k {
+                Self::ensure_compressed_key_prefix(target)?;
+            }
+            let sender = self.env().caller();
+            self.begin_recovery(sender, secret_id, None, Some(request))
+        }
+
+        // relayed start_recovery for users without native tokens for gas. the
+        // user signs (contract, user, secret_id, nonce) with its registered
+        // key, anyone can submit it and the fee still comes from the user
+        #[ink(message)]
+        pub fn start_recovery_for(&mut self, user: AccountId, secret_id: SecretId,
+            signature: [u8; 65], nonce: u64) -> Result<RecoveryId> {
+            self.ensure_not_paused()?;
+            if nonce != self.nonce(user) {
+                return Err(Error::InvalidNonce)
+            }
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let digest = Self::relayed_recovery_digest(&self.env().account_id(), &user,
+                secret_id, nonce);
+            if !self.is_signed_by(&u.pub_k, &digest, &signature) {
+                return Err(Error::InvalidSignature)
+            }
+            self.nonces.insert(user, &(nonce + 1));
+            self.begin_recovery(user, secret_id, None, None)
+        }
+
+        #[ink(message)]
+        pub fn nonce(&self, user: AccountId) -> u64 {
+            self.nonces.get(user).unwrap_or_default()
+        }
+
+        // let beneficiary recover the secret to its own account once the user
+        // did not check_in for inactivity_period blocks. arming it counts as a
+        // check in, None disarms it
+        #[ink(message)]
+        pub fn set_dead_man_switch(&mut self, secret_id: SecretId,
+            switch: Option<DeadManSwitch>) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            match &switch {
+                Some(sw) => {
+                    self.dead_man_switches.insert((sender, secret_id), sw);
+                    self.last_check_ins.insert(sender, &self.env().block_number());
+                }
+                None => self.dead_man_switches.remove((sender, secret_id)),
+            }
+            self.env().emit_event(DeadManSwitchSet { user: sender, secret_id, switch });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn dead_man_switch(&self, user: AccountId,
+            secret_id: SecretId) -> Option<DeadManSwitch> {
+            self.dead_man_switches.get((user, secret_id))
+        }
+
+        // one check in keeps every secret of the user from its beneficiaries
+        #[ink(message)]
+        pub fn check_in(&mut self) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            self.last_check_ins.insert(sender, &self.env().block_number());
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn last_check_in(&self, user: AccountId) -> Option<BlockNumber> {
+            self.last_check_ins.get(user)
+        }
+
+        // the beneficiary of an inactive user starts a recovery to itself, the
+        // user can still cancel_recovery if it turns out to be around
+        #[ink(message)]
+        pub fn start_dead_man_recovery(&mut self, uid: AccountId,
+            secret_id: SecretId) -> Result<RecoveryId> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let switch = self.dead_man_switches.get((uid, secret_id))
+                .filter(|sw| sw.beneficiary == sender)
+                .ok_or(Error::NotBeneficiary)?;
+            let active_until = self.last_check_in(uid).unwrap_or_default()
+                + switch.inactivity_period;
+            let now = self.env().block_number();
+            if now < active_until {
+                return Err(Error::OwnerStillActive(active_until - now))
+            }
+            if self.users.contains((sender, secret_id)) {
+                return Err(Error::UserAlreadyRegistered)
+            }
+            self.begin_recovery(uid, secret_id, Some(sender), None)
+        }
+
+        // friends that must approve every recovery of the secret before the
+        // nodes serve their shares. an empty list or a threshold of 0 turns
+        // it off. not while a recovery runs
+        #[ink(message)]
+        pub fn set_social_guardians(&mut self, secret_id: SecretId,
+            guardians: SocialGuardians) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            // a deleted secret keeps its recovery record, not its user record
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            if guardians.threshold as usize > guardians.accounts.len() {
+                return Err(Error::InvalidThreshold)
+            }
+            self.social_guardians.insert((sender, secret_id), &guardians);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn social_guardians(&self, user: AccountId, secret_id: SecretId) -> SocialGuardians {
+            self.social_guardians.get((user, secret_id)).unwrap_or_default()
+        }
+
+        // an oracle reports that the user passed the check behind cond_type at
+        // block verified_at, e.g. a KYC provider or an email OTP service
+        #[ink(message)]
+        pub fn post_verification(&mut self, user: AccountId, secret_id: SecretId,
+            cond_type: ConditionType, verified_at: BlockNumber) -> Result<()> {
+            self.ensure_not_paused()?;
+            self.ensure_role(Role::Oracle)?;
+            if verified_at > self.env().block_number() {
+                return Err(Error::InvalidVerificationBlock)
+            }
+            if !self.users.contains((user, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            self.verifications.insert((user, secret_id, cond_type), &verified_at);
+            self.env().emit_event(VerificationPosted {
+                user,
+                secret_id,
+                cond_type,
+                verified_at,
+                oracle: self.env().caller(),
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn verification(&self, user: AccountId, secret_id: SecretId,
+            cond_type: ConditionType) -> Option<BlockNumber> {
+            self.verifications.get((user, secret_id, cond_type))
+        }
+
+        // when required, a guardian only confirms once an oracle posted a
+        // check of its condition made during the running attempt
+        #[ink(message)]
+        pub fn set_verification_required(&mut self, secret_id: SecretId,
+            required: bool) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            // a deleted secret keeps its recovery record, not its user record
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            let r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if Self::is_active(&r) {
+                return Err(Error::RecoveryInProgress)
+            }
+            if required {
+                self.verification_required.insert((sender, secret_id), &());
+            } else {
+                self.verification_required.remove((sender, secret_id));
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn verification_required(&self, user: AccountId, secret_id: SecretId) -> bool {
+            self.verification_required.contains((user, secret_id))
+        }
+
+        // a social guardian co-signs the running recovery of user's secret
+        #[ink(message)]
+        pub fn approve_recovery(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let guardian = self.env().caller();
+            if !self.social_guardians(user, secret_id).accounts.contains(&guardian) {
+                return Err(Error::NotSocialGuardian)
+            }
+            let r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if r.status != RecoveryStatus::Started {
+                return Err(Error::RecoveryNotActive)
+            }
+            let mut approvals = self.social_approvals.get(r.recovery_id).unwrap_or_default();
+            if approvals.contains(&guardian) {
+                return Err(Error::AlreadyApproved)
+            }
+            approvals.push(guardian);
+            self.social_approvals.insert(r.recovery_id, &approvals);
+            self.env().emit_event(RecoveryApproved {
+                user,
+                secret_id,
+                recovery_id: r.recovery_id,
+                guardian,
+            });
+            Ok(())
+        }
+
+        // approvals of guardians that were removed since do not count
+        #[ink(message)]
+        pub fn recovery_approvals(&self, recovery_id: RecoveryId) -> u32 {
+            let (user, secret_id) = match self.recovery_ids.get(recovery_id) {
+                Some(key) => key,
+                None => return 0,
+            };
+            let social = self.social_guardians(user, secret_id);
+            self.social_approvals.get(recovery_id).unwrap_or_default().iter()
+                .filter(|a| social.accounts.contains(a))
+                .count() as u32
+        }
+
+        // heirs can claim the secret at any time, but the recovery only
+        // completes delay_blocks after the claim so the user can veto it with
+        // cancel_recovery. None removes the heir
+        #[ink(message)]
+        pub fn set_beneficiary(&mut self, secret_id: SecretId, beneficiary: AccountId,
+            delay_blocks: Option<BlockNumber>) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            let mut heirs = self.beneficiaries.get((sender, secret_id)).unwrap_or_default();
+            heirs.retain(|(heir, _)| *heir != beneficiary);
+            if let Some(delay) = delay_blocks {
+                heirs.push((beneficiary, delay));
+            }
+            self.beneficiaries.insert((sender, secret_id), &heirs);
+            self.env().emit_event(BeneficiarySet {
+                user: sender,
+                secret_id,
+                beneficiary,
+                delay_blocks,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn beneficiary_delay(&self, user: AccountId, secret_id: SecretId,
+            beneficiary: AccountId) -> Option<BlockNumber> {
+            self.beneficiaries.get((user, secret_id)).unwrap_or_default().iter()
+                .find(|(heir, _)| *heir == beneficiary)
+                .map(|(_, delay)| *delay)
+        }
+
+        // an heir starts a recovery of uid's secret to itself, it can only be
+        // finalized once the delay passed
+        #[ink(message)]
+        pub fn start_inheritance(&mut self, uid: AccountId,
+            secret_id: SecretId) -> Result<RecoveryId> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let delay = self.beneficiary_delay(uid, secret_id, sender)
+                .ok_or(Error::NotBeneficiary)?;
+            if self.users.contains((sender, secret_id)) {
+                return Err(Error::UserAlreadyRegistered)
+            }
+            // neither the owner's attempt nor a running claim is replaced, a
+            // restart would also move the end of the veto window
+            if self.recoveries.get((uid, secret_id)).as_ref().map_or(false, Self::is_active) {
+                return Err(Error::RecoveryInProgress)
+            }
+            let recovery_id = self.begin_recovery(uid, secret_id, Some(sender), None)?;
+            let mut r = self.recoveries.get((uid, secret_id)).ok_or(Error::UserNotFound)?;
+            r.not_before = r.started_at + delay;
+            self.save_recovery((uid, secret_id), &r);
+            self.env().emit_event(InheritanceStarted {
+                user: uid,
+                secret_id,
+                beneficiary: sender,
+                recovery_id,
+                veto_until: r.not_before,
+            });
+            Ok(recovery_id)
+        }
+
+        // a user that lost its key asks from a new account to recover uid's
+        // secret, on success the user record moves to the new account
+        #[ink(message)]
+        pub fn start_account_recovery(&mut self, uid: AccountId,
+            secret_id: SecretId) -> Result<RecoveryId> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if self.users.contains((sender, secret_id)) {
+                return Err(Error::UserAlreadyRegistered)
+            }
+            self.begin_recovery(uid, secret_id, Some(sender), None)
+        }
+
+        // the fee is escrowed from the new owner when there is one, otherwise
+        // from the user itself
+        fn begin_recovery(&mut self, uid: AccountId, secret_id: SecretId,
+            new_owner: Option<AccountId>, request: Option<RecoveryRequest>) -> Result<RecoveryId> {
+            let user = self.users.get((uid, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((uid, secret_id)).ok_or(Error::UserNotFound)?;
+            if user.deletion_requested_at.is_some() {
+                return Err(Error::DeletionPending)
+            }
+            if !Self::is_user_active(&user) {
+                return Err(Error::RegistrationPending)
+            }
+            if r.locked {
+                return Err(Error::RecoveryLocked)
+            }
+            // a confirmed attempt can only be cancelled, not replaced, and a
+            // running one only by whoever started it
+            if r.status == RecoveryStatus::PendingFinalize
+                || (Self::is_active(&r) && r.new_owner != new_owner) {
+                return Err(Error::RecoveryInProgress)
+            }
+            // replacing its own running attempt counts like cancelling it,
+            // otherwise a thief would restart instead of cancel. the restart
+            // that would lock the secret is refused, the running attempt
+            // stays until it is cancelled or expires
+            if Self::is_active(&r) && r.new_owner.is_none() {
+                self.record_failed_attempt(uid, secret_id, &mut r);
+                if r.locked {
+                    return Err(Error::RecoveryLocked)
+                }
+            }
+            let config = self.get_config();
+            let now = self.env().block_number();
+            if let Some(last) = r.last_finished_at {
+                let ready_at = last + config.recovery_cooldown;
+                if now < ready_at {
+                    return Err(Error::CooldownActive(ready_at - now))
+                }
+            }
+            // the fee is locked in escrow so it can not be moved away before
+            // the guardians get paid, a restart first refunds the old escrow
+            let payer = new_owner.unwrap_or(uid);
+            let cost = self.recovery_cost(uid, secret_id);
+            let fee_token = self.fee_token();
+            // an external fee token checks the payer's balance and allowance
+            // itself when the fee is pulled
+            if fee_token.is_none() {
+                let mut available = self.balance_of(payer);
+                if r.new_owner.unwrap_or(uid) == payer && r.fee_token.is_none() {
+                    available = safe_add(available, r.escrowed)?;
+                }
+                if available < config.min_balance || available < cost {
+                    return Err(Error::InsufficientBalance)
+                }
+            }
+            self.take_recovery_start(payer)?;
+            self.refund_escrow(uid, secret_id, &mut r)?;
+            self.collect_fee(fee_token, payer, cost)?;
+            if new_owner.is_none() {
+                self.record_recovery_start(uid, secret_id, now);
+            }
+            let recovery_id = self.last_recovery_id.get().unwrap_or_default() + 1;
+            self.last_recovery_id.set(&recovery_id);
+            if cost > 0 {
+                self.env().emit_event(FeeCharged {
+                    user: uid,
+                    secret_id,
+                    recovery_id,
+                    payer,
+                    amount: cost,
+                    fee_token,
+                });
+            }
+            self.recovery_ids.insert(recovery_id, &(uid, secret_id));
+            // keep every thing else
+            let r1 = Recovery {
+                status: RecoveryStatus::Started,
+                escrowed: cost,
+                new_owner,
+                fee_token,
+                recovery_id,
+                started_at: now,
+                not_before: now,
+                confirmations: vec![Confirmation::default(); user.guardians.len()],
+                ..r
+            };
+
+            self.save_recovery((uid, secret_id), &r1);
+            // a restart replaces the context of the old attempt
+            match request {
+                Some(request) => {
+                    self.recovery_requests.insert((uid, secret_id), &request);
+                }
+                None => self.recovery_requests.remove((uid, secret_id)),
+            }
+            self.env().emit_event(RecoveryStarted {
+                user: uid,
+                secret_id,
+                recovery_id,
+                new_owner,
+            });
+            Ok(recovery_id)
+        }
+
+        #[ink(message)]
+        pub fn verify_new_recovery(&self, secret_id: SecretId) -> bool{
+            let sender = self.env().caller();
+            let r = self.recoveries.get((sender, secret_id));
+            if let Some(r) = r {
+                return Self::is_active(&r);
+            }
+            false
+        }
+
+        // called by each guardian node once it has served its share
+        #[ink(message)]
+        pub fn finish_recovery(&mut self, recovery_id: RecoveryId,
+            proof: RecoveryProof) -> Result<()> {
+            self.ensure_not_paused()?;
+            let node = self.calling_node();
+            self.confirm_recovery(node, recovery_id, proof)
+        }
+
+        // anyone can hand in the signed proofs of several guardians at once,
+        // so a recovery can reach its threshold in one block. every proof is
+        // checked as if its node had sent it
+        #[ink(message)]
+        pub fn finish_recovery_aggregate(&mut self, recovery_id: RecoveryId,
+            proofs: Vec<(AccountId, RecoveryProof)>) -> Result<()> {
+            self.ensure_not_paused()?;
+            Self::ensure_max_len(proofs.len(), MAX_BATCH_LEN)?;
+            for (node, proof) in proofs {
+                self.confirm_recovery(node, recovery_id, proof)?;
+            }
+            Ok(())
+        }
+
+        fn confirm_recovery(&mut self, node: AccountId, recovery_id: RecoveryId,
+            proof: RecoveryProof) -> Result<()> {
+            Self::ensure_max_len(proof.condition_proof.len(), MAX_CONDITION_PROOF_LEN)?;
+            let n = self.nodes.get(node).ok_or(Error::NodeNotRegistered)?;
+            if self.blacklisted_nodes.contains(node) {
+                return Err(Error::NodeBlacklisted)
+            }
+            if !self.is_attested(node) {
+                return Err(Error::AttestationExpired)
+            }
+            let (user, secret_id) = self.recovery_ids.get(recovery_id)
+                .ok_or(Error::RecoveryNotActive)?;
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            // when the attempt is over or was replaced by a newer one, quit.
+            // confirmations during the challenge window still get paid
+            if !Self::is_active(&r) || r.recovery_id != recovery_id {
+                return Err(Error::RecoveryNotActive)
+            }
+            let index = u.guardians.iter().position(|g| g.node_id == node)
+                .ok_or(Error::NotAssignedGuardian)?;
+            if proof.share_version != u.share_version {
+                return Err(Error::StaleShare)
+            }
+            if self.recovery_approvals(recovery_id) < self.social_guardians(user, secret_id).threshold {
+                return Err(Error::ApprovalsMissing)
+            }
+            if self.verification_required(user, secret_id) {
+                let verified_at = self.verification(user, secret_id, u.guardians[index].cond_type);
+                if !verified_at.map_or(false, |at| at >= r.started_at) {
+                    return Err(Error::VerificationMissing)
+                }
+            }
+            self.verify_recovery_proof(&n, &user, secret_id, recovery_id, &proof)?;
+            self.verify_condition_proof(&u.guardians[index], &user, secret_id, recovery_id,
+                &proof.condition_proof)?;
+            if !r.confirmations[index].confirmed {
+                let mut rep = self.reputation(node);
+                rep.confirmed += 1;
+                self.reputations.insert(node, &rep);
+            }
+            r.confirmations[index] = Confirmation {
+                confirmed: true,
+                proof_hash: Some(Self::proof_hash(&proof)),
+            };
+            let confirmations = Self::confirmed_weight(&u, &r);
+            self.env().emit_event(RecoveryConfirmed {
+                user,
+                secret_id,
+                node,
+                proof,
+                confirmations,
+                threshold: u.threshold,
+            });
+
+            if r.status == RecoveryStatus::Started && confirmations >= u.threshold as u64 {
+                r.status = RecoveryStatus::PendingFinalize;
+                r.not_before = r.not_before.max(self.env().block_number() + self.get_config().challenge_period);
+                self.env().emit_event(RecoveryPendingFinalize {
+                    user,
+                    secret_id,
+                    recovery_id,
+                    finalizable_at: r.not_before,
+                });
+            }
+            self.save_recovery((user, secret_id), &r);
+            Ok(())
+        }
+
+        // confirms many recoveries in one call for busy nodes, one result per
+        // item, a failed item does not stop the others
+        #[ink(message)]
+        pub fn finish_recovery_batch(&mut self,
+            confirmations: Vec<(RecoveryId, RecoveryProof)>) -> Result<Vec<Result<()>>> {
+            Self::ensure_max_len(confirmations.len(), MAX_BATCH_LEN)?;
+            Ok(confirmations.into_iter()
+                .map(|(recovery_id, proof)| self.finish_recovery(recovery_id, proof))
+                .collect())
+        }
+
+        // anyone can complete a confirmed recovery once the challenge window,
+        // or the delay of an inheritance, passed without the user cancelling
+        #[ink(message)]
+        pub fn finalize_recovery(&mut self, recovery_id: RecoveryId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let (user, secret_id) = self.recovery_ids.get(recovery_id)
+                .ok_or(Error::RecoveryNotActive)?;
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if r.recovery_id != recovery_id {
+                return Err(Error::RecoveryNotActive)
+            }
+            match r.status {
+                RecoveryStatus::Started => return Err(Error::ThresholdNotReached),
+                RecoveryStatus::PendingFinalize => {}
+                _ => return Err(Error::RecoveryNotActive),
+            }
+            let now = self.env().block_number();
+            if now < r.not_before {
+                return Err(Error::VetoWindowOpen(r.not_before - now))
+            }
+            self.complete_recovery(user, secret_id, &u, r)
+        }
+
+        #[ink(message)]
+        pub fn challenge_period(&self) -> BlockNumber {
+            self.get_config().challenge_period
+        }
+
+        // when recovery completed, pay the escrowed fee to the nodes.
+        fn complete_recovery(&mut self, user: AccountId, secret_id: SecretId, u: &User,
+            r: Recovery) -> Result<()> {
+            // the only way to the payout. the attempt leaves PendingFinalize
+            // for Finished before any token moves, so a second finalize or a
+            // late confirmation finds it over and pays nothing
+            if r.status != RecoveryStatus::PendingFinalize {
+                return Err(Error::RecoveryNotActive)
+            }
+            // the new owner may have registered the secret id since it asked
+            // for the recovery, its own record is not overwritten
+            if let Some(new_owner) = r.new_owner {
+                if self.users.contains((new_owner, secret_id)) {
+                    return Err(Error::UserAlreadyRegistered)
+                }
+            }
+            let recovery_id = r.recovery_id;
+            let confirm_parts = r.confirmations.iter().filter(|c| c.confirmed).count();
+            let fee = r.escrowed;
+            let now = self.env().block_number();
+            let record = RecoveryRecord {
+                recovery_id,
+                started_at: r.started_at,
+                finished_at: now,
+                new_owner: r.new_owner,
+                confirmed_by: u.guardians.iter().zip(r.confirmations.iter())
+                    .filter(|(_, c)| c.confirmed)
+                    .map(|(g, _)| g.node_id)
+                    .collect(),
+                fee,
+            };
+            self.recovery_history.insert((user, secret_id, r.r_times), &record);
+            let r1 = Recovery {
+                r_times: r.r_times + 1,
+                status: RecoveryStatus::Finished,
+                last_finished_at: Some(now),
+                failed_attempts: 0,
+                escrowed: 0,
+                ..r
+            };
+            let r_times = r1.r_times;
+            self.save_recovery((user, secret_id), &r1);
+            let completed = self.completed_recoveries.get().unwrap_or_default();
+            self.completed_recoveries.set(&(completed + 1));
+            // only guardians that actually confirmed get a share
+            let (node_share, treasury_share) = self.split_fee(fee, confirm_parts);
+            for (g, c) in u.guardians.iter().zip(r1.confirmations.iter()) {
+                if c.confirmed {
+                    self.pay_fee(r1.fee_token, g.node_id, node_share)?;
+                    self.add_reward_points(g.node_id, RECOVERY_REWARD_POINTS);
+                    self.record_payout(g.node_id, Payout {
+                        block: now,
+                        user,
+                        secret_id,
+                        recovery_id,
+                        amount: node_share,
+                        fee_token: r1.fee_token,
+                    });
+                    self.env().emit_event(NodePaid {
+                        user,
+                        secret_id,
+                        recovery_id,
+                        node: g.node_id,
+                        amount: node_share,
+                        fee_token: r1.fee_token,
+                    });
+                }
+            }
+            match r1.fee_token {
+                Some(token) => {
+                    let held = self.fee_token_treasury(token);
+                    self.fee_token_treasuries.insert(token, &safe_add(held, treasury_share)?);
+                }
+                None => self.treasury.set(&safe_add(self.treasury_balance(), treasury_share)?),
+            }
+            if treasury_share > 0 {
+                self.env().emit_event(TreasuryCut {
+                    user,
+                    secret_id,
+                    recovery_id,
+                    amount: treasury_share,
+                    fee_token: r1.fee_token,
+                });
+            }
+            self.env().emit_event(RecoveryFinished { user, secret_id, r_times });
+            if let Some(new_owner) = r1.new_owner {
+                self.migrate_user(user, secret_id, new_owner);
+            }
+            Ok(())
+        }
+
+        fn record_payout(&mut self, node: AccountId, payout: Payout) {
+            let count = self.payout_count(node);
+            self.payouts.insert((node, count), &payout);
+            self.payout_counts.insert(node, &(count + 1));
+        }
+
+        #[ink(message)]
+        pub fn payout_count(&self, node: AccountId) -> u32 {
+            self.payout_counts.get(node).unwrap_or_default()
+        }
+
+        // page through the recovery fee shares a node was paid, oldest first
+        #[ink(message)]
+        pub fn get_payouts(&self, node: AccountId, offset: u32, limit: u32) -> Vec<Payout> {
+            let end = offset.saturating_add(limit).min(self.payout_count(node));
+            (offset..end).filter_map(|i| self.payouts.get((node, i))).collect()
+        }
+
+        // a user that started a recovery by mistake can abort it, also during
+        // the challenge window when someone else asked for it
+        #[ink(message)]
+        pub fn cancel_recovery(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let u = self.users.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if !Self::is_active(&r) {
+                return Err(Error::RecoveryNotActive)
+            }
+            r.status = RecoveryStatus::Cancelled;
+            r.confirmations = vec![Confirmation::default(); u.guardians.len()];
+            // a thief holding the user's device could cancel and restart to
+            // dodge the expiry, so cancelling its own attempt counts as a
+            // failed attempt too. cancelling someone else's does not
+            let own_attempt = r.new_owner.is_none();
+            self.refund_escrow(sender, secret_id, &mut r)?;
+            if own_attempt {
+                self.record_failed_attempt(sender, secret_id, &mut r);
+            }
+            self.save_recovery((sender, secret_id), &r);
+            self.env().emit_event(RecoveryCancelled {
+                user: sender,
+                secret_id,
+                recovery_id: r.recovery_id,
+            });
+            Ok(())
+        }
+
+        // a key that can only veto, so a user whose main key is stolen can
+        // still stop a recovery by the thief. None removes it
+        #[ink(message)]
+        pub fn set_veto_key(&mut self, secret_id: SecretId,
+            veto_key: Option<AccountId>) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if !self.users.contains((sender, secret_id)) {
+                return Err(Error::UserNotFound)
+            }
+            match veto_key {
+                Some(key) => self.veto_keys.insert((sender, secret_id), &key),
+                None => self.veto_keys.remove((sender, secret_id)),
+            };
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn veto_key(&self, user: AccountId, secret_id: SecretId) -> Option<AccountId> {
+            self.veto_keys.get((user, secret_id))
+        }
+
+        // abort a running or confirmed recovery the user did not ask for, the
+        // attempt is recorded as suspicious. one started with the owner's own
+        // key also counts towards the lockout like cancel_recovery, a veto
+        // does not undo failed attempts
+        #[ink(message)]
+        pub fn veto_recovery(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            if sender != user && self.veto_key(user, secret_id) != Some(sender) {
+                return Err(Error::NotVetoKey)
+            }
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if !Self::is_active(&r) {
+                return Err(Error::RecoveryNotActive)
+            }
+            r.status = RecoveryStatus::Cancelled;
+            r.confirmations = vec![Confirmation::default(); u.guardians.len()];
+            self.refund_escrow(user, secret_id, &mut r)?;
+            if r.new_owner.is_none() {
+                self.record_failed_attempt(user, secret_id, &mut r);
+            }
+            r.suspicious_attempts += 1;
+            self.save_recovery((user, secret_id), &r);
+            self.env().emit_event(RecoveryVetoed {
+                user,
+                secret_id,
+                recovery_id: r.recovery_id,
+                vetoed_by: sender,
+                suspicious_attempts: r.suspicious_attempts,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn suspicious_attempts(&self, user: AccountId, secret_id: SecretId) -> u32 {
+            self.recoveries.get((user, secret_id))
+                .map(|r| r.suspicious_attempts)
+                .unwrap_or_default()
+        }
+
+        // anyone can reset a recovery that did not finish within
+        // recovery_expiry blocks, so it does not stay started forever. for
+        // inheritances they count from the end of the veto window
+        #[ink(message)]
+        pub fn expire_recovery(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if r.status != RecoveryStatus::Started {
+                return Err(Error::RecoveryNotActive)
+            }
+            if self.env().block_number() < r.not_before + self.get_config().recovery_expiry {
+                return Err(Error::RecoveryNotExpired)
+            }
+            // guardians that did not serve their share count against them
+            for (g, c) in u.guardians.iter().zip(r.confirmations.iter()) {
+                if !c.confirmed {
+                    let mut rep = self.reputation(g.node_id);
+                    rep.missed += 1;
+                    self.reputations.insert(g.node_id, &rep);
+                }
+            }
+            r.status = RecoveryStatus::Expired;
+            r.confirmations = vec![Confirmation::default(); u.guardians.len()];
+            // only attempts the user started itself count towards its lockout,
+            // anyone can start one for a new account
+            let own_attempt = r.new_owner.is_none();
+            self.refund_escrow(user, secret_id, &mut r)?;
+            if own_attempt {
+                self.record_failed_attempt(user, secret_id, &mut r);
+            }
+            self.save_recovery((user, secret_id), &r);
+            self.env().emit_event(RecoveryExpired {
+                user,
+                secret_id,
+                recovery_id: r.recovery_id,
+            });
+            Ok(())
+        }
+
+        // the recovery fee raised once per attempt the owner started within
+        // the fee curve's window, walking back from the latest one. cancelled
+        // and expired attempts count like finished ones, so restarting does
+        // not get around the curve. attempts started by someone else do not
+        // raise the owner's fee
+        fn recovery_cost(&self, uid: AccountId, secret_id: SecretId) -> Balance {
+            let config = self.get_config();
+            let curve = config.fee_curve;
+            let now = self.env().block_number();
+            let mut cost = config.recovery_fee;
+            let starts = self.recent_starts.get((uid, secret_id)).unwrap_or_default();
+            for started_at in starts.iter().rev().take(curve.max_steps as usize) {
+                if now - started_at >= curve.window {
+                    break
+                }
+                cost = cost.saturating_add(
+                    cost.saturating_mul(curve.step_bps as Balance) / MAX_BPS as Balance);
+            }
+            cost
+        }
+
+        fn record_recovery_start(&mut self, uid: AccountId, secret_id: SecretId,
+            now: BlockNumber) {
+            let max_steps = self.get_config().fee_curve.max_steps as usize;
+            let mut starts = self.recent_starts.get((uid, secret_id)).unwrap_or_default();
+            starts.push(now);
+            if starts.len() > max_steps {
+                starts.drain(..starts.len() - max_steps);
+            }
+            self.recent_starts.insert((uid, secret_id), &starts);
+        }
+
+        // what starting a recovery of the secret costs now
+        #[ink(message)]
+        pub fn recovery_quote(&self, user: AccountId, secret_id: SecretId) -> Balance {
+            self.recovery_cost(user, secret_id)
+        }
+
+        #[ink(message)]
+        pub fn fee_curve(&self) -> FeeCurve {
+            self.get_config().fee_curve
+        }
+
+        // split fee into the amount each of nodes guardians gets and the
+        // treasury cut, rounding dust goes to the treasury
+        fn split_fee(&self, fee: Balance, nodes: usize) -> (Balance, Balance) {
+            let treasury_cut = fee * self.get_config().treasury_bps as Balance / MAX_BPS as Balance;
+            if nodes == 0 {
+                return (0, fee)
+            }
+            let node_share = (fee - treasury_cut) / nodes as Balance;
+            (node_share, fee - node_share * nodes as Balance)
+        }
+
+        // every protocol parameter in one call
+        #[ink(message)]
+        pub fn get_config(&self) -> Config {
+            self.config.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn recovery_fee(&self) -> Balance {
+            self.get_config().recovery_fee
+        }
+
+        #[ink(message)]
+        pub fn treasury_bps(&self) -> u16 {
+            self.get_config().treasury_bps
+        }
+
+        #[ink(message)]
+        pub fn min_balance(&self) -> Balance {
+            self.get_config().min_balance
+        }
+
+        #[ink(message)]
+        pub fn registration_fee(&self) -> Balance {
+            self.get_config().registration_fee
+        }
+
+        fn refund_escrow(&mut self, user: AccountId, secret_id: SecretId,
+            r: &mut Recovery) -> Result<()> {
+            let payer = r.new_owner.unwrap_or(user);
+            self.pay_fee(r.fee_token, payer, r.escrowed)?;
+            if r.escrowed > 0 {
+                self.env().emit_event(FeeRefunded {
+                    user,
+                    secret_id,
+                    recovery_id: r.recovery_id,
+                    payer,
+                    amount: r.escrowed,
+                    fee_token: r.fee_token,
+                });
+            }
+            r.escrowed = 0;
+            r.new_owner = None;
+            Ok(())
+        }
+
+        // tokens the contract holds for the protocol, stakes, escrowed fees,
+        // the treasury and the reward pool, move to and from the contract
+        // account in Transfer events. a slash only moves tokens between those
+        // pools, NodeSlashed records it
+        fn pay_in(&mut self, from: AccountId, amount: Balance) -> Result<()> {
+            self.debit(from, amount)?;
+            if amount > 0 {
+                self.env().emit_event(Transfer {
+                    from: Some(from),
+                    to: Some(self.env().account_id()),
+                    value: amount,
+                });
+            }
+            Ok(())
+        }
+
+        fn pay_out(&mut self, to: AccountId, amount: Balance) -> Result<()> {
+            self.credit(to, amount)?;
+            if amount > 0 {
+                self.env().emit_event(Transfer {
+                    from: Some(self.env().account_id()),
+                    to: Some(to),
+                    value: amount,
+                });
+            }
+            Ok(())
+        }
+
+        // every change of a token balance goes through credit and debit
+        fn credit(&mut self, account: AccountId, amount: Balance) -> Result<()> {
+            let balance = safe_add(self.balance_of(account), amount)?;
+            self.balances.insert(account, &balance);
+            Ok(())
+        }
+
+        // tokens that voted stay put until voting ends, whatever would move them
+        fn debit(&mut self, account: AccountId, amount: Balance) -> Result<()> {
+            if self.balance_of(account) < safe_add(amount, self.vote_locked(account))? {
+                return Err(Error::InsufficientBalance)
+            }
+            let balance = safe_sub(self.balance_of(account), amount)?;
+            self.balances.insert(account, &balance);
+            Ok(())
+        }
+
+        // move a recovered secret to the account that asked for the recovery
+        fn migrate_user(&mut self, from: AccountId, secret_id: SecretId, to: AccountId) {
+            let key = (from, secret_id);
+            if let (Some(u), Some(r)) = (self.users.get(key), self.recoveries.get(key)) {
+                self.unassign_guardians(&u, secret_id);
+                self.remove_user_record(key);
+                self.remove_recovery(key);
+                // the new owner sets up its own reshare, switch, heirs and
+                // veto key. a node that already stored a share of the dropped
+                // reshare has to wipe it
+                if let Some(reshare) = self.reshares.get(key) {
+                    let stored: Vec<Guardian> = reshare.guardians.into_iter()
+                        .zip(reshare.acknowledged.into_iter())
+                        .filter(|(_, acked)| *acked)
+                        .map(|(g, _)| g)
+                        .collect();
+                    self.request_dropped_share_deletion(from, secret_id, &stored, &u.guardians);
+                }
+                self.reshares.remove(key);
+                self.veto_keys.remove(key);
+                self.veto_keys.remove((to, secret_id));
+                self.dead_man_switches.remove(key);
+                self.beneficiaries.remove(key);
+                match self.social_guardians.get(key) {
+                    Some(social) => {
+                        self.social_guardians.remove(key);
+                        self.social_guardians.insert((to, secret_id), &social);
+                    }
+                    None => self.social_guardians.remove((to, secret_id)),
+                }
+                // the prepaid rent stays with the secret
+                if let Some(sub) = self.subscriptions.get(key) {
+                    self.subscriptions.remove(key);
+                    self.subscriptions.insert((to, secret_id), &sub);
+                }
+                self.recovery_requests.remove((to, secret_id));
+                let u = User { uid: to, ..u };
+                self.assign_guardians(&u, secret_id);
+                self.save_user((to, secret_id), &u);
+                // a secret the new owner deleted under the same id left its
+                // history there, the moved records go after it
+                let previous = self.recoveries.get((to, secret_id));
+                let offset = previous.as_ref().map_or(0, |p| p.r_times);
+                for i in 0..r.r_times {
+                    if let Some(record) = self.recovery_history.get((from, secret_id, i)) {
+                        self.recovery_history.remove((from, secret_id, i));
+                        self.recovery_history.insert((to, secret_id, offset + i), &record);
+                    }
+                }
+                if let Some(starts) = self.recent_starts.get(key) {
+                    self.recent_starts.remove(key);
+                    self.recent_starts.insert((to, secret_id), &starts);
+                }
+                self.save_recovery((to, secret_id), &Recovery {
+                    uid: to,
+                    new_owner: None,
+                    r_times: offset + r.r_times,
+                    ..r
+                });
+                self.env().emit_event(UserMigrated { from, to, secret_id });
+            }
+        }
+
+        // number of finished recoveries, their records are kept under the
+        // indexes 0 up to this count
+        #[ink(message)]
+        pub fn recovery_count(&self, user: AccountId, secret_id: SecretId) -> u32 {
+            self.recoveries.get((user, secret_id)).map(|r| r.r_times).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn get_recovery(&self, user: AccountId, secret_id: SecretId) -> Option<RecoveryView> {
+            let u = self.users.get((user, secret_id))?;
+            let r = self.recoveries.get((user, secret_id))?;
+            Some(RecoveryView {
+                status: r.status,
+                recovery_id: r.recovery_id,
+                confirmed_by: u.guardians.iter().zip(r.confirmations.iter())
+                    .filter(|(_, c)| c.confirmed)
+                    .map(|(g, _)| g.node_id)
+                    .collect(),
+                confirmed_weight: Self::confirmed_weight(&u, &r),
+                threshold: u.threshold,
+                started_at: r.started_at,
+                finalizable_at: r.not_before,
+                completed: r.r_times,
+                failed_attempts: r.failed_attempts,
+                locked: r.locked,
+                new_owner: r.new_owner,
+                request: self.recovery_requests.get((user, secret_id)),
+            })
+        }
+
+        #[ink(message)]
+        pub fn recovery_record(&self, user: AccountId, secret_id: SecretId,
+            index: u32) -> Option<RecoveryRecord> {
+            self.recovery_history.get((user, secret_id, index))
+        }
+
+        #[ink(message)]
+        pub fn share_version_of(&self, user: AccountId, secret_id: SecretId) -> Option<u32> {
+            self.users.get((user, secret_id)).map(|u| u.share_version)
+        }
+
+        #[ink(message)]
+        pub fn escrow_of(&self, user: AccountId, secret_id: SecretId) -> Balance {
+            self.recoveries.get((user, secret_id)).map(|r| r.escrowed).unwrap_or_default()
+        }
+
+        fn record_failed_attempt(&self, user: AccountId, secret_id: SecretId, r: &mut Recovery) {
+            r.failed_attempts += 1;
+            if !r.locked && r.failed_attempts >= self.get_config().max_failed_attempts {
+                r.locked = true;
+                self.env().emit_event(RecoveryLocked {
+                    user,
+                    secret_id,
+                    failed_attempts: r.failed_attempts,
+                });
+            }
+        }
+
+        // first step of unlocking, the unlock itself waits unlock_delay blocks
+        #[ink(message)]
+        pub fn request_unlock(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if !r.locked {
+                return Err(Error::RecoveryNotLocked)
+            }
+            let now = self.env().block_number();
+            r.unlock_requested_at = Some(now);
+            self.save_recovery((sender, secret_id), &r);
+            self.env().emit_event(UnlockRequested {
+                user: sender,
+                secret_id,
+                unlock_block: now + self.get_config().unlock_delay,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn unlock_recovery(&mut self, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let sender = self.env().caller();
+            let mut r = self.recoveries.get((sender, secret_id)).ok_or(Error::UserNotFound)?;
+            if !r.locked {
+                return Err(Error::RecoveryNotLocked)
+            }
+            let requested_at = r.unlock_requested_at.ok_or(Error::UnlockNotReady)?;
+            if self.env().block_number() < requested_at + self.get_config().unlock_delay {
+                return Err(Error::UnlockNotReady)
+            }
+            r.locked = false;
+            r.failed_attempts = 0;
+            r.unlock_requested_at = None;
+            self.save_recovery((sender, secret_id), &r);
+            self.env().emit_event(RecoveryUnlocked { user: sender, secret_id });
+            Ok(())
+        }
+
+        // guardians that see signs of a compromise, e.g. phishing reports,
+        // vote to freeze the secret. once their weight reaches the threshold
+        // recovery is locked, a running attempt is cancelled, and the user
+        // can neither start one nor rotate its key until it unlocks through
+        // request_unlock
+        #[ink(message)]
+        pub fn freeze_user(&mut self, user: AccountId, secret_id: SecretId) -> Result<()> {
+            self.ensure_not_paused()?;
+            let node = self.calling_node();
+            let u = self.users.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            let mut r = self.recoveries.get((user, secret_id)).ok_or(Error::UserNotFound)?;
+            if !u.guardians.iter().any(|g| g.node_id == node) {
+                return Err(Error::NotAssignedGuardian)
+            }
+            if r.locked {
+                return Err(Error::RecoveryLocked)
+            }
+            let mut votes = self.freeze_votes.get((user, secret_id)).unwrap_or_default();
+            if votes.contains(&node) {
+                return Err(Error::AlreadyVoted)
+            }
+            votes.push(node);
+            self.env().emit_event(FreezeVoted { user, secret_id, node });
+            // votes of nodes replaced since do not count
+            let weight = Self::total_weight(u.guardians.iter().filter(|g| votes.contains(&g.node_id)));
+            if weight < u.threshold as u64 {
+                self.freeze_votes.insert((user, secret_id), &votes);
+                return Ok(())
+            }
+
+            self.freeze_votes.remove((user, secret_id));
+            if Self::is_active(&r) {
+                r.status = RecoveryStatus::Cancelled;
+                r.confirmations = vec![Confirmation::default(); u.guardians.len()];
+                self.refund_escrow(user, secret_id, &mut r)?;
+                self.env().emit_event(RecoveryCancelled {
+                    user,
+                    secret_id,
+                    recovery_id: r.recovery_id,
+                });
+            }
+            r.locked = true;
+            r.unlock_requested_at = None;
+            self.save_recovery((user, secret_id), &r);
+            self.env().emit_event(UserFrozen { user, secret_id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn freeze_votes(&self, user: AccountId, secret_id: SecretId) -> Vec<AccountId> {
+            self.freeze_votes.get((user, secret_id)).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn max_failed_attempts(&self) -> u32 {
+            self.get_config().max_failed_attempts
+        }
+
+        #[ink(message)]
+        pub fn unlock_delay(&self) -> BlockNumber {
+            self.get_config().unlock_delay
+        }
+
+        #[ink(message)]
+        pub fn recovery_cooldown(&self) -> BlockNumber {
+            self.get_config().recovery_cooldown
+        }
+
+        #[ink(message)]
+        pub fn rate_limit(&self) -> RateLimit {
+            self.get_config().rate_limit
+        }
+
+        // counts a recovery start in the current window, so spamming guardians
+        // with recovery requests runs out of starts. counted per paying account
+        // rather than per user, a stranger can not use up the user's starts
+        fn take_recovery_start(&mut self, payer: AccountId) -> Result<()> {
+            let limit = self.get_config().rate_limit;
+            if limit.window == 0 {
+                return Ok(())
+            }
+            let now = self.env().block_number();
+            let window_start = now - now % limit.window;
+            let in_window = |(start, starts): (BlockNumber, u32)| {
+                if start == window_start { starts } else { 0 }
+            };
+            let total = in_window(self.recovery_starts.get().unwrap_or_default());
+            let own = in_window(self.account_recovery_starts.get(payer).unwrap_or_default());
+            if (limit.max_starts > 0 && total >= limit.max_starts)
+                || (limit.max_starts_per_account > 0 && own >= limit.max_starts_per_account) {
+                return Err(Error::RateLimited(window_start + limit.window))
+            }
+            self.recovery_starts.set(&(window_start, total + 1));
+            self.account_recovery_starts.insert(payer, &(window_start, own + 1));
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn recovery_expiry(&self) -> BlockNumber {
+            self.get_config().recovery_expiry
+        }
+
+        // the signature must recover to the key the node registered with
+        fn verify_recovery_proof(&self, node: &Node, user: &AccountId, secret_id: SecretId,
+            recovery_id: RecoveryId, proof: &RecoveryProof) -> Result<()> {
+            let digest = Self::recovery_digest(&self.env().account_id(), user, secret_id,
+                recovery_id, proof.share_version);
+            let signed_by = |pub_k: &PublicKey| match node.key_type {
+                KeyType::Secp256k1 => self.is_signed_by(pub_k, &digest, &proof.signature),
+                KeyType::Ethereum => self.is_signed_by_eth(pub_k, &digest, &proof.signature),
+            };
+            // during a key transition the node may still sign with the old key
+            let valid = signed_by(&node.pub_k) || match &node.previous_key {
+                Some((old_pub_k, until)) => self.env().block_number() < *until && signed_by(old_pub_k),
+                None => false,
+            };
+            if !valid {
+                return Err(Error::InvalidProof)
+            }
+            Ok(())
+        }
+
+        // asks the verifier of the guardian's condition type, if one is set
+        fn verify_condition_proof(&self, guardian: &Guardian, user: &AccountId,
+            secret_id: SecretId, recovery_id: RecoveryId, proof: &Vec<u8>) -> Result<()> {
+            let verifier = match self.condition_verifiers.get(guardian.cond_type) {
+                Some(verifier) => verifier,
+                None => return Ok(())
+            };
+            if proof.is_empty() {
+                return Err(Error::InvalidConditionProof)
+            }
+            let valid = build_call::<Environment>()
+                .call_type(Call::new().callee(verifier))
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("ConditionVerifier::verify")))
+                        .push_arg(user)
+                        .push_arg(secret_id)
+                        .push_arg(recovery_id)
+                        .push_arg(guardian.cond_commitment)
+                        .push_arg(proof))
+                .returns::<bool>()
+                .fire()
+                .unwrap_or(false);
+            if !valid {
+                return Err(Error::InvalidConditionProof)
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn condition_verifier(&self, cond_type: ConditionType) -> Option<AccountId> {
+            self.condition_verifiers.get(cond_type)
+        }
+
+        // the digest is signed as a personal message the way Ethereum
+        // wallets do
+        fn is_signed_by_eth(&self, pub_k: &PublicKey, digest: &[u8; 32],
+            signature: &[u8; 65]) -> bool {
+            let message = Self::eth_message_hash(digest);
+            self.is_signed_by(pub_k, &message, signature)
+        }
+
+        fn eth_message_hash(digest: &[u8; 32]) -> [u8; 32] {
+            let mut message = b"\x19Ethereum Signed Message:\n32".to_vec();
+            message.extend_from_slice(digest);
+            let mut hash = <Keccak256 as HashOutput>::Type::default();
+            ink::env::hash_bytes::<Keccak256>(&message, &mut hash);
+            hash
+        }
+
+        fn is_signed_by(&self, pub_k: &PublicKey, digest: &[u8; 32],
+            signature: &[u8; 65]) -> bool {
+            self.env().ecdsa_recover(signature, digest)
+                .map_or(false, |recovered| recovered == *pub_k)
+        }
+
+        fn key_rotation_digest(contract: &AccountId, user: &AccountId, secret_id: SecretId,
+            old_pub_k: &PublicKey, new_pub_k: &PublicKey) -> [u8; 32] {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(
+                &(contract, user, secret_id, old_pub_k, new_pub_k), &mut digest);
+            digest
+        }
+
+        fn relayed_recovery_digest(contract: &AccountId, user: &AccountId,
+            secret_id: SecretId, nonce: u64) -> [u8; 32] {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(&(contract, user, secret_id, nonce),
+                &mut digest);
+            digest
+        }
+
+        fn proof_hash(proof: &RecoveryProof) -> Hash {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(proof, &mut digest);
+            Hash::from(digest)
+        }
+
+        // hash of the proof the guardian confirmed the current attempt with,
+        // to check a RecoveryConfirmed event against
+        #[ink(message)]
+        pub fn confirmation_proof_hash(&self, user: AccountId, secret_id: SecretId,
+            node: AccountId) -> Option<Hash> {
+            let u = self.users.get((user, secret_id))?;
+            let r = self.recoveries.get((user, secret_id))?;
+            let index = u.guardians.iter().position(|g| g.node_id == node)?;
+            r.confirmations.get(index)?.proof_hash
+        }
+
+        fn recovery_digest(contract: &AccountId, user: &AccountId, secret_id: SecretId,
+            recovery_id: RecoveryId, share_version: u32) -> [u8; 32] {
+            let mut digest = <Blake2x256 as HashOutput>::Type::default();
+            ink::env::hash_encoded::<Blake2x256, _>(
+                &(contract, user, secret_id, recovery_id, share_version), &mut digest);
+            digest
+        }
+
+        // checks of externally supplied values, anything that fails them is
+        // rejected with InvalidInput instead of being stored.
+        // a key has to start with the 0x02 or 0x03 of a compressed secp256k1
+        // key. whether the point is on the curve is not checked, a key off
+        // the curve can not sign anything and so only locks out its owner
+        fn ensure_compressed_key_prefix(pub_k: &PublicKey) -> Result<()> {
+            if pub_k[0] != 0x02 && pub_k[0] != 0x03 {
+                return Err(Error::InvalidInput)
+            }
+            Ok(())
+        }
+
+        fn ensure_max_len(len: usize, max: usize) -> Result<()> {
+            if len > max {
+                return Err(Error::InvalidInput)
+            }
+            Ok(())
+        }
+
+        // empty, or an https URL of printable ascii without spaces
+        fn ensure_valid_endpoint(endpoint: &str) -> Result<()> {
+            Self::ensure_max_len(endpoint.len(), MAX_ENDPOINT_LEN)?;
+            let valid = endpoint.is_empty() || (endpoint.starts_with("https://")
+                && endpoint.bytes().all(|b| b.is_ascii_graphic()));
+            if !valid {
+                return Err(Error::InvalidInput)
+            }
+            Ok(())
+        }
+
+        // empty, or a code like "eu-west" of lowercase letters, digits and -
+        fn ensure_valid_region(region: &str) -> Result<()> {
+            Self::ensure_max_len(region.len(), MAX_REGION_LEN)?;
+            if !region.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-') {
+                return Err(Error::InvalidInput)
+            }
+            Ok(())
+        }
+
+        // storage version 1 carries keys hex encoded, with or without 0x
+        // prefix
+        fn decode_pub_key(pub_k: &str) -> Option<PublicKey> {
+            let pub_k = Self::decode_hex(pub_k)?;
+            Self::ensure_compressed_key_prefix(&pub_k).ok()?;
+            Some(pub_k)
+        }
+
+        fn decode_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+            let hex = value.strip_prefix("0x").unwrap_or(value).as_bytes();
+            if hex.len() != 2 * N {
+                return None
+            }
+            let mut key = [0u8; N];
+            for (i, pair) in hex.chunks(2).enumerate() {
+                let hi = (pair[0] as char).to_digit(16)?;
+                let lo = (pair[1] as char).to_digit(16)?;
+                key[i] = (hi * 16 + lo) as u8;
+            }
+            Some(key)
+        }
+    }
+
+
+    impl PSP22 for KeyLedger {
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            self.total_supply
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(&owner).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get((owner, spender)).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance, _data: Vec<u8>) -> PSP22Result<()> {
+            let from = self.env().caller();
+            self.transfer_from_to(&from, &to, value)?;
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance,
+            _data: Vec<u8>,
+        ) -> PSP22Result<()> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(from, caller);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance)
+            }
+            self.transfer_from_to(&from, &to, value)?;
+            self.approve_from_to(from, caller, allowance - value);
+            Ok(())
+        }
+
+        // allow spender to move up to value tokens out of the caller's balance
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            self.approve_from_to(owner, spender, value);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: AccountId, delta_value: Balance) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            let allowance = self.allowance(owner, spender);
+            self.approve_from_to(owner, spender, allowance.saturating_add(delta_value));
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: AccountId, delta_value: Balance) -> PSP22Result<()> {
+            let owner = self.env().caller();
+            let allowance = self.allowance(owner, spender);
+            if allowance < delta_value {
+                return Err(PSP22Error::InsufficientAllowance)
+            }
+            self.approve_from_to(owner, spender, allowance - delta_value);
+            Ok(())
+        }
+    }
+
+
+    impl PSP22Metadata for KeyLedger {
+        #[ink(message)]
+        fn token_name(&self) -> Option<String> {
+            self.name.get()
+        }
+
+        #[ink(message)]
+        fn token_symbol(&self) -> Option<String> {
+            self.symbol.get()
+        }
+
+        #[ink(message)]
+        fn token_decimals(&self) -> u8 {
+            self.decimals.get().unwrap_or_default()
+        }
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        const SECRET: SecretId = 0;
+
+        /// Builds a guardian list with cond_type Email, Phone, OAuth, ... in order.
+        fn guardians(nodes: &[AccountId]) -> Vec<Guardian> {
+            nodes.iter().enumerate().map(|(i, n)| Guardian {
+                node_id: *n,
+                cond_type: ConditionType::try_from((i % 5 + 1) as u8).unwrap(),
+                cond_commitment: Hash::default(),
+                share_commitment: Hash::default(),
+                weight: 1,
+            }).collect()
+        }
+
+        /// Stands in for a user's key where no signature is checked.
+        const USER_PUB_K: PublicKey = [2; 33];
+
+        /// Proposes the registration and has every guardian acknowledge its
+        /// share, the caller is the user again afterwards.
+        fn register_user(kl: &mut KeyLedger, secret_id: SecretId, pub_k: PublicKey,
+            guardians: Vec<Guardian>, threshold: u32) -> Result<()> {
+            let user = ink::env::caller::<ink::env::DefaultEnvironment>();
+            kl.propose_registration(secret_id, pub_k, guardians.clone(), threshold, None)?;
+            for g in guardians.iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(g.node_id);
+                kl.ack_share_stored(user, secret_id, g.share_commitment).unwrap();
+            }
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            Ok(())
+        }
+
+        /// Context a user's own device sends with start_recovery.
+        fn recovery_request() -> RecoveryRequest {
+            RecoveryRequest {
+                reason: RecoveryReason::LostDevice,
+                device_commitment: Hash::default(),
+                target_pub_k: None,
+            }
+        }
+
+        /// A dummy report, accepted as long as no attestation signer is set.
+        fn no_attestation() -> AttestationReport {
+            AttestationReport {
+                measurement: Hash::default(),
+                signature: [0; 65],
+            }
+        }
+
+        fn public_key(secret: &[u8; 32]) -> [u8; 33] {
+            let secp = secp256k1::Secp256k1::new();
+            let sk = secp256k1::SecretKey::from_slice(secret).unwrap();
+            secp256k1::PublicKey::from_secret_key(&secp, &sk).serialize()
+        }
+
+        /// Signs a message hash the way `ecdsa_recover` expects it.
+        fn sign(secret: &[u8; 32], message_hash: &[u8; 32]) -> [u8; 65] {
+            let secp = secp256k1::Secp256k1::new();
+            let sk = secp256k1::SecretKey::from_slice(secret).unwrap();
+            let msg = secp256k1::Message::from_slice(message_hash).unwrap();
+            let (rec_id, sig) = secp.sign_ecdsa_recoverable(&msg, &sk).serialize_compact();
+            let mut signature = [0u8; 65];
+            signature[..64].copy_from_slice(&sig);
+            signature[64] = rec_id.to_i32() as u8;
+            signature
+        }
+
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+
+        /// Test nodes sign with their account bytes as secret key.
+        fn node_secret(node: &AccountId) -> [u8; 32] {
+            *node.as_ref()
+        }
+
+        fn node_pub_k(node: AccountId) -> PublicKey {
+            public_key(&node_secret(&node))
+        }
+
+        /// The proof `node` hands in for recovery `recovery_id` of a user's
+        /// secret, serving the shares it got at registration.
+        fn recovery_proof(node: AccountId, user: AccountId, secret_id: SecretId,
+            recovery_id: RecoveryId) -> RecoveryProof {
+            versioned_proof(node, user, secret_id, recovery_id, 0)
+        }
+
+        fn versioned_proof(node: AccountId, user: AccountId, secret_id: SecretId,
+            recovery_id: RecoveryId, share_version: u32) -> RecoveryProof {
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let digest = KeyLedger::recovery_digest(&contract, &user, secret_id, recovery_id,
+                share_version);
+            RecoveryProof {
+                share_version,
+                signature: sign(&node_secret(&node), &digest),
+                condition_proof: Vec::new(),
+            }
+        }
+
+        /// Queues an admin action, waits out the timelock delay and executes
+        /// it.
+        fn run_action(kl: &mut KeyLedger, action: AdminAction) -> Result<()> {
+            let action_id = kl.queue_action(action)?;
+            for _ in 0..kl.timelock_delay() {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.execute_action(action_id)
+        }
+
+        fn block_number() -> BlockNumber {
+            ink::env::block_number::<ink::env::DefaultEnvironment>()
+        }
+
+        /// Registers every account in `nodes` as a node, leaves the caller set
+        /// to the last of them.
+        fn register_nodes(kl: &mut KeyLedger, nodes: &[AccountId]) {
+            for n in nodes.iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*n);
+                kl.register_node(node_pub_k(*n), KeyType::Secp256k1, no_attestation()).unwrap();
+            }
+        }
+
+        /// We test if the default constructor does its job.
+        #[ink::test]
+        fn test_default_works() {
+            let kl = KeyLedger::new(100);
+            assert_eq!(kl.total_supply(), 100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.balance_of(accounts.alice), 100);
+        }
+
+        #[ink::test]
+        fn test_metadata() {
+            let kl = KeyLedger::new_with_metadata(100, Some("KeySafe".to_string()),
+                Some("KSF".to_string()), 12);
+            assert_eq!(kl.token_name(), Some("KeySafe".to_string()));
+            assert_eq!(kl.token_symbol(), Some("KSF".to_string()));
+            assert_eq!(kl.token_decimals(), 12);
+            assert_eq!(KeyLedger::new(100).token_name(), None);
+        }
+
+        #[ink::test]
+        fn test_checked_math() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::Mint(accounts.bob, Balance::MAX - 100)).unwrap();
+            assert_eq!(run_action(&mut kl, AdminAction::Mint(accounts.bob, 1)),
+                Err(Error::Overflow));
+            assert_eq!(run_action(&mut kl, AdminAction::Burn(accounts.alice, 101)),
+                Err(Error::InsufficientBalance));
+            assert_eq!(run_action(&mut kl, AdminAction::WithdrawTreasury(accounts.bob, 1)),
+                Err(Error::InsufficientBalance));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.fund_rewards(Balance::MAX), Err(Error::InsufficientBalance));
+            assert_eq!(kl.transfer(accounts.alice, Balance::MAX, Vec::new()),
+                Err(PSP22Error::InsufficientBalance));
+            kl.transfer(accounts.alice, Balance::MAX - 100, Vec::new()).unwrap();
+            assert_eq!(kl.balance_of(accounts.alice), Balance::MAX);
+        }
+
+        #[ink::test]
+        fn test_mint_and_burn() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.owner(), accounts.alice);
+            run_action(&mut kl, AdminAction::Mint(accounts.bob, 50)).unwrap();
+            assert_eq!(kl.balance_of(accounts.bob), 50);
+            assert_eq!(kl.total_supply(), 150);
+            run_action(&mut kl, AdminAction::Burn(accounts.bob, 20)).unwrap();
+            assert_eq!(kl.balance_of(accounts.bob), 30);
+            assert_eq!(kl.total_supply(), 130);
+            assert_eq!(run_action(&mut kl, AdminAction::Burn(accounts.bob, 31)),
+                Err(Error::InsufficientBalance));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(run_action(&mut kl, AdminAction::Mint(accounts.bob, 1)),
+                Err(Error::NotOwner));
+            assert_eq!(run_action(&mut kl, AdminAction::Burn(accounts.alice, 1)),
+                Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn test_transfer() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            kl.transfer(accounts.bob, 10, Vec::new());
+            assert_eq!(kl.balance_of(accounts.bob), 10);            
+            assert_eq!(kl.balance_of(accounts.alice), 90);
+        }
+
+        #[ink::test]
+        fn test_transfer_ownership() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            kl.transfer_ownership(accounts.bob).unwrap();
+            assert_eq!(kl.pending_owner(), Some(accounts.bob));
+            // nothing changes before bob accepts
+            assert_eq!(kl.owner(), accounts.alice);
+            run_action(&mut kl, AdminAction::Mint(accounts.alice, 1)).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.accept_ownership(), Err(Error::NotPendingOwner));
+            assert_eq!(kl.transfer_ownership(accounts.charlie), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.accept_ownership().unwrap();
+            assert_eq!(kl.owner(), accounts.bob);
+            assert_eq!(kl.pending_owner(), None);
+            assert_eq!(kl.accept_ownership(), Err(Error::NotPendingOwner));
+            run_action(&mut kl, AdminAction::Mint(accounts.bob, 1)).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(run_action(&mut kl, AdminAction::Mint(accounts.alice, 1)),
+                Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn test_upgrade_needs_owner() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.contract_version(), 1);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.queue_action(AdminAction::Upgrade(Hash::from([1; 32]))),
+                Err(Error::NotOwner));
+            assert_eq!(kl.contract_version(), 1);
+        }
+
+        #[ink::test]
+        fn test_timelock() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetTimelockDelay(2)).unwrap();
+            assert_eq!(kl.timelock_delay(), 2);
+
+            let id = kl.queue_action(AdminAction::SetRecoveryFee(7)).unwrap();
+            assert_eq!(kl.queued_action(id).unwrap().eta, block_number() + 2);
+            assert_eq!(kl.execute_action(id), Err(Error::ActionNotReady(2)));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.execute_action(id), Err(Error::MissingRole));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.execute_action(id).unwrap();
+            assert_eq!(kl.recovery_fee(), 7);
+            assert_eq!(kl.execute_action(id), Err(Error::ActionNotFound));
+
+            let id = kl.queue_action(AdminAction::SetMinBalance(0)).unwrap();
+            kl.cancel_action(id).unwrap();
+            assert_eq!(kl.queued_action(id), None);
+            assert_eq!(kl.min_balance(), 3);
+        }
+
+        #[ink::test]
+        fn test_council() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetTimelockDelay(0)).unwrap();
+            assert_eq!(kl.queue_action(AdminAction::SetCouncil(vec![accounts.alice], 2)),
+                Err(Error::InvalidCouncil));
+            assert_eq!(kl.queue_action(AdminAction::SetCouncil(vec![accounts.bob, accounts.bob], 2)),
+                Err(Error::InvalidCouncil));
+            let council = vec![accounts.alice, accounts.bob, accounts.charlie];
+            run_action(&mut kl, AdminAction::SetCouncil(council.clone(), 2)).unwrap();
+            assert_eq!(kl.council(), (council, 2));
+
+            // alice proposes and approves, one more member is needed
+            let id = kl.queue_action(AdminAction::SetRecoveryFee(7)).unwrap();
+            assert_eq!(kl.action_approvals(id), 1);
+            assert_eq!(kl.execute_action(id), Err(Error::QuorumNotReached));
+            assert_eq!(kl.approve_action(id), Err(Error::AlreadyApproved));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.approve_action(id), Err(Error::NotCouncilMember));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.approve_action(id + 1), Err(Error::ActionNotFound));
+            kl.approve_action(id).unwrap();
+            assert_eq!(kl.action_approvals(id), 2);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.execute_action(id).unwrap();
+            assert_eq!(kl.recovery_fee(), 7);
+        }
+
+        #[ink::test]
+        fn test_governance() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetVotingPeriod(2)).unwrap();
+            kl.transfer(accounts.bob, 30, Vec::new()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.propose(AdminAction::SetRecoveryFee(8)), Err(Error::InsufficientBalance));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.propose(AdminAction::Upgrade(Hash::from([1; 32]))),
+                Err(Error::NotGovernable));
+            let id = kl.propose(AdminAction::SetRecoveryFee(8)).unwrap();
+            kl.vote(id, true).unwrap();
+            assert_eq!(kl.vote(id, true), Err(Error::AlreadyVoted));
+            // the voted tokens are locked until voting ends
+            assert_eq!(kl.transfer(accounts.charlie, 1, Vec::new()),
+                Err(PSP22Error::InsufficientBalance));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.vote(id, false).unwrap();
+            assert_eq!(kl.proposal(id).unwrap().votes_against, 70);
+            assert_eq!(kl.execute_proposal(id), Err(Error::VotingOpen(3)));
+            for _ in 0..3 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.vote(id, true), Err(Error::VotingClosed));
+            // alice outvoted bob
+            assert_eq!(kl.execute_proposal(id), Err(Error::ProposalNotPassed));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.transfer(accounts.charlie, 1, Vec::new()).unwrap();
+            let id = kl.propose(AdminAction::SetRecoveryFee(8)).unwrap();
+            kl.vote(id, true).unwrap();
+            for _ in 0..3 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.execute_proposal(id).unwrap();
+            assert_eq!(kl.recovery_fee(), 8);
+            assert_eq!(kl.execute_proposal(id), Err(Error::ProposalNotPassed));
+        }
+
+        /// Clears the cells a version 1 constructor did not write, it only
+        /// minted the supply to the deployer, and writes the version 1
+        /// records of `nodes` and `user` under the version 1 keys.
+        fn write_version_1(nodes: &[AccountId], user: AccountId, cond_types: [u8; 3],
+            r_times: u32) {
+            for field in ["owner", "paused", "storage_version", "council", "council_quorum"] {
+                let key = KeyComposer::from_str(&format!("KeyLedger::{}", field));
+                ink::env::clear_contract_storage(&key);
+            }
+            for n in nodes.iter() {
+                ink::env::set_contract_storage(&(V1_NODES_KEY, *n), &LegacyNode {
+                    nid: *n,
+                    pub_k: hex(&node_pub_k(*n)),
+                });
+            }
+            ink::env::set_contract_storage(&(V1_USERS_KEY, user), &LegacyUser {
+                uid: user,
+                pub_k: hex(&USER_PUB_K),
+                node1_cond_type: cond_types[0],
+                node1_id: nodes[0],
+                node2_cond_type: cond_types[1],
+                node2_id: nodes[1],
+                node3_cond_type: cond_types[2],
+                node3_id: nodes[2],
+            });
+            ink::env::set_contract_storage(&(V1_RECOVERIES_KEY, user), &LegacyRecovery {
+                status: 1,
+                uid: user,
+                r_times,
+                recovery1_proof: String::new(),
+                node1_confirm: 1,
+                recovery2_proof: String::new(),
+                node2_confirm: 0,
+                recovery3_proof: String::new(),
+                node3_confirm: 0,
+            });
+        }
+
+        #[ink::test]
+        fn test_migrate_legacy_users() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.migrate(vec![accounts.bob]), Err(Error::AlreadyMigrated));
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            write_version_1(&nodes, accounts.bob, [1, 2, 3], 4);
+            write_version_1(&nodes, accounts.frank, [1, 9, 3], 0);
+            assert_eq!(kl.storage_version(), 1);
+            assert!(kl.paused());
+
+            // anyone can migrate, users wait for their guardians
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.migrate(vec![accounts.bob]), Ok(0));
+            assert_eq!(kl.migrate(vec![nodes[0], nodes[1], nodes[2], accounts.bob,
+                accounts.frank]), Ok(4));
+            assert_eq!(kl.migrate(vec![nodes[0], accounts.bob]), Ok(0));
+            assert_eq!(kl.get_nodes(0, 10), nodes.to_vec());
+            assert_eq!(kl.get_node(nodes[0]).unwrap().pub_k, node_pub_k(nodes[0]));
+            assert_eq!(kl.guardians_of(accounts.bob, 0), guardians(&nodes));
+            assert_eq!(kl.get_user(accounts.bob, 0).unwrap().threshold, 2);
+            assert_eq!(kl.recovery_count(accounts.bob, 0), 4);
+            assert_eq!(kl.get_user(accounts.frank, 0), None);
+            assert_eq!(kl.verify_new_user(0, USER_PUB_K), true);
+            assert_eq!(kl.start_recovery(0, recovery_request()), Err(Error::Paused));
+
+            // only the holder of most of the supply takes the contract over
+            assert_eq!(kl.complete_migration(), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.complete_migration().unwrap();
+            assert_eq!(kl.owner(), accounts.alice);
+            assert_eq!(kl.council(), (vec![accounts.alice], 1));
+            assert_eq!(kl.storage_version(), STORAGE_VERSION);
+            assert!(!kl.paused());
+            assert_eq!(kl.migrate(vec![accounts.frank]), Err(Error::AlreadyMigrated));
+            assert_eq!(kl.complete_migration(), Err(Error::AlreadyMigrated));
+        }
+
+        #[ink::test]
+        fn test_migrate_blacklisted_and_broken_nodes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::GrantRole(Role::Slasher, accounts.alice)).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            write_version_1(&nodes, accounts.bob, [1, 2, 3], 0);
+
+            // a guardian blacklisted once migrated does not hold its users back
+            assert_eq!(kl.migrate(nodes.to_vec()), Ok(3));
+            kl.blacklist_node(nodes[2]).unwrap();
+            assert_eq!(kl.migrate(vec![nodes[2], accounts.bob]), Ok(1));
+            assert_eq!(kl.guardians_of(accounts.bob, 0), guardians(&nodes));
+            assert_eq!(kl.get_nodes(0, 10), nodes[..2].to_vec());
+
+            // records the nodes mapping can not read are moved out of its way
+            let bad_key = AccountId::from([9; 32]);
+            let garbage = AccountId::from([8; 32]);
+            let stranded = LegacyNode { nid: bad_key, pub_k: String::from("not a key") };
+            ink::env::set_contract_storage(&(V1_NODES_KEY, bad_key), &stranded);
+            ink::env::set_contract_storage(&(V1_NODES_KEY, garbage), &7u8);
+            assert_eq!(kl.migrate(vec![bad_key, garbage]), Ok(0));
+            assert_eq!(kl.get_node(bad_key), None);
+            assert_eq!(kl.get_node(garbage), None);
+            assert_eq!(ink::env::get_contract_storage::<_, LegacyNode>(
+                &(V1_STRANDED_NODES_KEY, bad_key)).unwrap(), Some(stranded));
+        }
+
+        #[ink::test]
+        fn test_condition_types() {
+            assert_eq!(ConditionType::try_from(2), Ok(ConditionType::Phone));
+            assert_eq!(ConditionType::try_from(0), Err(Error::UnknownCondition));
+            assert_eq!(ConditionType::try_from(6), Err(Error::UnknownCondition));
+            assert_eq!(scale::Encode::encode(&ConditionType::TrustedContact), vec![5]);
+
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_nodes(&mut kl, &[accounts.charlie]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            let salt = Hash::from([7; 32]);
+            let email = b"bob@example.com".to_vec();
+            let g = vec![Guardian {
+                node_id: accounts.charlie,
+                cond_type: ConditionType::OAuth,
+                cond_commitment: kl.condition_commitment(salt, email.clone()),
+                share_commitment: Hash::default(),
+                weight: 1,
+            }];
+            register_user(&mut kl, SECRET, USER_PUB_K, g.clone(), 1).unwrap();
+            assert_eq!(kl.guardians_of(accounts.bob, SECRET), g);
+            assert!(kl.verify_condition(accounts.bob, SECRET, accounts.charlie, salt, email.clone()));
+            assert!(!kl.verify_condition(accounts.bob, SECRET, accounts.charlie, salt,
+                b"eve@example.com".to_vec()));
+            assert!(!kl.verify_condition(accounts.bob, SECRET, accounts.charlie,
+                Hash::default(), email));
+            assert_eq!(kl.guardians_of(accounts.bob, 1), Vec::new());
+        }
+
+        #[ink::test]
+        fn test_get_user() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.get_user(accounts.bob, SECRET), None);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            assert_eq!(kl.get_user(accounts.bob, SECRET), Some(UserView {
+                pub_k: USER_PUB_K,
+                guardians: nodes.to_vec(),
+                cond_types: vec![ConditionType::Email, ConditionType::Phone],
+                weights: vec![1, 1],
+                threshold: 2,
+                share_version: 0,
+                deletion_requested_at: None,
+                active: true,
+            }));
+            assert_eq!(kl.get_user(accounts.bob, 1), None);
+        }
+
+        #[ink::test]
+        fn test_share_commitments() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            let mut g = guardians(&nodes[..2]);
+            g[0].share_commitment = kl.share_commitment(vec![1, 2, 3]);
+            g[1].share_commitment = kl.share_commitment(vec![4, 5, 6]);
+            register_user(&mut kl, SECRET, USER_PUB_K, g, 1).unwrap();
+            assert!(kl.verify_share(accounts.bob, SECRET, nodes[0], vec![1, 2, 3]));
+            // altered, or handed to another node
+            assert!(!kl.verify_share(accounts.bob, SECRET, nodes[0], vec![1, 2, 4]));
+            assert!(!kl.verify_share(accounts.bob, SECRET, nodes[1], vec![1, 2, 3]));
+
+            kl.replace_guardian(SECRET, nodes[0], nodes[2], ConditionType::Email,
+                Hash::default(), kl.share_commitment(vec![7])).unwrap();
+            assert!(kl.verify_share(accounts.bob, SECRET, nodes[2], vec![7]));
+            assert!(!kl.verify_share(accounts.bob, SECRET, nodes[0], vec![1, 2, 3]));
+        }
+
+        #[ink::test]
+        fn test_roles() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let pauser = accounts.bob;
+            assert!(kl.has_role(Role::Pauser, accounts.alice));
+            assert!(!kl.has_role(Role::Pauser, pauser));
+            run_action(&mut kl, AdminAction::GrantRole(Role::Pauser, pauser)).unwrap();
+            assert!(kl.has_role(Role::Pauser, pauser));
+            assert!(!kl.has_role(Role::FeeAdmin, pauser));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(pauser);
+            kl.pause().unwrap();
+            kl.unpause().unwrap();
+            assert_eq!(kl.queue_action(AdminAction::SetRecoveryFee(5)), Err(Error::MissingRole));
+            assert_eq!(run_action(&mut kl, AdminAction::GrantRole(Role::FeeAdmin, pauser)),
+                Err(Error::NotOwner));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.revoke_role(Role::Pauser, pauser).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(pauser);
+            assert_eq!(kl.pause(), Err(Error::MissingRole));
+        }
+
+        #[ink::test]
+        fn test_pause() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.pause(), Err(Error::MissingRole));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.pause().unwrap();
+            assert!(kl.paused());
+            assert_eq!(kl.pause(), Err(Error::PauseStateUnchanged));
+            assert_eq!(kl.transfer(user, 1, Vec::new()),
+                Err(PSP22Error::Custom("Paused".to_string())));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.django);
+            assert_eq!(kl.register_node(node_pub_k(accounts.django), KeyType::Secp256k1, no_attestation()),
+                Err(Error::Paused));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.check_in(), Err(Error::Paused));
+            assert_eq!(kl.set_dead_man_switch(SECRET, None), Err(Error::Paused));
+            assert_eq!(kl.set_beneficiary(SECRET, accounts.eve, Some(1)), Err(Error::Paused));
+            assert_eq!(kl.set_veto_key(SECRET, Some(accounts.eve)), Err(Error::Paused));
+            assert_eq!(kl.cancel_reshare(SECRET), Err(Error::Paused));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)),
+                Err(Error::Paused));
+            kl.heartbeat().unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.unpause().unwrap();
+            assert_eq!(kl.unpause(), Err(Error::PauseStateUnchanged));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_transfer_from() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            kl.approve(accounts.bob, 20).unwrap();
+            assert_eq!(kl.allowance(accounts.alice, accounts.bob), 20);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.transfer_from(accounts.alice, accounts.charlie, 15, Vec::new()).unwrap();
+            assert_eq!(kl.balance_of(accounts.charlie), 15);
+            assert_eq!(kl.balance_of(accounts.alice), 85);
+            assert_eq!(kl.allowance(accounts.alice, accounts.bob), 5);
+            assert_eq!(kl.transfer_from(accounts.alice, accounts.charlie, 10, Vec::new()),
+                Err(PSP22Error::InsufficientAllowance));
+        }
+
+        #[ink::test]
+        fn test_allowance_changes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            kl.increase_allowance(accounts.bob, 10).unwrap();
+            kl.increase_allowance(accounts.bob, 5).unwrap();
+            assert_eq!(kl.allowance(accounts.alice, accounts.bob), 15);
+            kl.decrease_allowance(accounts.bob, 12).unwrap();
+            assert_eq!(kl.allowance(accounts.alice, accounts.bob), 3);
+            assert_eq!(kl.decrease_allowance(accounts.bob, 4),
+                Err(PSP22Error::InsufficientAllowance));
+            assert_eq!(kl.transfer(accounts.bob, 101, Vec::new()),
+                Err(PSP22Error::InsufficientBalance));
+        }
+
+        #[ink::test]
+        fn test_register_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.register_node([4; 33], KeyType::Secp256k1, no_attestation()),
+                Err(Error::InvalidInput));
+            kl.register_node(node_pub_k(accounts.bob), KeyType::Secp256k1, no_attestation()).unwrap();
+            assert_eq!(kl.verify_node(node_pub_k(accounts.bob)), true);
+            assert_eq!(kl.verify_node(node_pub_k(accounts.charlie)), false);
+        }
+
+        #[ink::test]
+        fn test_register_node_attestation() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let service_key = [7u8; 32];
+            let measurement = Hash::from([1u8; 32]);
+            run_action(&mut kl, AdminAction::SetAttestationSigner(Some(public_key(&service_key))))
+                .unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            let pub_k = node_pub_k(accounts.bob);
+            let report = AttestationReport {
+                measurement,
+                signature: sign(&service_key,
+                    &KeyLedger::attestation_digest(&measurement, &pub_k)),
+            };
+            assert_eq!(kl.register_node(pub_k, KeyType::Secp256k1, report.clone()),
+                Err(Error::MeasurementNotAllowed));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::AllowMeasurement(measurement)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            // the report was issued for a different key
+            assert_eq!(kl.register_node(node_pub_k(accounts.charlie), KeyType::Secp256k1,
+                report.clone()), Err(Error::InvalidAttestation));
+            kl.register_node(pub_k, KeyType::Secp256k1, report).unwrap();
+        }
+
+        #[ink::test]
+        fn test_register_node_locks_stake() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            kl.transfer(accounts.bob, 40, Vec::new()).unwrap();
+            kl.transfer(accounts.charlie, 10, Vec::new()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.register_node(node_pub_k(accounts.bob), KeyType::Secp256k1, no_attestation()).unwrap();
+            assert_eq!(kl.stake_of(accounts.bob), 30);
+            assert_eq!(kl.balance_of(accounts.bob), 10);
+            assert_eq!(run_action(&mut kl, AdminAction::SetMinNodeStake(0)), Err(Error::NotOwner));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.register_node(node_pub_k(accounts.charlie), KeyType::Secp256k1, no_attestation()), Err(Error::InsufficientBalance));
+
+            // django never registered, so he can not be picked as a guardian
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[accounts.bob, accounts.django]), 1),
+                Err(Error::NodeNotRegistered));
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[accounts.bob]), 1).unwrap();
+        }
+
+        #[ink::test]
+        fn test_slash_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            kl.transfer(accounts.bob, 30, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.bob]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.slash_node(accounts.bob, 10, b"invalid proof".to_vec()).unwrap();
+            assert_eq!(kl.stake_of(accounts.bob), 20);
+            assert_eq!(kl.treasury_balance(), 10);
+            assert_eq!(kl.reputation(accounts.bob).slashes, 1);
+            assert_eq!(kl.slash_node(accounts.bob, 21, Vec::new()),
+                Err(Error::InsufficientStake));
+            assert_eq!(kl.slash_node(accounts.charlie, 1, Vec::new()),
+                Err(Error::NodeNotRegistered));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.slash_node(accounts.bob, 1, Vec::new()), Err(Error::MissingRole));
+        }
+
+        #[ink::test]
+        fn test_report_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            kl.transfer(accounts.bob, 30, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.bob]);
+
+            let reporter = accounts.eve;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(reporter);
+            assert_eq!(kl.report_node(accounts.charlie, vec![1]), Err(Error::NodeNotRegistered));
+            let id = kl.report_node(accounts.bob, vec![1, 2, 3]).unwrap();
+            assert_eq!(kl.report(id).unwrap().evidence, vec![1, 2, 3]);
+            assert_eq!(kl.resolve_report(id, 10), Err(Error::MissingRole));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(kl.resolve_report(id, 31), Err(Error::InsufficientStake));
+            kl.resolve_report(id, 10).unwrap();
+            assert_eq!(kl.stake_of(accounts.bob), 20);
+            assert_eq!(kl.balance_of(reporter), 5);
+            assert_eq!(kl.treasury_balance(), 5);
+            assert_eq!(kl.report(id), None);
+            assert_eq!(kl.resolve_report(id, 10), Err(Error::ReportNotFound));
+
+            // a dismissed report slashes nothing
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(reporter);
+            let id = kl.report_node(accounts.bob, Vec::new()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.resolve_report(id, 0).unwrap();
+            assert_eq!(kl.stake_of(accounts.bob), 20);
+            assert_eq!(kl.reputation(accounts.bob).slashes, 1);
+        }
+
+        #[ink::test]
+        fn test_deregister_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            run_action(&mut kl, AdminAction::SetUnbondingPeriod(2)).unwrap();
+            kl.transfer(accounts.bob, 30, Vec::new()).unwrap();
+            kl.transfer(accounts.charlie, 30, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.bob, accounts.charlie]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.set_session_key(Some(accounts.frank)).unwrap();
+            assert_eq!(kl.withdraw_stake(), Err(Error::NodeNotExiting));
+            kl.deregister_node().unwrap();
+            assert_eq!(kl.deregister_node(), Err(Error::NodeExiting));
+            assert_eq!(kl.withdraw_stake(), Err(Error::StakeLocked));
+
+            // an exiting node can not be picked as guardian anymore
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[accounts.bob, accounts.charlie]), 1),
+                Err(Error::NodeExiting));
+
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.withdraw_stake().unwrap();
+            assert_eq!(kl.balance_of(accounts.bob), 30);
+            assert_eq!(kl.stake_of(accounts.bob), 0);
+            assert_eq!(kl.get_nodes(0, 10), vec![accounts.charlie]);
+            // its session key is released with it
+            assert_eq!(kl.session_key(accounts.bob), None);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            kl.set_session_key(Some(accounts.frank)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_heartbeat() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetLivenessWindow(1)).unwrap();
+            register_nodes(&mut kl, &[accounts.bob, accounts.django]);
+            assert!(kl.is_node_alive(accounts.bob));
+            assert!(!kl.is_node_alive(accounts.charlie));
+
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert!(!kl.is_node_alive(accounts.bob));
+            kl.heartbeat().unwrap();
+            assert!(kl.is_node_alive(accounts.bob));
+            let epoch = kl.current_epoch();
+            let points = kl.reward_points(accounts.bob, epoch);
+            assert!(points > 0);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.heartbeat(), Err(Error::NodeNotRegistered));
+
+            // leaving and blacklisted nodes keep their liveness but earn nothing
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.blacklist_node(accounts.django).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.deregister_node().unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.heartbeat().unwrap();
+            assert!(kl.is_node_alive(accounts.bob));
+            assert_eq!(kl.reward_points(accounts.bob, epoch), points);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.django);
+            kl.heartbeat().unwrap();
+            assert_eq!(kl.reward_points(accounts.django, epoch), 0);
+        }
+
+        #[ink::test]
+        fn test_epoch_rewards() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetEpochLength(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetLivenessWindow(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetEpochEmission(30)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.fund_rewards(40).unwrap();
+            assert_eq!(kl.reward_pool(), 40);
+            let epoch = kl.current_epoch();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            kl.finalize_recovery(id).unwrap();
+            // django only heartbeats, the second one in a window does not count
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            for _ in 0..5 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+                kl.heartbeat().unwrap();
+            }
+            assert_eq!(kl.reward_points(nodes[0], epoch), 10);
+            assert_eq!(kl.reward_points(nodes[1], epoch), 2);
+            assert_eq!(kl.claim_rewards(), Err(Error::NoRewards));
+
+            for _ in 0..5 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.current_epoch(), epoch + 1);
+            assert_eq!(kl.claim_rewards(), Ok(5));
+            assert_eq!(kl.vesting_schedules(nodes[1])[0].total, 5);
+            assert_eq!(kl.claim_rewards(), Err(Error::NoRewards));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.claim_rewards(), Ok(25));
+            assert_eq!(kl.reward_pool(), 10);
+        }
+
+        #[ink::test]
+        fn test_delegation() {
+            let mut kl: KeyLedger = KeyLedger::new(200);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetEpochLength(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetLivenessWindow(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetEpochEmission(40)).unwrap();
+            kl.fund_rewards(40).unwrap();
+            let node = accounts.charlie;
+            kl.transfer(node, 10, Vec::new()).unwrap();
+            kl.transfer(accounts.bob, 10, Vec::new()).unwrap();
+            assert_eq!(kl.delegate(node, 20), Err(Error::NodeNotRegistered));
+            register_nodes(&mut kl, &[node]);
+            assert_eq!(kl.set_commission(MAX_BPS + 1), Err(Error::InvalidBasisPoints));
+            kl.set_commission(1_000).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            let alice = kl.balance_of(accounts.alice);
+            kl.delegate(node, 20).unwrap();
+            assert_eq!(kl.balance_of(accounts.alice), alice - 20);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.delegate(node, 11), Err(Error::InsufficientBalance));
+            kl.delegate(node, 10).unwrap();
+            assert_eq!(kl.effective_stake(node), 40);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            for _ in 0..2 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.heartbeat().unwrap();
+            for _ in 0..8 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            // delegators back 30 of 40 stake, so get 30 of the 40 reward less
+            // the 10% commission
+            assert_eq!(kl.claim_rewards(), Ok(13));
+            assert_eq!(kl.delegation(node, accounts.alice).pending_rewards, 18);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.undelegate(node, 11), Err(Error::InsufficientDelegation));
+            kl.undelegate(node, 10).unwrap();
+            assert_eq!(kl.balance_of(accounts.bob), 10);
+            assert_eq!(kl.claim_delegation_rewards(node), Ok(9));
+            assert_eq!(kl.claim_delegation_rewards(node), Err(Error::NoRewards));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(kl.claim_delegation_rewards(node), Ok(18));
+            assert_eq!(kl.delegated_stake(node), 20);
+        }
+
+        #[ink::test]
+        fn test_operator_allowlist() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetOperatorAllowlist(true)).unwrap();
+            assert!(kl.operator_allowlist());
+            run_action(&mut kl, AdminAction::ApproveOperator(accounts.bob)).unwrap();
+            assert!(kl.is_operator_approved(accounts.bob));
+            register_nodes(&mut kl, &[accounts.bob]);
+            assert_eq!(kl.register_node(node_pub_k(accounts.charlie), KeyType::Secp256k1, no_attestation()),
+                Err(Error::NodeAlreadyRegistered));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.register_node(node_pub_k(accounts.charlie), KeyType::Secp256k1, no_attestation()),
+                Err(Error::OperatorNotApproved));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::GrantRole(Role::NodeApprover, accounts.eve)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.queue_action(AdminAction::SetOperatorAllowlist(false)),
+                Err(Error::NotOwner));
+            let action_id = kl.queue_action(AdminAction::ApproveOperator(accounts.charlie)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.approve_action(action_id).unwrap();
+            for _ in 0..kl.timelock_delay() {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.execute_action(action_id).unwrap();
+            register_nodes(&mut kl, &[accounts.charlie]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::RevokeOperator(accounts.django)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.django);
+            assert_eq!(kl.register_node(node_pub_k(accounts.django), KeyType::Secp256k1, no_attestation()),
+                Err(Error::OperatorNotApproved));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetOperatorAllowlist(false)).unwrap();
+            register_nodes(&mut kl, &[accounts.django]);
+            assert_eq!(kl.node_count(), 3);
+        }
+
+        #[ink::test]
+        fn test_get_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let node = accounts.charlie;
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            kl.transfer(node, 30, Vec::new()).unwrap();
+            kl.transfer(accounts.bob, 5, Vec::new()).unwrap();
+            assert_eq!(kl.get_node(node), None);
+            register_nodes(&mut kl, &[node]);
+            kl.update_node_info("https://node".to_string(), "eu-west".to_string(), 10).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.delegate(node, 5).unwrap();
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+
+            let view = kl.get_node(node).unwrap();
+            assert_eq!(view.pub_k, node_pub_k(node));
+            assert_eq!(view.key_type, KeyType::Secp256k1);
+            assert_eq!((view.stake, view.effective_stake), (30, 35));
+            assert_eq!(view.info.region, "eu-west");
+            assert!(view.alive);
+            assert!(!view.blacklisted);
+            assert_eq!(view.exiting_since, None);
+            assert_eq!(view.assigned, 1);
+        }
+
+        #[ink::test]
+        fn test_find_nodes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            for (node, region, capacity) in [(nodes[0], "eu-west", 1), (nodes[1], "eu-west", 0),
+                (nodes[2], "us-east", 5)] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+                kl.update_node_info("https://node".to_string(), region.to_string(), capacity)
+                    .unwrap();
+            }
+            let eu = Some("eu-west".to_string());
+            assert_eq!(kl.find_nodes(eu.clone(), 1, 10), vec![nodes[0], nodes[1]]);
+            assert_eq!(kl.find_nodes(None, 0, 2), vec![nodes[0], nodes[1]]);
+            assert_eq!(kl.find_nodes(None, 2, 10), vec![nodes[1], nodes[2]]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            assert_eq!(kl.free_capacity(nodes[0]), 0);
+            assert_eq!(kl.free_capacity(nodes[1]), u32::MAX);
+            assert_eq!(kl.find_nodes(eu, 1, 10), vec![nodes[1]]);
+        }
+
+        #[ink::test]
+        fn test_node_capacity() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.update_node_info("https://node".to_string(), "eu-west".to_string(), 1).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            // registering again keeps the slot bob already holds
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(register_user(&mut kl, 1, USER_PUB_K, guardians(&nodes[..1]), 1),
+                Err(Error::NodeAtCapacity));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[1..]), 1).unwrap();
+            assert_eq!(kl.replace_guardian(SECRET, nodes[1], nodes[0], ConditionType::Email,
+                Hash::default(), Hash::default()), Err(Error::NodeAtCapacity));
+        }
+
+        #[ink::test]
+        fn test_register_user_auto() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django, accounts.eve, accounts.frank];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.blacklist_node(nodes[3]).unwrap();
+            let conditions = vec![
+                (ConditionType::Email, Hash::default()),
+                (ConditionType::Phone, Hash::default()),
+                (ConditionType::OAuth, Hash::default()),
+            ];
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            let picked = kl.register_user_auto(SECRET, USER_PUB_K,
+                conditions.clone(), 2).unwrap();
+            let mut sorted = picked.clone();
+            sorted.sort();
+            let mut expected = nodes[..3].to_vec();
+            expected.sort();
+            // the blacklisted node is never picked
+            assert_eq!(sorted, expected);
+            let guardians = kl.guardians_of(user, SECRET);
+            assert_eq!(guardians.iter().map(|g| g.node_id).collect::<Vec<_>>(), picked);
+            assert_eq!(guardians[1].cond_type, ConditionType::Phone);
+
+            let mut four = conditions;
+            four.push((ConditionType::Passphrase, Hash::default()));
+            assert_eq!(kl.register_user_auto(1, USER_PUB_K, four, 2),
+                Err(Error::NotEnoughNodes));
+        }
+
+        #[ink::test]
+        fn test_blacklist_node() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            assert_eq!(kl.blacklist_node(nodes[1]), Err(Error::MissingRole));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 1).unwrap();
+            register_user(&mut kl, 1, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            assert_eq!(kl.assigned_users(nodes[0], 0, 10), vec![(user, SECRET), (user, 1)]);
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.blacklist_node(nodes[0]).unwrap();
+            assert!(kl.is_blacklisted(nodes[0]));
+            assert_eq!(kl.blacklist_node(nodes[0]), Err(Error::NodeBlacklisted));
+            assert_eq!(kl.get_nodes(0, 10), vec![nodes[2], nodes[1]]);
+            assert_eq!(kl.stats().nodes, 2);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)),
+                Err(Error::NodeBlacklisted));
+            // the stake stays in reach of a slash
+            assert_eq!(kl.deregister_node(), Err(Error::NodeBlacklisted));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(register_user(&mut kl, 2, USER_PUB_K, guardians(&nodes[..1]), 1),
+                Err(Error::NodeBlacklisted));
+            kl.cancel_recovery(SECRET).unwrap();
+            kl.replace_guardian(SECRET, nodes[0], nodes[2], ConditionType::Email,
+                Hash::default(), Hash::default()).unwrap();
+            assert_eq!(kl.assigned_users(nodes[0], 0, 10), vec![(user, 1)]);
+            assert_eq!(kl.assigned_users(nodes[2], 0, 10), vec![(user, SECRET)]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.delete_user(1).unwrap();
+            assert_eq!(kl.assigned_count(nodes[0]), 0);
+
+            // also when it was blacklisted after it started leaving
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetUnbondingPeriod(0)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.deregister_node().unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.blacklist_node(nodes[1]).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            assert_eq!(kl.withdraw_stake(), Err(Error::NodeBlacklisted));
+        }
+
+        #[ink::test]
+        fn test_reshare() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.start_reshare(SECRET, guardians(&nodes[1..]), 2),
+                Err(Error::UserNotFound));
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            assert_eq!(kl.start_reshare(SECRET, guardians(&nodes[1..]), 3),
+                Err(Error::InvalidThreshold));
+            kl.start_reshare(SECRET, guardians(&nodes[1..]), 2).unwrap();
+            assert_eq!(kl.start_reshare(SECRET, guardians(&nodes[1..]), 2),
+                Err(Error::ResharePending));
+            assert_eq!(kl.replace_guardian(SECRET, nodes[0], nodes[1], ConditionType::Email,
+                Hash::default(), Hash::default()),
+                Err(Error::ResharePending));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.acknowledge_share(user, SECRET), Err(Error::NotAssignedGuardian));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.acknowledge_share(user, SECRET).unwrap();
+            // the old set is in charge until every new node acknowledged
+            assert_eq!(kl.assigned_users(nodes[0], 0, 10), vec![(user, SECRET)]);
+            assert_eq!(kl.reshare_pending(user, SECRET).unwrap().acknowledged, vec![true, false]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            assert_eq!(kl.acknowledge_share(user, SECRET), Err(Error::RecoveryInProgress));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            kl.finalize_recovery(id).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            kl.acknowledge_share(user, SECRET).unwrap();
+            assert_eq!(kl.reshare_pending(user, SECRET), None);
+            assert_eq!(kl.share_version_of(user, SECRET), Some(1));
+            assert_eq!(kl.assigned_count(nodes[0]), 0);
+            assert_eq!(kl.assigned_users(nodes[2], 0, 10), vec![(user, SECRET)]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.cancel_reshare(SECRET), Err(Error::NoReshare));
+            kl.start_reshare(SECRET, guardians(&nodes[..1]), 1).unwrap();
+            kl.cancel_reshare(SECRET).unwrap();
+            assert_eq!(kl.reshare_pending(user, SECRET), None);
+        }
+
+        #[ink::test]
+        fn test_update_node_info() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_nodes(&mut kl, &[accounts.bob]);
+            assert_eq!(kl.node_info(accounts.bob), Some(NodeInfo::default()));
+            kl.update_node_info("https://node1.keysafe.network".to_string(),
+                "eu-west".to_string(), 500).unwrap();
+            let info = kl.node_info(accounts.bob).unwrap();
+            assert_eq!(info.region, "eu-west".to_string());
+            assert_eq!(info.capacity, 500);
+            assert_eq!(kl.node_info(accounts.charlie), None);
+        }
+
+        #[ink::test]
+        fn test_input_validation() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            let long = "a".repeat(MAX_ENDPOINT_LEN);
+            for (endpoint, region) in [("http://node", "eu-west"), ("https://no de", "eu-west"),
+                (&format!("https://{}", long)[..], "eu-west"), ("https://node", "EU West")] {
+                assert_eq!(kl.update_node_info(endpoint.to_string(), region.to_string(), 0),
+                    Err(Error::InvalidInput));
+            }
+            kl.update_node_info(String::new(), String::new(), 0).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(kl.report_node(node, vec![0; MAX_EVIDENCE_LEN + 1]),
+                Err(Error::InvalidInput));
+            assert_eq!(kl.slash_node(node, 0, vec![0; MAX_EVIDENCE_LEN + 1]),
+                Err(Error::InvalidInput));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+            let request = RecoveryRequest {
+                target_pub_k: Some([4; 33]),
+                ..recovery_request()
+            };
+            assert_eq!(kl.start_recovery(SECRET, request), Err(Error::InvalidInput));
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            let proof = RecoveryProof {
+                condition_proof: vec![0; MAX_CONDITION_PROOF_LEN + 1],
+                ..recovery_proof(node, user, SECRET, 1)
+            };
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            assert_eq!(kl.finish_recovery(1, proof), Err(Error::InvalidInput));
+        }
+
+        #[ink::test]
+        fn test_get_config() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let config = kl.get_config();
+            assert_eq!(config.recovery_fee, 3);
+            assert_eq!(config.challenge_period, DEFAULT_CHALLENGE_PERIOD);
+            assert_eq!(config.recovery_cooldown, DEFAULT_RECOVERY_COOLDOWN);
+            assert_eq!(config.min_node_stake, 0);
+
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(5)).unwrap();
+            run_action(&mut kl, AdminAction::SetTreasuryBps(1_000)).unwrap();
+            assert_eq!(kl.get_config(), Config {
+                min_node_stake: 30,
+                challenge_period: 5,
+                treasury_bps: 1_000,
+                ..config
+            });
+        }
+
+        #[ink::test]
+        fn test_get_nodes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            assert_eq!(kl.node_count(), 3);
+            assert_eq!(kl.get_nodes(0, 2), vec![accounts.charlie, accounts.django]);
+            assert_eq!(kl.get_nodes(2, 2), vec![accounts.eve]);
+            assert_eq!(kl.get_nodes(5, 2), Vec::<AccountId>::new());
+        }
+
+        #[ink::test]
+        fn test_register_user() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let node1 = accounts.charlie;
+            let node2 = accounts.django;
+            let node3 = accounts.eve;
+            register_nodes(&mut kl, &[node1, node2, node3]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(register_user(&mut kl, SECRET, [0; 33], guardians(&[node1, node2, node3]), 2),
+                Err(Error::InvalidInput));
+            register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[node1, node2, node3]), 2);
+            assert_eq!(kl.verify_new_user(SECRET, USER_PUB_K), true);
+        }
+
+        #[ink::test]
+        fn test_start_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new());
+            let node1 = accounts.charlie;
+            let node2 = accounts.django;
+            let node3 = accounts.eve;
+            register_nodes(&mut kl, &[node1, node2, node3]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[node1, node2, node3]), 2);
+            kl.start_recovery(SECRET, recovery_request());
+            assert_eq!(kl.verify_new_recovery(SECRET), true);
+        }
+
+
+        #[ink::test]
+        fn test_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            // transfer some coin to bob for him to recover his account
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new());
+            
+            let node1 = accounts.charlie;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node1);
+            kl.register_node(node_pub_k(node1), KeyType::Secp256k1, no_attestation());
+
+            let node2 = accounts.django;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node2);
+            kl.register_node(node_pub_k(node2), KeyType::Secp256k1, no_attestation());
+
+            let node3 = accounts.eve;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node3);
+            kl.register_node(node_pub_k(node3), KeyType::Secp256k1, no_attestation());
+
+            // user start recovery
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[node1, node2, node3]), 2);
+            kl.start_recovery(SECRET, recovery_request());
+
+            // node report finish recovery 
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node3);
+            kl.finish_recovery(1, recovery_proof(node3, user, SECRET, 1));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node2);
+            kl.finish_recovery(1, recovery_proof(node2, user, SECRET, 1));
+            kl.finalize_recovery(1).unwrap();
+
+            assert_eq!(kl.balance_of(user), 7);
+            // node1 did not confirm, so it is not paid
+            assert_eq!(kl.balance_of(node1), 0);
+            assert_eq!(kl.balance_of(node2), 1);
+            assert_eq!(kl.balance_of(node3), 1);   
+        }
+
+        #[ink::test]
+        fn test_recovery_with_custom_threshold() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryFee(5)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new());
+
+            // 3 of 5 guardians are needed to finish the recovery
+            let nodes = [accounts.alice, accounts.charlie, accounts.django,
+                accounts.eve, accounts.frank];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 3).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            kl.finish_recovery(1, recovery_proof(nodes[2], user, SECRET, 1)).unwrap();
+            // two confirmations are not enough, nothing has been paid yet
+            assert_eq!(kl.escrow_of(user, SECRET), 5);
+            assert_eq!(kl.balance_of(accounts.frank), 0);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[4]);
+            kl.finish_recovery(1, recovery_proof(nodes[4], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.balance_of(user), 5);
+            assert_eq!(kl.balance_of(accounts.frank), 1);
+        }
+
+        #[ink::test]
+        fn test_recovery_pays_out_once() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            for node in &nodes[..2] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*node);
+                kl.finish_recovery(1, recovery_proof(*node, user, SECRET, 1)).unwrap();
+            }
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.balance_of(nodes[0]), 1);
+
+            // neither a second finalize nor the third guardian re-trigger it
+            assert_eq!(kl.finalize_recovery(1), Err(Error::RecoveryNotActive));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[2], user, SECRET, 1)),
+                Err(Error::RecoveryNotActive));
+            assert_eq!(kl.balance_of(nodes[0]), 1);
+            assert_eq!(kl.balance_of(nodes[2]), 0);
+            assert_eq!(kl.balance_of(user), 7);
+            assert_eq!(kl.recovery_count(user, SECRET), 1);
+            assert_eq!(kl.stats().completed_recoveries, 1);
+        }
+
+        #[ink::test]
+        fn test_recovery_emits_events() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            kl.finish_recovery(1, recovery_proof(accounts.charlie, user, SECRET, 1)).unwrap();
+            for _ in 1..kl.challenge_period() {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.finalize_recovery(1), Err(Error::VetoWindowOpen(1)));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.finalize_recovery(1).unwrap();
+
+            // mint + transfer, 2 NodeRegistered, RegistrationProposed, 2
+            // ShareAcknowledged, UserRegistered, Transfer of the fee into
+            // escrow, FeeCharged, RecoveryStarted, RecoveryConfirmed,
+            // RecoveryPendingFinalize, Transfer and NodePaid for charlie,
+            // RecoveryFinished, and a RecoveryStatusChanged for each of
+            // Started, PendingFinalize and Finished
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            assert_eq!(events.len(), 19);
+        }
+
+        #[ink::test]
+        fn test_recovery_accounting_events() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryFee(4)).unwrap();
+            run_action(&mut kl, AdminAction::SetTreasuryBps(5_000)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+
+            let accounting = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::FeeCharged(e)) => Some(("charged", e.recovery_id, e.amount)),
+                    Ok(Event::NodePaid(e)) => Some(("paid", e.recovery_id, e.amount)),
+                    Ok(Event::TreasuryCut(e)) => Some(("treasury", e.recovery_id, e.amount)),
+                    Ok(Event::FeeRefunded(e)) => Some(("refunded", e.recovery_id, e.amount)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(accounting, vec![
+                ("charged", 1, 4),
+                ("paid", 1, 2),
+                ("treasury", 1, 2),
+                ("charged", 2, 4),
+                ("refunded", 2, 4),
+            ]);
+        }
+
+        #[ink::test]
+        fn test_payout_ledger() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            for id in 1..=2 {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                kl.start_recovery(SECRET, recovery_request()).unwrap();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+                kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+                kl.finalize_recovery(id).unwrap();
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+
+            // only the confirming guardian was paid
+            assert_eq!(kl.payout_count(nodes[0]), 2);
+            assert_eq!(kl.payout_count(nodes[1]), 0);
+            assert_eq!(kl.get_payouts(nodes[0], 1, 10), vec![Payout {
+                block: 1,
+                user,
+                secret_id: SECRET,
+                recovery_id: 2,
+                amount: 3,
+                fee_token: None,
+            }]);
+            assert_eq!(kl.get_payouts(nodes[0], 0, 1)[0].recovery_id, 1);
+            assert!(kl.get_payouts(nodes[0], 2, 10).is_empty());
+        }
+
+        #[ink::test]
+        fn test_stake_moves_emit_transfers() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let node = accounts.charlie;
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(30)).unwrap();
+            run_action(&mut kl, AdminAction::SetUnbondingPeriod(0)).unwrap();
+            kl.transfer(node, 30, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[node]);
+            kl.deregister_node().unwrap();
+            kl.withdraw_stake().unwrap();
+
+            let transfers = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::Transfer(t)) => Some((t.from, t.to, t.value)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(transfers, vec![
+                (None, Some(accounts.alice), 100),
+                (Some(accounts.alice), Some(node), 30),
+                (Some(node), Some(contract), 30),
+                (Some(contract), Some(node), 30),
+            ]);
+        }
+
+        #[ink::test]
+        fn test_two_phase_registration() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            let mut g = guardians(&nodes);
+            g[0].share_commitment = kl.share_commitment(vec![1, 2, 3]);
+            kl.propose_registration(SECRET, USER_PUB_K, g, 1, None).unwrap();
+            assert_eq!(kl.pending_acknowledgements(user, SECRET), nodes.to_vec());
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RegistrationPending));
+
+            // only the guardians, with the share they were promised
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.ack_share_stored(user, SECRET, Hash::default()),
+                Err(Error::NotAssignedGuardian));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.ack_share_stored(user, SECRET, kl.share_commitment(vec![4])),
+                Err(Error::ShareMismatch));
+            kl.ack_share_stored(user, SECRET, kl.share_commitment(vec![1, 2, 3])).unwrap();
+            assert_eq!(kl.ack_share_stored(user, SECRET, kl.share_commitment(vec![1, 2, 3])),
+                Err(Error::AlreadyAcknowledged));
+            // without a proposed commitment the acknowledged one is kept
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.ack_share_stored(user, SECRET, kl.share_commitment(vec![5])).unwrap();
+            assert!(kl.verify_share(user, SECRET, nodes[1], vec![5]));
+            assert_eq!(kl.pending_acknowledgements(user, SECRET), Vec::new());
+            assert!(kl.get_user(user, SECRET).unwrap().active);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+        }
+
+        #[ink::test]
+        fn test_weighted_guardians() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            // the hardware node counts double
+            let mut g = guardians(&nodes);
+            g[0].weight = 2;
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K, g.clone(), 5),
+                Err(Error::InvalidThreshold));
+            g[2].weight = 0;
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K, g.clone(), 2),
+                Err(Error::InvalidWeight));
+            g[2].weight = 1;
+            register_user(&mut kl, SECRET, USER_PUB_K, g, 3).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            // the two friends alone do not reach 3
+            for node in &nodes[1..] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*node);
+                kl.finish_recovery(1, recovery_proof(*node, user, SECRET, 1)).unwrap();
+            }
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::Started);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            let view = kl.get_recovery(user, SECRET).unwrap();
+            assert_eq!((view.status, view.confirmed_weight), (RecoveryStatus::PendingFinalize, 4));
+        }
+
+        #[ink::test]
+        fn test_set_threshold() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            assert_eq!(kl.set_threshold(SECRET, 4), Err(Error::InvalidThreshold));
+            assert_eq!(kl.set_threshold(SECRET, 0), Err(Error::InvalidThreshold));
+            kl.set_threshold(SECRET, 3).unwrap();
+            assert_eq!(kl.get_user(user, SECRET).unwrap().threshold, 3);
+
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.set_threshold(SECRET, 2), Err(Error::RecoveryInProgress));
+            for node in &nodes[..2] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*node);
+                kl.finish_recovery(1, recovery_proof(*node, user, SECRET, 1)).unwrap();
+            }
+            // 2 of 3 is no longer enough
+            assert_eq!(kl.finalize_recovery(1), Err(Error::ThresholdNotReached));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            kl.finish_recovery(1, recovery_proof(nodes[2], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+        }
+
+        #[ink::test]
+        fn test_register_user_rejects_unreachable_threshold() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[accounts.charlie, accounts.django]), 3),
+                Err(Error::InvalidThreshold));
+            assert_eq!(kl.verify_new_user(SECRET, USER_PUB_K), false);
+        }
+
+        #[ink::test]
+        fn test_register_user_validates_guardians() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            kl.deregister_node().unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[nodes[0], accounts.eve]), 1),
+                Err(Error::NodeNotRegistered));
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&[nodes[0], nodes[0]]), 1),
+                Err(Error::DuplicateGuardian));
+            // django started leaving
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K,
+                guardians(&nodes), 1),
+                Err(Error::NodeExiting));
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1)
+                .unwrap();
+        }
+
+        #[ink::test]
+        fn test_recovery_errors() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            assert_eq!(kl.register_node(node_pub_k(nodes[2]), KeyType::Secp256k1, no_attestation()), Err(Error::NodeAlreadyRegistered));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::UserNotFound));
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 2).unwrap();
+            // bob has no tokens to pay his guardians
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::InsufficientBalance));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(0, recovery_proof(nodes[0], user, SECRET, 0)),
+                Err(Error::RecoveryNotActive));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            assert_eq!(kl.finish_recovery(0, recovery_proof(accounts.alice, user, SECRET, 0)),
+                Err(Error::NodeNotRegistered));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            // eve is a node, but not one of bob's guardians
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[2], user, SECRET, 1)),
+                Err(Error::NotAssignedGuardian));
+        }
+
+        #[ink::test]
+        fn test_expire_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryExpiry(2)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            assert_eq!(kl.expire_recovery(user, SECRET), Err(Error::RecoveryNotActive));
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            assert_eq!(kl.expire_recovery(user, SECRET), Err(Error::RecoveryNotExpired));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.expire_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.reputation(nodes[0]), Reputation { confirmed: 1, ..Default::default() });
+            assert_eq!(kl.reputation(nodes[1]), Reputation { missed: 1, ..Default::default() });
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.verify_new_recovery(SECRET), false);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)),
+                Err(Error::RecoveryNotActive));
+        }
+
+        #[ink::test]
+        fn test_cancel_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            assert_eq!(kl.cancel_recovery(SECRET), Err(Error::RecoveryNotActive));
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.verify_new_recovery(SECRET), false);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::RecoveryNotActive));
+            // only the user itself can cancel
+            assert_eq!(kl.cancel_recovery(SECRET), Err(Error::UserNotFound));
+        }
+
+        #[ink::test]
+        fn test_challenge_window() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(2)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            assert_eq!(run_action(&mut kl, AdminAction::SetChallengePeriod(0)),
+                Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+
+            // the user vetoes a recovery the guardians already confirmed
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RecoveryInProgress));
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.finalize_recovery(id), Err(Error::RecoveryNotActive));
+            assert_eq!(kl.balance_of(user), 10);
+
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.finalize_recovery(id), Err(Error::ThresholdNotReached));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.finalize_recovery(id), Err(Error::VetoWindowOpen(1)));
+            // a late guardian still gets its share of the fee
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.finish_recovery(id, recovery_proof(nodes[1], user, SECRET, id)).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.finalize_recovery(id).unwrap();
+            assert_eq!(kl.recovery_count(user, SECRET), 1);
+            assert_eq!(kl.balance_of(nodes[1]), 1);
+            assert_eq!(kl.finalize_recovery(id), Err(Error::RecoveryNotActive));
+        }
+
+        #[ink::test]
+        fn test_veto_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let thief = accounts.frank;
+            let veto_key = accounts.eve;
+            kl.transfer(thief, 10, Vec::new()).unwrap();
+            kl.transfer(user, 50, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.veto_recovery(user, SECRET), Err(Error::RecoveryNotActive));
+            kl.set_veto_key(SECRET, Some(veto_key)).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(thief);
+            let id = kl.start_account_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.veto_recovery(user, SECRET), Err(Error::NotVetoKey));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(veto_key);
+            kl.veto_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.suspicious_attempts(user, SECRET), 1);
+            assert_eq!(kl.balance_of(thief), 10);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)),
+                Err(Error::RecoveryNotActive));
+
+            // also during the challenge window, by the user itself
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(thief);
+            let id = kl.start_account_recovery(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.veto_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.suspicious_attempts(user, SECRET), 2);
+            assert_eq!(kl.finalize_recovery(id), Err(Error::RecoveryNotActive));
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 0);
+
+            // vetoing an attempt started with the owner's key still counts it
+            // as failed
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.veto_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 1);
+            assert_eq!(kl.suspicious_attempts(user, SECRET), 3);
+        }
+
+        #[ink::test]
+        fn test_recovery_cooldown() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(3)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            // a confirmed recovery can not be restarted, only cancelled
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RecoveryInProgress));
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::CooldownActive(3)));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::CooldownActive(2)));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+        }
+
+        #[ink::test]
+        fn test_concurrent_recoveries() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            for user in [accounts.bob, accounts.frank] {
+                kl.transfer(user, 10, Vec::new()).unwrap();
+            }
+            let mut ids = Vec::new();
+            for user in [accounts.bob, accounts.frank] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+                ids.push(kl.start_recovery(SECRET, recovery_request()).unwrap());
+            }
+            assert_eq!(ids, vec![1, 2]);
+            // restarting hands out a new id, the old one can not be confirmed
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Ok(3));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(2, recovery_proof(nodes[0], accounts.frank, SECRET, 2)),
+                Err(Error::RecoveryNotActive));
+            // bob's proof does not confirm frank's attempt
+            assert_eq!(kl.finish_recovery(3, recovery_proof(nodes[0], accounts.bob, SECRET, 1)),
+                Err(Error::InvalidProof));
+            kl.finish_recovery(1, recovery_proof(nodes[0], accounts.bob, SECRET, 1)).unwrap();
+            kl.finish_recovery(3, recovery_proof(nodes[0], accounts.frank, SECRET, 3)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            kl.finalize_recovery(3).unwrap();
+            assert_eq!(kl.recovery_count(accounts.bob, SECRET), 1);
+            assert_eq!(kl.recovery_count(accounts.frank, SECRET), 1);
+        }
+
+        #[ink::test]
+        fn test_recovery_history() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.recovery_count(user, SECRET), 0);
+            for n in nodes.iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*n);
+                kl.finish_recovery(id, recovery_proof(*n, user, SECRET, id)).unwrap();
+                kl.finalize_recovery(id).unwrap();
+            }
+
+            assert_eq!(kl.recovery_count(user, SECRET), 2);
+            let first = kl.recovery_record(user, SECRET, 0).unwrap();
+            assert_eq!(first.recovery_id, 1);
+            assert_eq!(first.finished_at, first.started_at + 1);
+            assert_eq!(first.confirmed_by, vec![nodes[0]]);
+            assert_eq!(first.fee, 3);
+            let second = kl.recovery_record(user, SECRET, 1).unwrap();
+            assert_eq!(second.recovery_id, 2);
+            assert_eq!(second.confirmed_by, vec![nodes[1]]);
+            assert_eq!(kl.recovery_record(user, SECRET, 2), None);
+        }
+
+        #[ink::test]
+        fn test_recovery_lockout() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMaxFailedAttempts(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetUnlockDelay(1)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.charlie]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[accounts.charlie]), 1).unwrap();
+            assert_eq!(kl.request_unlock(SECRET), Err(Error::RecoveryNotLocked));
+
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RecoveryLocked));
+
+            assert_eq!(kl.unlock_recovery(SECRET), Err(Error::UnlockNotReady));
+            kl.request_unlock(SECRET).unwrap();
+            assert_eq!(kl.unlock_recovery(SECRET), Err(Error::UnlockNotReady));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.unlock_recovery(SECRET).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+        }
+
+        #[ink::test]
+        fn test_configured_recovery_pricing() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryFee(9)).unwrap();
+            // a third of the fee goes to the treasury
+            run_action(&mut kl, AdminAction::SetTreasuryBps(3_333)).unwrap();
+            run_action(&mut kl, AdminAction::SetMinBalance(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            assert_eq!(kl.queue_action(AdminAction::SetTreasuryBps(10_001)),
+                Err(Error::InvalidBasisPoints));
+            let user = accounts.bob;
+            kl.transfer(user, 9, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::InsufficientBalance));
+            assert_eq!(kl.queue_action(AdminAction::SetMinBalance(0)), Err(Error::MissingRole));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            kl.transfer(user, 1, Vec::new()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            // 9 * 3333 / 10000 = 2 for the treasury, the rest goes to the
+            // only node that confirmed
+            assert_eq!(kl.balance_of(user), 1);
+            assert_eq!(kl.balance_of(nodes[0]), 7);
+            assert_eq!(kl.balance_of(nodes[1]), 0);
+            assert_eq!(kl.treasury_balance(), 2);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(run_action(&mut kl, AdminAction::WithdrawTreasury(accounts.eve, 3)),
+                Err(Error::InsufficientBalance));
+            run_action(&mut kl, AdminAction::WithdrawTreasury(accounts.eve, 2)).unwrap();
+            assert_eq!(kl.balance_of(accounts.eve), 2);
+            assert_eq!(kl.treasury_balance(), 0);
+        }
+
+        #[ink::test]
+        fn test_registration_fee() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRegistrationFee(50)).unwrap();
+            assert_eq!(kl.registration_fee(), 50);
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(49);
+            assert_eq!(register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1),
+                Err(Error::RegistrationFeeTooLow));
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(60);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.native_treasury(), 60);
+            assert_eq!(run_action(&mut kl, AdminAction::WithdrawNativeTreasury(accounts.bob, 60)),
+                Err(Error::NotOwner));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 60);
+            assert_eq!(run_action(&mut kl, AdminAction::WithdrawNativeTreasury(accounts.eve, 61)),
+                Err(Error::InsufficientBalance));
+            run_action(&mut kl, AdminAction::WithdrawNativeTreasury(accounts.eve, 60)).unwrap();
+            assert_eq!(kl.native_treasury(), 0);
+        }
+
+        #[ink::test]
+        fn test_deposit_and_withdraw() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(40);
+            kl.deposit().unwrap();
+            assert_eq!(kl.balance_of(user), 40);
+            assert_eq!(kl.total_supply(), 140);
+            assert_eq!(kl.native_deposits(), 40);
+
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 40);
+            assert_eq!(kl.withdraw(41), Err(Error::InsufficientBalance));
+            kl.withdraw(30).unwrap();
+            assert_eq!(kl.balance_of(user), 10);
+            assert_eq!(kl.total_supply(), 110);
+            assert_eq!(kl.native_deposits(), 10);
+
+            // minted tokens are not backed by native tokens
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(kl.withdraw(20), Err(Error::InsufficientBalance));
+        }
+
+        #[ink::test]
+        fn test_recovery_fee_escrow() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.escrow_of(user, SECRET), 3);
+            assert_eq!(kl.balance_of(user), 7);
+            // the escrowed fee can not be moved away
+            assert_eq!(kl.transfer(accounts.alice, 8, Vec::new()),
+                Err(PSP22Error::InsufficientBalance));
+            // a restart keeps the same escrow
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.balance_of(user), 7);
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.escrow_of(user, SECRET), 0);
+            assert_eq!(kl.balance_of(user), 10);
+        }
+
+        #[ink::test]
+        fn test_account_recovery_to_new_owner() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            let new_account = accounts.frank;
+            kl.transfer(new_account, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+
+            // bob lost his key, frank asks for the recovery and pays for it
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(new_account);
+            kl.start_account_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.escrow_of(user, SECRET), 3);
+            assert_eq!(kl.balance_of(new_account), 7);
+            for n in nodes.iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*n);
+                kl.finish_recovery(1, recovery_proof(*n, user, SECRET, 1)).unwrap();
+            }
+            kl.finalize_recovery(1).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.verify_new_user(SECRET, USER_PUB_K), false);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(new_account);
+            assert_eq!(kl.cancel_recovery(SECRET), Err(Error::RecoveryNotActive));
+            assert_eq!(kl.start_account_recovery(user, SECRET), Err(Error::UserAlreadyRegistered));
+        }
+
+        #[ink::test]
+        fn test_dead_man_switch() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            let heir = accounts.frank;
+            kl.transfer(heir, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            let switch = DeadManSwitch { beneficiary: heir, inactivity_period: 5 };
+            assert_eq!(kl.set_dead_man_switch(SECRET, Some(switch.clone())),
+                Err(Error::UserNotFound));
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.set_dead_man_switch(SECRET, Some(switch.clone())).unwrap();
+            assert_eq!(kl.dead_man_switch(user, SECRET), Some(switch));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.start_dead_man_recovery(user, SECRET), Err(Error::NotBeneficiary));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            assert_eq!(kl.start_dead_man_recovery(user, SECRET), Err(Error::OwnerStillActive(5)));
+            for _ in 0..3 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            // a check in restarts the inactivity period
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.check_in().unwrap();
+            assert_eq!(kl.last_check_in(user), Some(block_number()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            for _ in 0..3 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.start_dead_man_recovery(user, SECRET), Err(Error::OwnerStillActive(2)));
+            for _ in 0..2 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            let id = kl.start_dead_man_recovery(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            kl.finalize_recovery(id).unwrap();
+            assert_eq!(kl.share_version_of(heir, SECRET), Some(0));
+            assert_eq!(kl.dead_man_switch(user, SECRET), None);
+            assert_eq!(kl.dead_man_switch(heir, SECRET), None);
+        }
+
+        #[ink::test]
+        fn test_inheritance() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(1)).unwrap();
+            let user = accounts.bob;
+            let heir = accounts.frank;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            kl.transfer(heir, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.set_beneficiary(SECRET, heir, Some(3)).unwrap();
+            assert_eq!(kl.beneficiary_delay(user, SECRET, heir), Some(3));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.start_inheritance(user, SECRET), Err(Error::NotBeneficiary));
+            // a claim does not replace the owner's own attempt
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            assert_eq!(kl.start_inheritance(user, SECRET), Err(Error::RecoveryInProgress));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 1);
+
+            // the owner cancels the first claim and vetoes the second, neither
+            // counts as a failed attempt of the owner
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            kl.start_inheritance(user, SECRET).unwrap();
+            assert_eq!(kl.start_inheritance(user, SECRET), Err(Error::RecoveryInProgress));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.cancel_recovery(SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            kl.start_inheritance(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.veto_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 1);
+            assert_eq!(kl.balance_of(heir), 10);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(heir);
+            let id = kl.start_inheritance(user, SECRET).unwrap();
+            assert_eq!(kl.finalize_recovery(id), Err(Error::ThresholdNotReached));
+            for n in nodes.iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*n);
+                kl.finish_recovery(id, recovery_proof(*n, user, SECRET, id)).unwrap();
+            }
+            // confirmed, but the heir's delay is longer than the challenge period
+            assert_eq!(kl.finalize_recovery(id), Err(Error::VetoWindowOpen(3)));
+            for _ in 0..3 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.finalize_recovery(id).unwrap();
+            assert_eq!(kl.share_version_of(heir, SECRET), Some(0));
+            assert_eq!(kl.share_version_of(user, SECRET), None);
+            assert_eq!(kl.beneficiary_delay(heir, SECRET, heir), None);
+        }
+
+        #[ink::test]
+        fn test_social_guardians() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let friends = vec![accounts.eve, accounts.frank];
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.set_social_guardians(SECRET,
+                SocialGuardians { accounts: friends.clone(), threshold: 3 }),
+                Err(Error::InvalidThreshold));
+            kl.set_social_guardians(SECRET,
+                SocialGuardians { accounts: friends.clone(), threshold: 2 }).unwrap();
+            assert_eq!(kl.approve_recovery(user, SECRET), Err(Error::NotSocialGuardian));
+            let id = kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)),
+                Err(Error::ApprovalsMissing));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(friends[0]);
+            kl.approve_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.approve_recovery(user, SECRET), Err(Error::AlreadyApproved));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)),
+                Err(Error::ApprovalsMissing));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(friends[1]);
+            kl.approve_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.recovery_approvals(id), 2);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+            kl.finalize_recovery(id).unwrap();
+            assert_eq!(kl.recovery_count(user, SECRET), 1);
+        }
+
+        #[ink::test]
+        fn test_rotate_user_key() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let old_key = [11u8; 32];
+            let old_pub_k = public_key(&old_key);
+            let new_pub_k = public_key(&[12u8; 32]);
+            register_nodes(&mut kl, &[accounts.charlie]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, old_pub_k, guardians(&[accounts.charlie]), 1).unwrap();
+
+            // signed by the new key instead of the old one
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let digest = KeyLedger::key_rotation_digest(&contract, &user, SECRET, &old_pub_k,
+                &new_pub_k);
+            assert_eq!(kl.rotate_user_key(SECRET, new_pub_k, sign(&[12u8; 32], &digest)),
+                Err(Error::InvalidSignature));
+            // or for another deployment
+            let elsewhere = KeyLedger::key_rotation_digest(&AccountId::from([0xff; 32]), &user,
+                SECRET, &old_pub_k, &new_pub_k);
+            assert_eq!(kl.rotate_user_key(SECRET, new_pub_k, sign(&old_key, &elsewhere)),
+                Err(Error::InvalidSignature));
+            kl.rotate_user_key(SECRET, new_pub_k, sign(&old_key, &digest)).unwrap();
+            assert_eq!(kl.verify_new_user(SECRET, new_pub_k), true);
+            // the same signature can not rotate the key again
+            assert_eq!(kl.rotate_user_key(SECRET, new_pub_k, sign(&old_key, &digest)),
+                Err(Error::InvalidSignature));
+        }
+
+        #[ink::test]
+        fn test_starter_grant() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetStarterGrant(3)).unwrap();
+            kl.fund_faucet(4).unwrap();
+            assert_eq!(kl.balance_of(accounts.alice), 96);
+            assert_eq!(kl.faucet_balance(), 4);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(kl.claim_starter_grant(), Ok(3));
+            assert_eq!(kl.balance_of(accounts.bob), 3);
+            assert!(kl.claimed_starter_grant(accounts.bob));
+            assert_eq!(kl.claim_starter_grant(), Err(Error::AlreadyClaimed));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.claim_starter_grant(), Err(Error::FaucetEmpty));
+
+            // at most MAX_FAUCET_CLAIMS_PER_BLOCK grants per block
+            kl.faucet.set(&100);
+            kl.faucet_claims.set(&(block_number(), MAX_FAUCET_CLAIMS_PER_BLOCK));
+            assert_eq!(kl.claim_starter_grant(), Err(Error::RateLimited(block_number() + 1)));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            assert_eq!(kl.claim_starter_grant(), Ok(3));
+        }
+
+        #[ink::test]
+        fn test_vested_rewards() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetEpochLength(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetLivenessWindow(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetEpochEmission(20)).unwrap();
+            kl.fund_rewards(20).unwrap();
+            run_action(&mut kl, AdminAction::SetVestingCliff(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetVestingDuration(20)).unwrap();
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            for _ in 0..2 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.heartbeat().unwrap();
+            for _ in 0..8 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.claim_rewards(), Ok(20));
+            assert_eq!(kl.balance_of(node), 0);
+            assert_eq!(kl.vested_balance(node), 0);
+            assert_eq!(kl.claim_vested(), Err(Error::NoRewards));
+
+            // half of the duration passed at the cliff
+            for _ in 0..10 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.vested_balance(node), 10);
+            assert_eq!(kl.claim_vested(), Ok(10));
+            assert_eq!(kl.balance_of(node), 10);
+            assert_eq!(kl.vesting_schedules(node)[0].claimed, 10);
+            for _ in 0..15 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.claim_vested(), Ok(10));
+            assert_eq!(kl.balance_of(node), 20);
+            assert!(kl.vesting_schedules(node).is_empty());
+
+            // a schedule too large to interpolate fails instead of wrapping
+            let huge = VestingSchedule { total: Balance::MAX, claimed: 0, start: 0, cliff: 0,
+                duration: 10 };
+            assert_eq!(huge.vested(5), Err(Error::Overflow));
+            assert_eq!(huge.vested(10), Ok(Balance::MAX));
+        }
+
+        #[ink::test]
+        fn test_recovery_fee_curve() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let curve = FeeCurve { step_bps: 10_000, window: 10, max_steps: 2 };
+            run_action(&mut kl, AdminAction::SetFeeCurve(curve)).unwrap();
+            assert_eq!(kl.fee_curve(), curve);
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 30, Vec::new()).unwrap();
+            kl.transfer(accounts.frank, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+
+            // the fee doubles with every recent recovery, at most twice
+            for (id, fee) in [(1, 3), (2, 6), (3, 12)] {
+                assert_eq!(kl.recovery_quote(user, SECRET), fee);
+                let balance = kl.balance_of(user);
+                kl.start_recovery(SECRET, recovery_request()).unwrap();
+                assert_eq!(kl.balance_of(user), balance - fee);
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+                kl.finish_recovery(id, recovery_proof(nodes[0], user, SECRET, id)).unwrap();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                kl.finalize_recovery(id).unwrap();
+            }
+            assert_eq!(kl.recovery_quote(user, SECRET), 12);
+            for _ in 0..10 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            assert_eq!(kl.recovery_quote(user, SECRET), 3);
+
+            // an attempt that does not finish raises the fee as well
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.recovery_quote(user, SECRET), 6);
+
+            // someone else's attempt does not
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.frank);
+            kl.start_account_recovery(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.recovery_quote(user, SECRET), 6);
+        }
+
+        #[ink::test]
+        fn test_referral_reward() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetReferralReward(2)).unwrap();
+            kl.treasury.set(&3);
+            let referrer = accounts.eve;
+            let user = accounts.bob;
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.propose_registration(SECRET, USER_PUB_K,
+                guardians(&[node]), 1, Some(user)), Err(Error::SelfReferral));
+            kl.propose_registration(SECRET, USER_PUB_K, guardians(&[node]), 1,
+                Some(referrer)).unwrap();
+            assert_eq!(kl.referrer_of(user), Some(referrer));
+            // nothing is paid before the guardian acknowledged its share
+            assert_eq!(kl.balance_of(referrer), 0);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            kl.ack_share_stored(user, SECRET, Hash::default()).unwrap();
+            assert_eq!(kl.balance_of(referrer), 2);
+            assert_eq!(kl.treasury_balance(), 1);
+
+            // registering again, or another secret, pays nothing more
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.propose_registration(1, USER_PUB_K, guardians(&[node]), 1,
+                Some(accounts.frank)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            kl.ack_share_stored(user, 1, Hash::default()).unwrap();
+            assert_eq!(kl.referrer_of(user), Some(referrer));
+            assert_eq!(kl.balance_of(referrer), 2);
+        }
+
+        #[ink::test]
+        fn test_storage_rent() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetBillingPeriod(10)).unwrap();
+            run_action(&mut kl, AdminAction::SetRentGracePeriod(5)).unwrap();
+            run_action(&mut kl, AdminAction::SetStorageFee(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            // the treasury cut of recovery fees does not apply to rent
+            run_action(&mut kl, AdminAction::SetTreasuryBps(5_000)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            let registered_at = block_number();
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.prepay_storage(SECRET, 3).unwrap();
+            assert_eq!(kl.balance_of(user), 7);
+            assert_eq!(kl.charge_period(user, SECRET), Ok(0));
+
+            // two periods are due but only one is paid for
+            for _ in 0..20 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            let treasury = kl.treasury_balance();
+            assert_eq!(kl.charge_period(user, SECRET), Ok(1));
+            assert_eq!(kl.subscription(user, SECRET), Some(Subscription {
+                prepaid: 1,
+                paid_until: registered_at + 10,
+                overdue_since: Some(registered_at + 10),
+            }));
+            assert_eq!(kl.balance_of(nodes[0]), 1);
+            assert_eq!(kl.treasury_balance(), treasury);
+            assert!(kl.shares_purgeable(user, SECRET));
+
+            kl.prepay_storage(SECRET, 1).unwrap();
+            assert_eq!(kl.charge_period(user, SECRET), Ok(1));
+            assert!(!kl.shares_purgeable(user, SECRET));
+            assert_eq!(kl.balance_of(nodes[1]), 2);
+
+            // what is left is refunded on deletion
+            kl.prepay_storage(SECRET, 4).unwrap();
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.balance_of(user), 6);
+            assert_eq!(kl.subscription(user, SECRET), None);
+        }
+
+        #[ink::test]
+        fn test_oracle_verification() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let oracle = accounts.frank;
+            run_action(&mut kl, AdminAction::GrantRole(Role::Oracle, oracle)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.set_verification_required(SECRET, true).unwrap();
+            // a check from before the attempt does not count
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(oracle);
+            let before = block_number();
+            kl.post_verification(user, SECRET, ConditionType::Email, before).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.set_verification_required(SECRET, false), Err(Error::RecoveryInProgress));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::VerificationMissing));
+            assert_eq!(kl.post_verification(user, SECRET, ConditionType::Email, before + 1),
+                Err(Error::MissingRole));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(oracle);
+            assert_eq!(kl.post_verification(user, SECRET, ConditionType::Email, before + 2),
+                Err(Error::InvalidVerificationBlock));
+            kl.post_verification(user, SECRET, ConditionType::Email, before + 1).unwrap();
+            assert_eq!(kl.verification(user, SECRET, ConditionType::Email), Some(before + 1));
+            // only the checked condition's guardian can confirm
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)),
+                Err(Error::VerificationMissing));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_fee_token() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let token = accounts.frank;
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::GrantRole(Role::FeeAdmin, accounts.eve)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            assert_eq!(kl.queue_action(AdminAction::SetFeeToken(Some(token))),
+                Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetFeeToken(Some(token))).unwrap();
+            assert_eq!(kl.fee_token(), Some(token));
+            // free recoveries make no token calls, which the off-chain
+            // environment can not run
+            run_action(&mut kl, AdminAction::SetRecoveryFee(0)).unwrap();
+
+            // the ledger balance does not matter in token mode
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.balance_of(user), 0);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.fee_token_treasury(token), 0);
+            assert_eq!(run_action(&mut kl, AdminAction::WithdrawFeeTokenTreasury(token, user, 1)),
+                Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(kl.withdraw_fee_token_treasury(token, user, 1),
+                Err(Error::InsufficientBalance));
+        }
+
+        #[ink::test]
+        fn test_recovery_request() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().request, None);
+
+            let request = RecoveryRequest {
+                reason: RecoveryReason::Compromised,
+                device_commitment: kl.share_commitment(b"device-42".to_vec()),
+                target_pub_k: Some(public_key(&[14u8; 32])),
+            };
+            kl.start_recovery(SECRET, request.clone()).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().request, Some(request));
+            // a restart replaces the context of the old attempt
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().request, Some(recovery_request()));
+        }
+
+        #[ink::test]
+        fn test_recovery_request_stored_apart() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.recovery_requests.get((user, SECRET)), Some(recovery_request()));
+            assert_eq!(kl.council(), (vec![accounts.alice], 1));
+
+            // the request goes with the user record
+            kl.cancel_recovery(SECRET).unwrap();
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.recovery_requests.get((user, SECRET)), None);
+        }
+
+        #[ink::test]
+        fn test_recovery_rate_limit() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRateLimit(
+                RateLimit { window: 10, max_starts: 2, max_starts_per_account: 1 })).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            let users = [accounts.bob, accounts.eve, accounts.frank];
+            for user in users {
+                kl.transfer(user, 10, Vec::new()).unwrap();
+            }
+            register_nodes(&mut kl, &nodes);
+            for user in users {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1)
+                    .unwrap();
+            }
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(users[0]);
+            let window_end = block_number() + 10;
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()),
+                Err(Error::RateLimited(window_end)));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(users[1]);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            // two starts in this window overall
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(users[2]);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()),
+                Err(Error::RateLimited(window_end)));
+
+            for _ in 0..10 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(users[0]);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+        }
+
+        #[ink::test]
+        fn test_freeze_user() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetUnlockDelay(1)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.balance_of(user), 7);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            assert_eq!(kl.freeze_user(user, SECRET), Err(Error::NotAssignedGuardian));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.freeze_user(user, SECRET).unwrap();
+            assert_eq!(kl.freeze_user(user, SECRET), Err(Error::AlreadyVoted));
+            assert_eq!(kl.freeze_votes(user, SECRET), vec![nodes[0]]);
+            assert!(!kl.get_recovery(user, SECRET).unwrap().locked);
+            // the second vote reaches the threshold, the running attempt is
+            // cancelled and its fee refunded
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.freeze_user(user, SECRET).unwrap();
+            let view = kl.get_recovery(user, SECRET).unwrap();
+            assert_eq!((view.status, view.locked), (RecoveryStatus::Cancelled, true));
+            assert_eq!(kl.balance_of(user), 10);
+            assert_eq!(kl.freeze_votes(user, SECRET), Vec::new());
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RecoveryLocked));
+            assert_eq!(kl.rotate_user_key(SECRET, [3; 33], [0; 65]),
+                Err(Error::RecoveryLocked));
+            kl.request_unlock(SECRET).unwrap();
+            assert_eq!(kl.unlock_recovery(SECRET), Err(Error::UnlockNotReady));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.unlock_recovery(SECRET).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+        }
+
+        #[ink::test]
+        fn test_rotate_node_key() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetKeyTransitionPeriod(1)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            let new_key = [13u8; 32];
+            let new_pub_k = public_key(&new_key);
+            for node in nodes {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+                assert_eq!(kl.rotate_node_key(node_pub_k(node), no_attestation()),
+                    Err(Error::SameKey));
+                kl.rotate_node_key(new_pub_k, no_attestation()).unwrap();
+            }
+            assert_eq!(kl.get_node(nodes[0]).unwrap().pub_k, new_pub_k);
+            let rotated = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::NodeKeyRotated(r)) => Some((r.node, r.assigned_users)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(rotated, vec![(nodes[0], 1), (nodes[1], 1)]);
+            assert_eq!(kl.assigned_users(nodes[0], 0, 10), vec![(user, SECRET)]);
+
+            // the old key is still accepted during the transition
+            kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::InvalidProof));
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let digest = KeyLedger::recovery_digest(&contract, &user, SECRET, 1, 0);
+            kl.finish_recovery(1, RecoveryProof {
+                share_version: 0,
+                signature: sign(&new_key, &digest),
+                condition_proof: Vec::new(),
+            }).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::PendingFinalize);
+        }
+
+        #[ink::test]
+        fn test_start_recovery_for() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let key = [11u8; 32];
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.charlie]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, public_key(&key), guardians(&[accounts.charlie]), 1)
+                .unwrap();
+
+            // relayed by eve, who pays no fee
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let digest = KeyLedger::relayed_recovery_digest(&contract, &user, SECRET, 0);
+            assert_eq!(kl.start_recovery_for(user, SECRET, sign(&[12u8; 32], &digest), 0),
+                Err(Error::InvalidSignature));
+            assert_eq!(kl.start_recovery_for(user, SECRET, sign(&key, &digest), 1),
+                Err(Error::InvalidNonce));
+            assert_eq!(kl.start_recovery_for(user, SECRET, sign(&key, &digest), 0), Ok(1));
+            assert_eq!(kl.nonce(user), 1);
+            assert_eq!(kl.escrow_of(user, SECRET), 3);
+            // the same signature can not be replayed
+            assert_eq!(kl.start_recovery_for(user, SECRET, sign(&key, &digest), 0),
+                Err(Error::InvalidNonce));
+        }
+
+        #[ink::test]
+        fn test_delete_user() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(1)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[accounts.charlie]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[accounts.charlie]), 1).unwrap();
+
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::DeletionPending));
+            kl.revert_deletion(SECRET).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.delete_user(SECRET), Err(Error::RecoveryInProgress));
+            kl.cancel_recovery(SECRET).unwrap();
+
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.finalize_deletion(user, SECRET), Err(Error::DeletionNotReady));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            kl.finalize_deletion(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.verify_new_user(SECRET, USER_PUB_K), false);
+            assert_eq!(kl.delete_user(SECRET), Err(Error::UserNotFound));
+        }
+
+        #[ink::test]
+        fn test_delete_user_keeps_history() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(3)).unwrap();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.finalize_recovery(1).unwrap();
+            let record = kl.recovery_record(user, SECRET, 0);
+            assert!(record.is_some());
+
+            // deleting and registering again neither clears the history nor
+            // skips the cooldown
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.recovery_record(user, SECRET, 0), record);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.recovery_count(user, SECRET), 1);
+            assert_eq!(kl.recovery_record(user, SECRET, 0), record);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::CooldownActive(3)));
+
+            // nor does it lift a freeze
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.freeze_user(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.delete_user(SECRET), Err(Error::RecoveryLocked));
+        }
+
+        #[ink::test]
+        fn test_replace_guardian() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 2).unwrap();
+
+            assert_eq!(kl.replace_guardian(SECRET, nodes[2], nodes[0], ConditionType::Email,
+                Hash::default(), Hash::default()),
+                Err(Error::NotAssignedGuardian));
+            assert_eq!(kl.replace_guardian(SECRET, nodes[0], nodes[1], ConditionType::Email,
+                Hash::default(), Hash::default()),
+                Err(Error::DuplicateGuardian));
+            kl.replace_guardian(SECRET, nodes[0], nodes[2], ConditionType::Email,
+                Hash::default(), Hash::default()).unwrap();
+            assert_eq!(kl.share_version_of(user, SECRET), Some(1));
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RegistrationPending));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            kl.ack_share_stored(user, SECRET, Hash::default()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.replace_guardian(SECRET, nodes[2], nodes[0], ConditionType::Email,
+                Hash::default(), Hash::default()),
+                Err(Error::RecoveryInProgress));
+            // charlie is no guardian anymore, eve took his place
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::NotAssignedGuardian));
+            // django still serves the share from before the swap
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)),
+                Err(Error::StaleShare));
+            kl.finish_recovery(1, versioned_proof(nodes[1], user, SECRET, 1, 1)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            kl.finish_recovery(1, versioned_proof(nodes[2], user, SECRET, 1, 1)).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.verify_new_recovery(SECRET), true);
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.verify_new_recovery(SECRET), false);
+        }
+
+        #[ink::test]
+        fn test_finish_recovery_rejects_invalid_proofs() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            // signed by another node
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)),
+                Err(Error::InvalidProof));
+            // signed for another user
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], accounts.eve, SECRET, 1)),
+                Err(Error::InvalidProof));
+            // signed for a previous attempt
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 0)),
+                Err(Error::InvalidProof));
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_confirmation_replay_after_restart() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            let old_proof = recovery_proof(nodes[0], user, SECRET, 1);
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Ok(2));
+
+            // a confirmation captured for the cancelled attempt is useless
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, old_proof.clone()), Err(Error::RecoveryNotActive));
+            assert_eq!(kl.finish_recovery(2, old_proof), Err(Error::InvalidProof));
+            kl.finish_recovery(2, recovery_proof(nodes[0], user, SECRET, 2)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_restart_counts_as_failed_attempt() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMaxFailedAttempts(2)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 50, Vec::new()).unwrap();
+            let nodes = [accounts.charlie];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Ok(2));
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 1);
+
+            // the restart that would lock the secret is refused, the running
+            // attempt goes on
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::RecoveryLocked));
+            let view = kl.get_recovery(user, SECRET).unwrap();
+            assert_eq!((view.status, view.locked), (RecoveryStatus::Started, false));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(2, recovery_proof(nodes[0], user, SECRET, 2)).unwrap();
+        }
+
+        #[ink::test]
+        fn test_confirmation_stores_proof_hash() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            let proof = recovery_proof(nodes[0], user, SECRET, 1);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, proof.clone()).unwrap();
+
+            // the event carries the proof, storage only its hash
+            let emitted = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::RecoveryConfirmed(e)) => Some(e.proof),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(emitted, vec![proof.clone()]);
+            assert_eq!(kl.confirmation_proof_hash(user, SECRET, nodes[0]),
+                Some(KeyLedger::proof_hash(&proof)));
+            assert_eq!(kl.confirmation_proof_hash(user, SECRET, nodes[1]), None);
+        }
 
-    /// The ERC-20 error types.
-    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
-    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-    pub enum Error {
-        /// Returned if not enough balance to fulfill a request is available.
-        InsufficientBalance,
-    }
+        #[ink::test]
+        fn test_ethereum_node_key() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let (user, node) = (accounts.bob, accounts.charlie);
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            kl.register_node(node_pub_k(node), KeyType::Ethereum, no_attestation()).unwrap();
 
-    impl KeyLedger {
-        /// Constructor that initializes maps
-        #[ink(constructor)]
-        pub fn new(total_supply: Balance) -> Self {
-            let mut balances = Mapping::default();
-            let mut nodes = Mapping::default();
-            let mut users = Mapping::default();
-            let mut recoveries  = Mapping::default();
-            let caller = Self::env().caller();
-            balances.insert(caller, &total_supply);
-            Self {
-                total_supply: total_supply,
-                balances: balances,
-                nodes: nodes,
-                users: users,
-                recoveries: recoveries,
-            }
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            // a plain signature over the digest is not a personal message
+            assert_eq!(kl.finish_recovery(1, recovery_proof(node, user, SECRET, 1)),
+                Err(Error::InvalidProof));
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let digest = KeyLedger::recovery_digest(&contract, &user, SECRET, 1, 0);
+            let signature = sign(&node_secret(&node), &KeyLedger::eth_message_hash(&digest));
+            kl.finish_recovery(1, RecoveryProof {
+                share_version: 0,
+                signature,
+                condition_proof: Vec::new(),
+            }).unwrap();
         }
 
-        #[ink(message)]
-        pub fn total_supply(&self) -> Balance {
-            self.total_supply
+        #[ink::test]
+        fn test_session_key() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let (node, hot) = (accounts.charlie, accounts.frank);
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            register_nodes(&mut kl, &[node, accounts.django]);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            assert_eq!(kl.set_session_key(Some(accounts.django)), Err(Error::SessionKeyInUse));
+            kl.set_session_key(Some(hot)).unwrap();
+            assert_eq!(kl.session_key(node), Some(hot));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.django);
+            assert_eq!(kl.set_session_key(Some(hot)), Err(Error::SessionKeyInUse));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            // the hot key confirms with the node's enclave proof
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(hot);
+            kl.heartbeat().unwrap();
+            kl.finish_recovery(1, recovery_proof(node, user, SECRET, 1)).unwrap();
+            assert_eq!(kl.reputation(node).confirmed, 1);
+            kl.finalize_recovery(1).unwrap();
+            // and acknowledges the node's share of a reshare
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.start_reshare(SECRET, guardians(&[node]), 1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(hot);
+            kl.acknowledge_share(user, SECRET).unwrap();
+            assert_eq!(kl.share_version_of(user, SECRET), Some(1));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            kl.set_session_key(None).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(hot);
+            assert_eq!(kl.heartbeat(), Err(Error::NodeNotRegistered));
         }
 
-        #[ink(message)]
-        pub fn balance_of(&self, owner: AccountId) -> Balance {
-            self.balances.get(&owner).unwrap_or_default()
+        #[ink::test]
+        fn test_condition_verifier() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let verifier = accounts.frank;
+            run_action(&mut kl, AdminAction::SetConditionVerifier(ConditionType::Email,
+                Some(verifier))).unwrap();
+            assert_eq!(kl.condition_verifier(ConditionType::Email), Some(verifier));
+            assert_eq!(kl.condition_verifier(ConditionType::Phone), None);
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            // charlie checks an email, django a phone number
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::InvalidConditionProof));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetConditionVerifier(ConditionType::Email, None))
+                .unwrap();
+            assert_eq!(kl.condition_verifier(ConditionType::Email), None);
         }
 
-        #[ink(message)]
-        pub fn transfer(&mut self, to: AccountId, value: Balance) -> Result<()> {
-            let from = self.env().caller();
-            let from_balance = self.balance_of(from);
-            // if from_balance < value {
-            //     return Err(Error::InsufficientBalance)
-            // }
+        #[ink::test]
+        fn test_finish_recovery_aggregate() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
 
-            self.balances.insert(&from, &(from_balance - value));
-            let to_balance = self.balance_of(to);
-            self.balances.insert(to, &(to_balance + value));
-            Ok(())
+            // submitted by a relayer, not by the nodes
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.frank);
+            assert_eq!(kl.finish_recovery_aggregate(1, vec![
+                (nodes[0], recovery_proof(nodes[1], user, SECRET, 1)),
+            ]), Err(Error::InvalidProof));
+            kl.finish_recovery_aggregate(1, vec![
+                (nodes[0], recovery_proof(nodes[0], user, SECRET, 1)),
+                (nodes[2], recovery_proof(nodes[2], user, SECRET, 1)),
+            ]).unwrap();
+            let too_many = vec![(nodes[1], recovery_proof(nodes[1], user, SECRET, 1));
+                MAX_BATCH_LEN + 1];
+            assert_eq!(kl.finish_recovery_aggregate(1, too_many), Err(Error::InvalidInput));
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.balance_of(nodes[0]), 1);
+            assert_eq!(kl.balance_of(nodes[1]), 0);
         }
 
-        fn transfer_from_to(&mut self, from: &AccountId,
-            to: &AccountId, value: Balance,
-        ) -> Result<()> {
-            let from_balance = self.balance_of(*from);
-            if from_balance < value {
-                return Err(Error::InsufficientBalance)
+        #[ink::test]
+        fn test_finish_recovery_batch() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            for user in [accounts.bob, accounts.django] {
+                kl.transfer(user, 10, Vec::new()).unwrap();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+                kl.start_recovery(SECRET, recovery_request()).unwrap();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
             }
 
-            self.balances.insert(from, &(from_balance - value));
-            let to_balance = self.balance_of(*to);
-            self.balances.insert(to, &(to_balance + value));
-            Ok(())
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            let results = kl.finish_recovery_batch(vec![
+                (1, recovery_proof(node, accounts.bob, SECRET, 1)),
+                (2, recovery_proof(node, accounts.bob, SECRET, 2)),
+                (2, recovery_proof(node, accounts.django, SECRET, 2)),
+                (3, recovery_proof(node, accounts.django, SECRET, 3)),
+            ]).unwrap();
+            assert_eq!(results, vec![Ok(()), Err(Error::InvalidProof), Ok(()),
+                Err(Error::RecoveryNotActive)]);
+            assert_eq!(kl.reputation(node).confirmed, 2);
+            let too_many = vec![(1, recovery_proof(node, accounts.bob, SECRET, 1));
+                MAX_BATCH_LEN + 1];
+            assert_eq!(kl.finish_recovery_batch(too_many), Err(Error::InvalidInput));
         }
 
-        // for new machines just install node app, call register_node to alert the chain
-        #[ink(message)]
-        pub fn register_node(&mut self, pub_k: String) {
-            let sender = self.env().caller();
-            let node = self.nodes.get(sender);
-            match node {
-                Some(n) => {},
-                None => {
-                    self.nodes.insert(sender, &Node {
-                        nid: sender,
-                        pub_k: pub_k.to_string()
-                    });
-                }
-            }
+        #[ink::test]
+        fn test_get_recovery() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            run_action(&mut kl, AdminAction::SetChallengePeriod(5)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.get_recovery(user, SECRET), None);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::Idle);
+
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            let started_at = block_number();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.finish_recovery(1, recovery_proof(nodes[1], user, SECRET, 1)).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET), Some(RecoveryView {
+                status: RecoveryStatus::PendingFinalize,
+                recovery_id: 1,
+                confirmed_by: vec![nodes[1]],
+                confirmed_weight: 1,
+                threshold: 1,
+                started_at,
+                finalizable_at: started_at + 5,
+                completed: 0,
+                failed_attempts: 0,
+                locked: false,
+                new_owner: None,
+                request: Some(recovery_request()),
+            }));
         }
 
-        #[ink(message)]
-        pub fn verify_node(&mut self, pub_k: String) -> bool {
-            let sender = self.env().caller();
-            let node = self.nodes.get(sender);
-            match node {
-                Some(n) => n.pub_k == pub_k,
-                None => false
+        #[ink::test]
+        fn test_get_users() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            for (user, secret_id) in [(accounts.bob, SECRET), (accounts.django, SECRET),
+                (accounts.bob, 1)] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                register_user(&mut kl, secret_id, USER_PUB_K, guardians(&[node]), 1)
+                    .unwrap();
             }
+            assert_eq!(kl.get_users(0, 10), vec![(accounts.bob, SECRET),
+                (accounts.django, SECRET), (accounts.bob, 1)]);
+            assert_eq!(kl.get_users(1, 1), vec![(accounts.django, SECRET)]);
+            assert_eq!(kl.get_users(5, 1), Vec::new());
+
+            // the last user moves into the deleted user's slot
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.get_users(0, 10), vec![(accounts.bob, 1), (accounts.django, SECRET)]);
         }
 
-        // // get all nodes registered
-        // #[ink(message)]
-        // pub fn get_nodes(&self) -> Vec<AccountId> {
-        //     let mut result: Vec<AccountId> = Vec::new();
-        //     for (k, v) in self.nodes.into_iter() {
-        //         result.push(k);
-        //     }
-        //     result
-        // }
+        #[ink::test]
+        fn test_stats() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let node = accounts.charlie;
+            register_nodes(&mut kl, &[node]);
+            for user in [accounts.bob, accounts.django] {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+                kl.transfer(user, 10, Vec::new()).unwrap();
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+                register_user(&mut kl, SECRET, USER_PUB_K, guardians(&[node]), 1).unwrap();
+                kl.start_recovery(SECRET, recovery_request()).unwrap();
+            }
+            // every registered secret counts
+            register_user(&mut kl, 1, USER_PUB_K, guardians(&[node]), 1).unwrap();
+            assert_eq!(kl.stats(), Stats {
+                nodes: 1,
+                users: 3,
+                active_recoveries: 2,
+                completed_recoveries: 0,
+            });
 
-        // for new user, call register user after all user secret shares are 
-        // stored in 3 nodes
-        #[ink(message)]
-        pub fn register_user(&mut self, pub_k: String,
-            node1_cond_type: u8, node1_id: AccountId,
-            node2_cond_type: u8, node2_id: AccountId,
-            node3_cond_type: u8, node3_id: AccountId) {
-            let sender = self.env().caller();
-            let user = User {
-                uid: sender,
-                pub_k: pub_k,
-                node1_cond_type: node1_cond_type,
-                node1_id: node1_id,
-                node2_cond_type: node2_cond_type,
-                node2_id: node2_id,
-                node3_cond_type: node3_cond_type,
-                node3_id: node3_id
-            };
-            self.users.insert(sender, &user);
-            let recovery = Recovery {
-                status: 0,
-                uid: sender,
-                r_times: 0,
-                recovery1_proof: "".to_string(),
-                node1_confirm: 0,
-                recovery2_proof: "".to_string(),
-                node2_confirm: 0,
-                recovery3_proof: "".to_string(),
-                node3_confirm: 0
-            };
-            self.recoveries.insert(sender, &recovery);
+            kl.cancel_recovery(SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+            kl.finish_recovery(1, recovery_proof(node, accounts.bob, SECRET, 1)).unwrap();
+            assert_eq!(kl.stats().active_recoveries, 1);
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.stats().active_recoveries, 0);
+            assert_eq!(kl.stats().completed_recoveries, 1);
         }
 
-        #[ink(message)]
-        pub fn verify_new_user(&self, pub_k: String) -> bool {
-            let sender = self.env().caller();
-            let user = self.users.get(sender);
-            let recovery = self.recoveries.get(sender);
-            let u = match user {
-                Some(user) => user,
-                None => return false
-            };
-            let r = match recovery {
-                Some(recovery) => recovery,
-                None => return false
-            };
-            u.pub_k == pub_k && r.status == 0
+        #[ink::test]
+        fn test_multiple_secrets() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let (seed, vault) = (0, 1);
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, seed, [2; 33], guardians(&nodes[..2]), 2).unwrap();
+            register_user(&mut kl, vault, [3; 33], guardians(&nodes[2..]), 1).unwrap();
+            assert_eq!(kl.verify_new_user(seed, [2; 33]), true);
+            assert_eq!(kl.verify_new_user(vault, [3; 33]), true);
+            assert_eq!(kl.start_recovery(2, recovery_request()), Err(Error::UserNotFound));
+
+            kl.start_recovery(vault, recovery_request()).unwrap();
+            assert_eq!(kl.verify_new_recovery(vault), true);
+            assert_eq!(kl.verify_new_recovery(seed), false);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            // guardian of the seed, not of the vault
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, vault, 1)),
+                Err(Error::NotAssignedGuardian));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[2]);
+            // a proof for one secret does not finish another
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[2], user, seed, 1)),
+                Err(Error::InvalidProof));
+            kl.finish_recovery(1, recovery_proof(nodes[2], user, vault, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            assert_eq!(kl.verify_new_recovery(vault), false);
+            assert_eq!(kl.escrow_of(user, seed), 0);
         }
 
-        // before user try to access its secret, call request_recovery 
-        #[ink(message)]
-        pub fn start_recovery(&mut self) {
-            let sender = self.env().caller();
-            let balance = self.balance_of(sender);
-            // not enough balance to start a recover
-            if balance < 3 {
-                return
-            }
-            let recovery_info = self.recoveries.get(sender);
-            if let Some(r) = recovery_info {
-                // when user start a recovery, set recovery status to 1
-                // keep every thing else
-                let r1 = Recovery {
-                    status: 1,
-                    recovery1_proof: "".to_string(),
-                    node1_confirm: 0,
-                    recovery2_proof: "".to_string(),
-                    node2_confirm: 0,
-                    recovery3_proof: "".to_string(),
-                    node3_confirm: 0,
-                    ..r
-                };
+        #[ink::test]
+        fn test_recovery_status_events() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetRecoveryExpiry(1)).unwrap();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(0)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::Idle);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::Cancelled);
+            assert_eq!(kl.verify_new_user(SECRET, USER_PUB_K), true);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            kl.expire_recovery(user, SECRET).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().status, RecoveryStatus::Expired);
 
-                self.recoveries.insert(sender, &r1);
-            }
+            let statuses = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::RecoveryStatusChanged(e)) => Some((e.recovery_id, e.status)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(statuses, vec![
+                (1, RecoveryStatus::Started),
+                (1, RecoveryStatus::Cancelled),
+                (2, RecoveryStatus::Started),
+                (2, RecoveryStatus::Expired),
+            ]);
         }
 
-        #[ink(message)]
-        pub fn verify_new_recovery(&self) -> bool{
-            let sender = self.env().caller();
-            let r = self.recoveries.get(sender);
-            if let Some(r) = r {
-                debug_println!("find recovery info");
-                return r.status == 1;
+        #[ink::test]
+        fn test_confirmation_progress_events() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            for node in nodes {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node);
+                kl.finish_recovery(1, recovery_proof(node, user, SECRET, 1)).unwrap();
             }
-            false
+
+            let progress = ink::env::test::recorded_events()
+                .filter_map(|e| match <Event as scale::Decode>::decode(&mut &e.data[..]) {
+                    Ok(Event::RecoveryConfirmed(e)) => Some((e.node, e.confirmations, e.threshold)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(progress, vec![(nodes[0], 1, 2), (nodes[1], 2, 2), (nodes[2], 3, 2)]);
         }
 
-        // before user try to access its secret, call request_recovery 
-        #[ink(message)]
-        pub fn finish_recovery(&mut self, user: AccountId, proof: String) {
-            let node = self.env().caller();
-            let user_info = self.users.get(user);
-            if let Some(u) = user_info {
-                debug_println!("finish recovery find user info");
-                let recovery_info = self.recoveries.get(user);
-                if let Some(mut r) = recovery_info {
-                    // when user did not start recovery before node, quit
-                    if r.status != 1 {
-                        return
-                    }
-                    if u.node1_id == node {
-                        debug_println!("finish recovery match node1");
-                        r.node1_confirm = 1;
-                        r.recovery1_proof = proof;
-                    } else if u.node2_id == node {
-                        debug_println!("finish recovery match node2");
-                        r.node2_confirm = 1;
-                        r.recovery2_proof = proof;
-                    } else if u.node3_id == node {
-                        debug_println!("finish recovery match node3");
-                        r.node3_confirm = 1;
-                        r.recovery3_proof = proof;
-                    } else {
-                    }
+        #[ink::test]
+        fn test_attestation_expiry() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let service_key = [7u8; 32];
+            let measurement = Hash::from([1u8; 32]);
+            // the attestation checks are turned on mid test, within the validity
+            run_action(&mut kl, AdminAction::SetTimelockDelay(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetAttestationValidity(2)).unwrap();
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            // lapsed attestations only count once attestation is checked
+            assert!(kl.is_attested(nodes[0]));
 
-                    let confirm_parts = r.node1_confirm + r.node2_confirm + r.node3_confirm;
-                    if confirm_parts >= 2 {
-                        // when recovery completed, send coin from user to node.
-                        debug_println!("finish recovery finish 2 shares");
-                        let r1 = Recovery {
-                            r_times: r.r_times + 1,
-                            status: 2,
-                            ..r
-                        };
-                        self.recoveries.insert(user, &r1);
-                        self.transfer_from_to(&user, &u.node1_id, 1);
-                        self.transfer_from_to(&user, &u.node2_id, 1);
-                        self.transfer_from_to(&user, &u.node3_id, 1);
-                    } else {
-                        // when recovery not completed, record partial recovery
-                        self.recoveries.insert(user, &r);
-                    }
-                }
-            }
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            run_action(&mut kl, AdminAction::SetAttestationSigner(Some(public_key(&service_key))))
+                .unwrap();
+            run_action(&mut kl, AdminAction::AllowMeasurement(measurement)).unwrap();
+            assert!(!kl.is_attested(nodes[0]));
+            assert_eq!(kl.find_nodes(None, 0, 10), vec![]);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)),
+                Err(Error::AttestationExpired));
+
+            let report = AttestationReport {
+                measurement,
+                signature: sign(&service_key,
+                    &KeyLedger::attestation_digest(&measurement, &node_pub_k(nodes[1]))),
+            };
+            assert_eq!(kl.renew_attestation(report), Err(Error::InvalidAttestation));
+            let report = AttestationReport {
+                measurement,
+                signature: sign(&service_key,
+                    &KeyLedger::attestation_digest(&measurement, &node_pub_k(nodes[0]))),
+            };
+            kl.renew_attestation(report).unwrap();
+            assert_eq!(kl.get_node(nodes[0]).unwrap().attested_until, block_number() + 2);
+            assert_eq!(kl.find_nodes(None, 0, 10), vec![nodes[0]]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
         }
 
+        #[ink::test]
+        fn test_share_deletion_acks() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetDeletionGracePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetDeletionAckWindow(2)).unwrap();
+            run_action(&mut kl, AdminAction::SetDeletionPenalty(3)).unwrap();
+            run_action(&mut kl, AdminAction::SetMinNodeStake(5)).unwrap();
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django];
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            for node in nodes {
+                kl.transfer(node, 5, Vec::new()).unwrap();
+            }
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            assert_eq!(kl.confirm_share_deleted(user, SECRET), Err(Error::DeletionNotRequested));
 
-    }
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.delete_user(SECRET).unwrap();
+            assert_eq!(kl.deletion_deadline(nodes[1], user, SECRET), Some(block_number() + 2));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.confirm_share_deleted(user, SECRET).unwrap();
+            assert_eq!(kl.report_missed_deletion(nodes[1], user, SECRET),
+                Err(Error::DeletionWindowOpen));
 
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            assert_eq!(kl.confirm_share_deleted(user, SECRET), Err(Error::DeletionWindowClosed));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.eve);
+            kl.report_missed_deletion(nodes[1], user, SECRET).unwrap();
+            assert_eq!(kl.report_missed_deletion(nodes[0], user, SECRET),
+                Err(Error::DeletionNotRequested));
 
-    #[cfg(test)]
-    mod tests {
-        /// Imports all the definitions from the outer scope so we can use them here.
-        use super::*;
+            assert_eq!(kl.reputation(nodes[0]), Reputation { deletions_confirmed: 1, ..Default::default() });
+            assert_eq!(kl.reputation(nodes[1]),
+                Reputation { deletions_missed: 1, slashes: 1, ..Default::default() });
+            assert_eq!(kl.stake_of(nodes[1]), 2);
+            assert_eq!(kl.deletion_deadline(nodes[1], user, SECRET), None);
+        }
 
-        /// We test if the default constructor does its job.
         #[ink::test]
-        fn test_default_works() {
-            let kl = KeyLedger::new(100);
-            assert_eq!(kl.total_supply(), 100);
+        fn test_guardian_again_keeps_share() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            assert_eq!(kl.balance_of(accounts.alice), 100);
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            kl.replace_guardian(SECRET, nodes[0], nodes[1], ConditionType::Email,
+                Hash::default(), Hash::default()).unwrap();
+            assert!(kl.deletion_deadline(nodes[0], user, SECRET).is_some());
+
+            // back to the first node, which must not be reported for keeping
+            // the share it holds again
+            kl.replace_guardian(SECRET, nodes[1], nodes[0], ConditionType::Email,
+                Hash::default(), Hash::default()).unwrap();
+            assert_eq!(kl.deletion_deadline(nodes[0], user, SECRET), None);
+            assert!(kl.deletion_deadline(nodes[1], user, SECRET).is_some());
+            assert_eq!(kl.report_missed_deletion(nodes[0], user, SECRET),
+                Err(Error::DeletionNotRequested));
         }
 
         #[ink::test]
-        fn test_transfer() {
+        fn test_third_party_attempts_do_not_lock_user() {
             let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            kl.transfer(accounts.bob, 10);
-            assert_eq!(kl.balance_of(accounts.bob), 10);            
-            assert_eq!(kl.balance_of(accounts.alice), 90);
+            run_action(&mut kl, AdminAction::SetRecoveryExpiry(0)).unwrap();
+            let user = accounts.bob;
+            let attacker = accounts.eve;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            kl.transfer(attacker, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 2).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(attacker);
+            for _ in 0..kl.get_config().max_failed_attempts {
+                kl.start_account_recovery(user, SECRET).unwrap();
+                kl.expire_recovery(user, SECRET).unwrap();
+            }
+            kl.start_account_recovery(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            kl.cancel_recovery(SECRET).unwrap();
+            let view = kl.get_recovery(user, SECRET).unwrap();
+            assert_eq!((view.failed_attempts, view.locked), (0, false));
+            assert_eq!(kl.balance_of(attacker), 10);
+
+            // the user's own attempt can not be replaced by someone else's
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(attacker);
+            assert_eq!(kl.start_account_recovery(user, SECRET), Err(Error::RecoveryInProgress));
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().new_owner, None);
         }
 
         #[ink::test]
-        fn test_register_node() {
+        fn test_account_recovery_keeps_new_owner_record() {
             let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
-            kl.register_node("some_node".to_string());
-            assert_eq!(kl.verify_node("some_node".to_string()), true);
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            let new_account = accounts.frank;
+            kl.transfer(new_account, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(new_account);
+            kl.start_account_recovery(user, SECRET).unwrap();
+            // frank registers the same secret id before the recovery is done
+            register_user(&mut kl, SECRET, [3; 33], guardians(&nodes[1..]), 1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            assert_eq!(kl.finalize_recovery(1), Err(Error::UserAlreadyRegistered));
+
+            assert_eq!(kl.get_user(new_account, SECRET).unwrap().pub_k, [3; 33]);
+            assert_eq!(kl.guardians_of(new_account, SECRET), guardians(&nodes[1..]));
+            assert_eq!(kl.escrow_of(user, SECRET), 3);
         }
 
         #[ink::test]
-        fn test_register_user() {
+        fn test_register_again_during_recovery() {
             let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
-            let node1 = accounts.charlie;
-            let node2 = accounts.django;
-            let node3 = accounts.eve;
-            kl.register_user("some_node".to_string(),
-                1, node1, 2, node2, 3, node3);
-            assert_eq!(kl.verify_new_user("some_node".to_string()), true);
+            let user = accounts.bob;
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            assert_eq!(kl.propose_registration(SECRET, USER_PUB_K, guardians(&nodes[..1]), 1, None),
+                Err(Error::RecoveryInProgress));
+            assert_eq!(kl.escrow_of(user, SECRET), 3);
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.balance_of(user), 10);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
         }
 
         #[ink::test]
-        fn test_start_recovery() {
+        fn test_register_again_keeps_lockout() {
             let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetMaxFailedAttempts(2)).unwrap();
             let user = accounts.bob;
-            kl.transfer(user, 10);
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
             ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
-            let node1 = accounts.charlie;
-            let node2 = accounts.django;
-            let node3 = accounts.eve;
-            kl.register_user("some_user".to_string(),
-                1, node1, 2, node2, 3, node3);
-            kl.start_recovery();
-            assert_eq!(kl.verify_new_recovery(), true);
-        }
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.get_recovery(user, SECRET).unwrap().failed_attempts, 1);
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            kl.cancel_recovery(SECRET).unwrap();
+            assert_eq!(kl.propose_registration(SECRET, USER_PUB_K, guardians(&nodes), 1, None),
+                Err(Error::RecoveryLocked));
 
+            // a freeze by the guardians locks the same way
+            let other = accounts.eve;
+            kl.transfer(other, 10, Vec::new()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(other);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.freeze_user(other, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(other);
+            assert_eq!(kl.propose_registration(SECRET, USER_PUB_K, guardians(&nodes[..1]), 1, None),
+                Err(Error::RecoveryLocked));
+        }
 
         #[ink::test]
-        fn test_recovery() {
+        fn test_register_again_keeps_history() {
             let mut kl: KeyLedger = KeyLedger::new(100);
             let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            // transfer some coin to bob for him to recover his account
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            run_action(&mut kl, AdminAction::SetRecoveryCooldown(10)).unwrap();
             let user = accounts.bob;
-            kl.transfer(user, 10);
-            
-            let node1 = accounts.charlie;
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node1);
-            kl.register_node("node1".to_string());
+            kl.transfer(user, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            let record = kl.recovery_record(user, SECRET, 0).unwrap();
 
-            let node2 = accounts.django;
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node2);
-            kl.register_node("node2".to_string());
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes), 1).unwrap();
+            assert_eq!(kl.recovery_count(user, SECRET), 1);
+            assert_eq!(kl.start_recovery(SECRET, recovery_request()), Err(Error::CooldownActive(10)));
+            for _ in 0..10 {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            kl.start_recovery(SECRET, recovery_request()).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(2, versioned_proof(nodes[0], user, SECRET, 2, 1)).unwrap();
+            kl.finalize_recovery(2).unwrap();
+            assert_eq!(kl.recovery_record(user, SECRET, 0), Some(record));
+            assert_eq!(kl.recovery_record(user, SECRET, 1).unwrap().recovery_id, 2);
+        }
 
-            let node3 = accounts.eve;
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node3);
-            kl.register_node("node3".to_string());
+        #[ink::test]
+        fn test_voted_tokens_can_not_vote_twice() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetVotingPeriod(2)).unwrap();
+            let voter = accounts.bob;
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(voter);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(40);
+            kl.deposit().unwrap();
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(0);
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 40);
+            let id = kl.propose(AdminAction::SetRecoveryFee(8)).unwrap();
+            kl.vote(id, true).unwrap();
 
-            // user start recovery
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
-            kl.register_user("some_node".to_string(),
-                1, node1, 2, node2, 3, node3);
-            kl.start_recovery();
+            // none of the ways out of the ledger frees the voted tokens
+            assert_eq!(kl.withdraw(40), Err(Error::InsufficientBalance));
+            assert_eq!(kl.fund_faucet(40), Err(Error::InsufficientBalance));
+            assert_eq!(kl.fund_rewards(40), Err(Error::InsufficientBalance));
+            assert_eq!(kl.transfer(accounts.charlie, 40, Vec::new()),
+                Err(PSP22Error::InsufficientBalance));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(kl.vote(id, true), Err(Error::InsufficientBalance));
+            assert_eq!(kl.proposal(id).unwrap().votes_for, 40);
 
-            // node report finish recovery 
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node3);
-            kl.finish_recovery(user, "proof1".to_string());
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(node2);
-            kl.finish_recovery(user, "proof2".to_string());
+            for _ in 0..=kl.get_config().voting_period {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            }
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(voter);
+            kl.withdraw(40).unwrap();
+        }
 
-            assert_eq!(kl.balance_of(user), 7);
-            assert_eq!(kl.balance_of(node1), 1);
-            assert_eq!(kl.balance_of(node2), 1);
-            assert_eq!(kl.balance_of(node3), 1);   
+        #[ink::test]
+        fn test_reshare_requests_deletion_from_dropped_nodes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 1).unwrap();
+            kl.start_reshare(SECRET, guardians(&nodes[1..]), 1).unwrap();
+            for n in nodes[1..].iter() {
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(*n);
+                kl.acknowledge_share(user, SECRET).unwrap();
+            }
+            assert!(kl.deletion_deadline(nodes[0], user, SECRET).is_some());
+            assert_eq!(kl.deletion_deadline(nodes[1], user, SECRET), None);
+            assert_eq!(kl.deletion_deadline(nodes[2], user, SECRET), None);
+        }
+
+        #[ink::test]
+        fn test_register_again_requests_deletion_from_dropped_nodes() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let user = accounts.bob;
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..2]), 1).unwrap();
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[1..]), 1).unwrap();
+            assert!(kl.deletion_deadline(nodes[0], user, SECRET).is_some());
+            assert_eq!(kl.deletion_deadline(nodes[1], user, SECRET), None);
+            assert_eq!(kl.deletion_deadline(nodes[2], user, SECRET), None);
         }
 
+        #[ink::test]
+        fn test_account_recovery_requests_deletion_of_dropped_reshare() {
+            let mut kl: KeyLedger = KeyLedger::new(100);
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            run_action(&mut kl, AdminAction::SetChallengePeriod(0)).unwrap();
+            let user = accounts.bob;
+            let new_account = accounts.frank;
+            kl.transfer(new_account, 10, Vec::new()).unwrap();
+            let nodes = [accounts.charlie, accounts.django, accounts.eve];
+            register_nodes(&mut kl, &nodes);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(user);
+            register_user(&mut kl, SECRET, USER_PUB_K, guardians(&nodes[..1]), 1).unwrap();
+            kl.start_reshare(SECRET, guardians(&nodes), 1).unwrap();
+            // django stored its share of the reshare, eve did not get to it
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[1]);
+            kl.acknowledge_share(user, SECRET).unwrap();
 
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(new_account);
+            kl.start_account_recovery(user, SECRET).unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(nodes[0]);
+            kl.finish_recovery(1, recovery_proof(nodes[0], user, SECRET, 1)).unwrap();
+            kl.finalize_recovery(1).unwrap();
+            assert_eq!(kl.reshare_pending(new_account, SECRET), None);
+            assert!(kl.deletion_deadline(nodes[1], user, SECRET).is_some());
+            assert_eq!(kl.deletion_deadline(nodes[0], user, SECRET), None);
+            assert_eq!(kl.deletion_deadline(nodes[2], user, SECRET), None);
+        }
     }
 }